@@ -1,20 +1,36 @@
-use lychee_vm::execute;
+use lychee_vm::{bundled_program, execute, format_backtrace, ObjectFile};
 use std::fs;
 
 fn main() {
-    let program = get_bundled();
-    execute(program, false);
+    let bundled = get_bundled();
+    let program = ObjectFile::from_bytes(&bundled).unwrap_or_else(|error| {
+        eprintln!("Bundled program is invalid: {}", error);
+        std::process::exit(1);
+    });
+    let mut vm_config = program.header.config.clone();
+    vm_config.args = std::env::args().skip(1).collect();
+    match execute(&program, &vm_config, false) {
+        Ok(exit_code) => std::process::exit(exit_code as i32),
+        Err(trap) => {
+            eprintln!("{}", trap);
+            eprint!("{}", format_backtrace(&trap.backtrace, &program.line_table));
+            std::process::exit(1);
+        }
+    }
 }
 
+/// Reads the program bundled into this executable, exiting with an error if there is none.
 pub fn get_bundled() -> Vec<u8> {
-    let vm_path = std::env::args().nth(0).expect("VM executable path missing");
-    let vm_binary = fs::read(vm_path).unwrap();
-
-    let program_size_offset = vm_binary.len() - 8;
-    let program_size = u64::from_le_bytes(vm_binary[program_size_offset..].try_into().unwrap());
-
-    let program_start = program_size_offset - program_size as usize;
-    let program = vm_binary[program_start..program_size_offset].to_vec();
-
-    program
+    let vm_path = std::env::args().next().expect("VM executable path missing");
+    let vm_binary = fs::read(&vm_path).unwrap_or_else(|error| {
+        eprintln!("Cannot read {}: {}", vm_path, error);
+        std::process::exit(1);
+    });
+    match bundled_program(&vm_binary) {
+        Some(program) => program.to_vec(),
+        None => {
+            eprintln!("{} does not contain a bundled program", vm_path);
+            std::process::exit(1);
+        }
+    }
 }
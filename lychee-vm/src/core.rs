@@ -1,684 +1,304 @@
+use crate::core::backtrace::backtrace;
+use crate::core::decoder::{decode, DecodedProgram};
+use crate::core::files::FileSystem;
 use crate::core::heap::Heap;
+use crate::core::host::HostFunctions;
+use crate::core::layout::MemoryLayout;
 use crate::core::memory::Memory;
-use lychee_compiler::{BinopType, UnopType, DATA_SIZE_64};
-use std::io::{Read, Write};
+use crate::core::observer::{DebugPrinter, NoObserver, Observer};
+use crate::core::snapshot::Snapshot;
+use crate::core::trap::{VmTrap, VmTrapKind};
+use lychee_compiler::{ObjectFile, VmConfig};
+use std::time::Instant;
 
+pub mod backtrace;
 pub mod constants;
+pub mod debugger;
+pub mod decoder;
+pub mod files;
 pub mod heap;
+pub mod host;
+pub mod interpreter;
+pub mod layout;
+pub mod memcheck;
 pub mod memory;
-
-pub fn execute(program: Vec<u8>, debug_print: bool) {
-    let size = 0x200000;
-    let heap_size = 0x100000;
-    let heap_offset = program.len();
-    let mut memory = Memory::new(size, program);
-    let mut heap = Heap::new(&mut memory, heap_offset, heap_size);
-    let start_instant = std::time::Instant::now();
-    let exit_code = run(&mut memory, &mut heap, debug_print);
-    if debug_print {
-        let elapsed = start_instant.elapsed();
-        println!("Elapsed: {:?}", elapsed);
-        println!("Exit Code: {}", exit_code);
-    }
-}
-
-pub fn run(memory: &mut Memory, heap: &mut Heap, debug_print: bool) -> i64 {
-    let mut exit_code = 0;
-    loop {
-        let pc = memory.registers[constants::PC] as usize;
-        let opcode = memory.data[pc];
-        if debug_print {
-            println!("PC: {}, Opcode: {:X}", pc, opcode);
-        }
-        match opcode {
-            0x00 => {
-                exit(&mut exit_code, memory, debug_print);
-                break;
-            }
-            0x01 => load_or_store(pc, memory, true, debug_print),
-            0x02 => load_or_store(pc, memory, false, debug_print),
-            0x03 => push_or_pop(pc, memory, true, debug_print),
-            0x04 => push_or_pop(pc, memory, false, debug_print),
-
-            0x05 => binop(pc, memory, BinopType::Mov, false, debug_print),
-            0x06 => binop(pc, memory, BinopType::Add, false, debug_print),
-            0x07 => binop(pc, memory, BinopType::Sub, false, debug_print),
-            0x08 => binop(pc, memory, BinopType::Mul, false, debug_print),
-            0x09 => binop(pc, memory, BinopType::Div, false, debug_print),
-            0x0A => binop(pc, memory, BinopType::Mod, false, debug_print),
-            0x0B => binop(pc, memory, BinopType::And, false, debug_print),
-            0x0C => binop(pc, memory, BinopType::Or, false, debug_print),
-            0x0D => binop(pc, memory, BinopType::Xor, false, debug_print),
-            0x0E => binop(pc, memory, BinopType::Shl, false, debug_print),
-            0x0F => binop(pc, memory, BinopType::Shr, false, debug_print),
-            0x10 => binop(pc, memory, BinopType::Cmp, false, debug_print),
-
-            0x11 => binop(pc, memory, BinopType::Mov, true, debug_print),
-            0x12 => binop(pc, memory, BinopType::Add, true, debug_print),
-            0x13 => binop(pc, memory, BinopType::Sub, true, debug_print),
-            0x14 => binop(pc, memory, BinopType::Mul, true, debug_print),
-            0x15 => binop(pc, memory, BinopType::Div, true, debug_print),
-            0x16 => binop(pc, memory, BinopType::Mod, true, debug_print),
-            0x17 => binop(pc, memory, BinopType::And, true, debug_print),
-            0x18 => binop(pc, memory, BinopType::Or, true, debug_print),
-            0x19 => binop(pc, memory, BinopType::Xor, true, debug_print),
-            0x1A => binop(pc, memory, BinopType::Shl, true, debug_print),
-            0x1B => binop(pc, memory, BinopType::Shr, true, debug_print),
-            0x1C => binop(pc, memory, BinopType::Cmp, true, debug_print),
-
-            0x1D => jump(pc, memory, true, debug_print),
-            0x1E => jump(pc, memory, memory.flags.zero, debug_print),
-            0x1F => jump(pc, memory, !memory.flags.zero, debug_print),
-            0x20 => jump(pc, memory, memory.flags.positive, debug_print),
-            0x21 => jump(
-                pc,
-                memory,
-                memory.flags.positive || memory.flags.zero,
-                debug_print,
-            ),
-            0x22 => jump(
-                pc,
-                memory,
-                !memory.flags.positive && !memory.flags.zero,
-                debug_print,
-            ),
-            0x23 => jump(pc, memory, !memory.flags.positive, debug_print),
-
-            0x24 => set(pc, memory, true, debug_print),
-            0x25 => set(pc, memory, memory.flags.zero, debug_print),
-            0x26 => set(pc, memory, !memory.flags.zero, debug_print),
-            0x27 => set(pc, memory, memory.flags.positive, debug_print),
-            0x28 => set(
-                pc,
-                memory,
-                memory.flags.positive || memory.flags.zero,
-                debug_print,
-            ),
-            0x29 => set(
-                pc,
-                memory,
-                !memory.flags.positive && !memory.flags.zero,
-                debug_print,
-            ),
-            0x2A => set(pc, memory, !memory.flags.positive, debug_print),
-
-            0x2B => unop(pc, memory, UnopType::Not, debug_print),
-            0x2C => unop(pc, memory, UnopType::Neg, debug_print),
-            0x2D => unop(pc, memory, UnopType::Inc, debug_print),
-            0x2E => unop(pc, memory, UnopType::Dec, debug_print),
-
-            0x2F => call(pc, memory, debug_print),
-            0x30 => ret(memory, debug_print),
-            0x31 => read_stdin(pc, memory, debug_print),
-            0x32 => write_stdout(pc, memory, debug_print),
-            0x33 => rand(pc, memory, debug_print),
-            0x34 => sign_extend(pc, memory, debug_print),
-            0x35 => lea(pc, memory, debug_print),
-            0x36 => pushmem(pc, memory, debug_print),
-            0x37 => popmem(pc, memory, false, debug_print),
-            0x38 => popmem(pc, memory, true, debug_print),
-            0x39 => alloc(pc, memory, heap, debug_print),
-            0x3A => free(pc, memory, heap, debug_print),
-            0x3B => file_open(pc, memory, debug_print),
-            0x3C => file_close(pc, memory, debug_print),
-            0x3D => file_read(pc, memory, debug_print),
-            0x3E => file_write(pc, memory, debug_print),
-            0x3F => mem_copy(pc, memory, debug_print),
-            0x40 => mem_set(pc, memory, debug_print),
-            _ => panic!("Unknown opcode: {}", opcode),
-        };
-        if debug_print {
-            memory.print_registers();
-            memory.print_stack();
-            println!();
-        }
-    }
+pub mod observer;
+pub mod profiler;
+pub mod snapshot;
+pub mod tracer;
+pub mod trap;
+
+/// Number of instructions executed between two checks of the deadline.
+const DEADLINE_CHECK_INTERVAL: u64 = 0x1000;
+
+pub fn execute(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    debug_print: bool,
+) -> Result<i64, VmTrap> {
     if debug_print {
-        heap.print_blocks(&memory);
-    }
-    exit_code
-}
-
-fn read_address(pc: usize, memory: &mut Memory) -> u64 {
-    let first_byte = memory.data[pc];
-    let address_type = first_byte & 0b00000011;
-    let address = match address_type {
-        0 => {
-            memory.registers[constants::PC] += 9;
-            memory.read_u64_le(pc + 1, DATA_SIZE_64)
-        }
-        1 => {
-            memory.registers[constants::PC] += 1;
-            let register = (first_byte & 0b11110000) >> 4;
-            memory.registers[register as usize]
-        }
-        2 => {
-            memory.registers[constants::PC] += 9;
-            let register = (first_byte & 0b11110000) >> 4;
-            let offset = memory.read_i64_le(pc + 1, DATA_SIZE_64);
-            if offset >= 0 {
-                memory.registers[register as usize] + offset as u64
-            } else {
-                memory.registers[register as usize] - (-offset) as u64
-            }
-        }
-        3 => {
-            memory.registers[constants::PC] += 10;
-            let byte3 = memory.data[pc + 1];
-            let index_register = byte3 & 0b00001111;
-            let register = (byte3 & 0b11110000) >> 4;
-            let scale = memory.read_i64_le(pc + 2, DATA_SIZE_64);
-            let signed_offset = scale * memory.registers[index_register as usize] as i64;
-            if signed_offset >= 0 {
-                memory.registers[register as usize] + signed_offset as u64
-            } else {
-                memory.registers[register as usize] - (-signed_offset) as u64
-            }
-        }
-        _ => panic!("Invalid address type: {}", address_type),
-    };
-    address
-}
-
-fn load_or_store(pc: usize, memory: &mut Memory, is_load: bool, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let data_size = 1 << ((byte1 & 0x30) >> 4);
-    let register = (byte1 & 0x0F) as usize;
-    let address = read_address(pc + 2, memory);
-
-    if is_load {
-        let value = memory.read_i64_le(address as usize, data_size);
-        memory.registers[register] = value as u64;
+        execute_observed(program, vm_config, true, &mut DebugPrinter)
     } else {
-        let value = memory.registers[register] as i64;
-        memory.write_i64_le(address as usize, value, data_size);
-    }
-
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        let bytes = memory.read_bytes(address as usize, data_size as usize);
-        if is_load {
-            println!(
-                "Loaded {} bytes ({:?}) from address {} into register {}",
-                data_size, bytes, address, register
-            );
-        } else {
-            println!(
-                "Stored {} bytes from register {} into address {}",
-                data_size, register, address
-            );
-        }
+        execute_observed(program, vm_config, false, &mut NoObserver)
     }
 }
 
-fn push_or_pop(pc: usize, memory: &mut Memory, is_push: bool, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let data_size = 1 << ((byte1 & 0x30) >> 4);
-    let register = (byte1 & 0x0F) as usize;
-
-    if is_push {
-        memory.registers[constants::SP] -= data_size as u64;
-        let address = memory.registers[constants::SP];
-        let value = memory.registers[register] as i64;
-        memory.write_i64_le(address as usize, value, data_size);
-    } else {
-        let address = memory.registers[constants::SP];
-        let value = memory.read_i64_le(address as usize, data_size);
-        memory.registers[register] = value as u64;
-        memory.registers[constants::SP] += data_size as u64;
-    }
-    memory.registers[constants::PC] += 2;
-
+pub fn execute_observed<O: Observer>(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    debug_print: bool,
+    observer: &mut O,
+) -> Result<i64, VmTrap> {
+    let mut machine = Machine::new(program, vm_config, debug_print)?;
+    observer.attach(&machine.heap, &machine.memory);
+    let start_instant = std::time::Instant::now();
+    let result = machine.run_observed(observer);
     if debug_print {
-        if is_push {
-            println!(
-                "Pushed {} bytes from register {} onto the stack at address {}",
-                data_size,
-                register,
-                memory.registers[constants::SP]
-            );
-        } else {
-            println!(
-                "Popped {} bytes from the stack at address {} into register {}",
-                data_size,
-                memory.registers[constants::SP],
-                register
-            );
+        let elapsed = start_instant.elapsed();
+        if result.is_ok() {
+            machine.heap.print_blocks(&machine.memory);
         }
-    }
-}
-
-fn binop(pc: usize, memory: &mut Memory, op_type: BinopType, immediate: bool, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let dest_register = (byte1 & 0x0F) as usize;
-    let left_value = memory.registers[dest_register] as i64;
-
-    let right_value = if immediate {
-        memory.read_i64_le(pc + 2, DATA_SIZE_64)
-    } else {
-        let source_register = ((byte1 & 0xF0) >> 4) as usize;
-        memory.registers[source_register] as i64
-    };
-
-    let result = match op_type {
-        BinopType::Mov => right_value,
-        BinopType::Add => left_value + right_value,
-        BinopType::Sub | BinopType::Cmp => left_value - right_value,
-        BinopType::Mul => left_value * right_value,
-        BinopType::Div => left_value / right_value,
-        BinopType::Mod => left_value % right_value,
-        BinopType::And => left_value & right_value,
-        BinopType::Or => left_value | right_value,
-        BinopType::Xor => left_value ^ right_value,
-        BinopType::Shl => left_value << right_value,
-        BinopType::Shr => left_value >> right_value,
-    };
-
-    set_flags(memory, result);
-
-    if op_type != BinopType::Cmp {
-        memory.registers[dest_register] = result as u64;
-    }
-
-    memory.registers[constants::PC] += if immediate { 10 } else { 2 };
-
-    if debug_print {
-        if immediate {
-            println!(
-                "Performed {:?} operation with register {} and immediate value {}, Result: {}, Flags: {:?}",
-                op_type, dest_register, right_value, result, memory.flags
-            );
-        } else {
-            println!(
-                "Performed {:?} operation with register {} and register {}, Result: {}, Flags: {:?}",
-                op_type,
-                dest_register,
-                ((byte1 & 0xF0) >> 4) as usize,
-                result,
-                memory.flags
-            );
+        println!("Elapsed: {:?}", elapsed);
+        match &result {
+            Ok(exit_code) => println!("Exit Code: {}", exit_code),
+            Err(trap) => println!("{}", trap),
         }
     }
+    result
 }
 
-fn jump(pc: usize, memory: &mut Memory, should_jump: bool, debug_print: bool) {
-    let address = read_address(pc + 1, memory);
+/// The complete state of a running program.
+pub struct Machine {
+    pub(crate) memory: Memory,
+    pub(crate) heap: Heap,
+    pub(crate) program: DecodedProgram,
+    pub(crate) host_functions: HostFunctions,
+    executed: u64,
+    /// The value of `executed` at which the instruction budget runs out.
+    fuel_end: u64,
+    deadline: Option<Instant>,
+    /// The value of `executed` at which the limits are checked next, so the run loop only
+    /// compares two counters.
+    next_limit_check: u64,
+}
 
-    if should_jump {
-        memory.registers[constants::PC] = address;
+impl Machine {
+    /// Loads the code and the read-only data of `program` at the start of memory and points
+    /// the PC at its entry point.
+    pub fn new(
+        program: &ObjectFile,
+        vm_config: &VmConfig,
+        debug_print: bool,
+    ) -> Result<Machine, VmTrap> {
+        let image = program.image();
+        let layout = MemoryLayout::new(image.len(), &vm_config.memory)
+            .map_err(|kind| VmTrap::new(kind, 0, [0; 16]))?;
         if debug_print {
-            println!("Jumped to address {}", address);
+            println!("Memory layout: {:?}", layout);
         }
-    } else {
-        memory.registers[constants::PC] += 1;
-        if debug_print {
-            println!("Did not jump to address {}", address);
+        let mut memory = Memory::new(&layout, image, vm_config);
+        memory.registers[constants::PC] = program.header.entry;
+        let heap = Heap::new(&mut memory, &layout)
+            .map_err(|kind| VmTrap::new(kind, 0, memory.registers))?;
+        Ok(Machine::with_memory(
+            memory,
+            heap,
+            program.code.len(),
+            0,
+            vm_config,
+        ))
+    }
+
+    /// Recreates the machine a snapshot was taken from, reopening its files in `file_system`.
+    /// The memory layout comes from the snapshot, while the limits are taken from `vm_config`.
+    pub fn restore(
+        snapshot: &Snapshot,
+        vm_config: &VmConfig,
+        file_system: Box<dyn FileSystem>,
+    ) -> Result<Machine, VmTrap> {
+        let layout = &snapshot.layout;
+        let mut memory = Memory::new(layout, Vec::new(), vm_config);
+        memory.data.copy_from_slice(&snapshot.data);
+        let heap = Heap::restore(&mut memory, layout, snapshot.heap_stats)
+            .map_err(|kind| VmTrap::new(kind, 0, memory.registers))?;
+        memory.registers = snapshot.registers;
+        memory.flags = snapshot.flags;
+        memory.call_depth = snapshot.call_depth;
+        memory.rng = snapshot.rng.clone();
+        memory.args = snapshot.args.clone();
+        memory.files.file_system = file_system;
+        memory.files.restore(&snapshot.files).map_err(|kind| {
+            VmTrap::new(kind, snapshot.registers[constants::PC], memory.registers)
+        })?;
+        Ok(Machine::with_memory(
+            memory,
+            heap,
+            snapshot.code_size as usize,
+            snapshot.executed,
+            vm_config,
+        ))
+    }
+
+    fn with_memory(
+        mut memory: Memory,
+        heap: Heap,
+        code_size: usize,
+        executed: u64,
+        vm_config: &VmConfig,
+    ) -> Machine {
+        memory.code_size = code_size;
+        let program = DecodedProgram::new(&memory, code_size);
+        let mut machine = Machine {
+            memory,
+            heap,
+            program,
+            host_functions: HostFunctions::default(),
+            executed,
+            fuel_end: u64::MAX,
+            deadline: None,
+            next_limit_check: 0,
+        };
+        machine.set_fuel(vm_config.fuel);
+        machine.set_deadline(vm_config.timeout.map(|timeout| Instant::now() + timeout));
+        machine
+    }
+
+    /// Captures the state of the machine.
+    pub fn snapshot(&self) -> Result<Snapshot, VmTrap> {
+        let pc = self.memory.registers[constants::PC];
+        let files =
+            (self.memory.files.snapshot()).map_err(|kind| trap_at(kind, pc, &self.memory))?;
+        Ok(Snapshot {
+            layout: MemoryLayout {
+                memory_size: self.memory.data.len(),
+                max_memory_size: self.heap.offset + self.heap.max_size,
+                heap_offset: self.heap.offset,
+                heap_size: self.heap.size,
+                stack_start: self.memory.stack_limit as usize,
+                stack_end: self.memory.stack_end as usize,
+            },
+            code_size: self.program.size() as u64,
+            data: self.memory.data.clone(),
+            registers: self.memory.registers,
+            flags: self.memory.flags,
+            call_depth: self.memory.call_depth,
+            executed: self.executed,
+            heap_stats: self.heap.stats(&self.memory),
+            rng: self.memory.rng.clone(),
+            files,
+            args: self.memory.args.clone(),
+            file_policy: self.memory.file_policy.clone(),
+            checked_arithmetic: self.memory.checked_arithmetic,
+        })
+    }
+
+    /// The number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// The number of instructions left in the budget, or `None` if it is unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        (self.fuel_end != u64::MAX).then(|| self.fuel_end - self.executed)
+    }
+
+    /// Limits how many more instructions may be executed. Once they are used up, running
+    /// traps with `VmTrapKind::OutOfFuel`, and the machine can be resumed after refueling.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel_end = fuel.map_or(u64::MAX, |fuel| self.executed.saturating_add(fuel));
+        self.next_limit_check = self.executed;
+    }
+
+    /// Adds `fuel` instructions to the budget, if it is limited.
+    pub fn refuel(&mut self, fuel: u64) {
+        if let Some(remaining) = self.fuel() {
+            self.set_fuel(Some(remaining.saturating_add(fuel)));
         }
     }
-}
 
-fn set(pc: usize, memory: &mut Memory, flag: bool, debug_print: bool) {
-    let register = memory.data[pc + 1] as usize;
-    memory.registers[register] = flag as u64;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!("Set register {} to {}", register, flag as u64);
+    /// Stops execution with `VmTrapKind::Timeout` once `deadline` has passed. The deadline is
+    /// only checked every few thousand instructions.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.next_limit_check = self.executed;
     }
-}
 
-fn unop(pc: usize, memory: &mut Memory, op_type: UnopType, debug_print: bool) {
-    let register = memory.data[pc + 1] as usize;
-    let value = memory.registers[register] as i64;
-    let result = match op_type {
-        UnopType::Not => !value,
-        UnopType::Neg => -value,
-        UnopType::Inc => value + 1,
-        UnopType::Dec => value - 1,
-    };
-    set_flags(memory, result);
-
-    memory.registers[register] = result as u64;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Performed {:?} operation on register {}, Result: {}, Flags: {:?}",
-            op_type, register, result, memory.flags
-        );
+    /// Runs the program until it exits, faults or reaches one of its limits.
+    pub fn run(&mut self) -> Result<i64, VmTrap> {
+        self.run_observed(&mut NoObserver)
     }
-}
-
-fn call(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let address = read_address(pc + 1, memory);
-    memory.registers[constants::SP] -= 8;
-    let return_pc = memory.registers[constants::PC] + 1;
-    memory.write_u64_le(
-        memory.registers[constants::SP] as usize,
-        return_pc,
-        DATA_SIZE_64,
-    );
-    memory.registers[constants::PC] = address;
-
-    if debug_print {
-        println!("Called Address {}, Return PC: {}", address, return_pc);
-    }
-}
-
-fn ret(memory: &mut Memory, debug_print: bool) {
-    let address = memory.read_u64_le(memory.registers[constants::SP] as usize, DATA_SIZE_64);
-    memory.registers[constants::SP] += 8;
-    memory.registers[constants::PC] = address;
-
-    if debug_print {
-        println!("Returned to Address {}", address);
-    }
-}
-
-fn read_stdin(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let read_bytes = memory.registers[register as usize] as usize;
-    let address = read_address(pc + 2, memory) as usize;
 
-    let mut buffer = vec![0; read_bytes];
-    std::io::stdin().read_exact(&mut buffer).unwrap();
-    memory.write_bytes(address, &buffer);
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Read {} bytes from stdin into address {}",
-            read_bytes, address
-        );
-    }
-}
-
-fn write_stdout(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let write_bytes = memory.registers[register as usize] as usize;
-    let address = read_address(pc + 2, memory) as usize;
-
-    let buffer = memory.read_bytes(address, write_bytes);
-    std::io::stdout().write_all(&buffer).unwrap();
-    memory.registers[constants::PC] += 2;
-
-    let str = std::str::from_utf8(&buffer).unwrap();
-
-    if debug_print {
-        println!(
-            "Wrote '{}' ({} bytes) to stdout from address {}",
-            str, write_bytes, address
-        );
-    }
-}
-
-fn rand(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let value = rand::random::<u64>();
-    memory.registers[register as usize] = value;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Generated random number {} and stored it in register {}",
-            value, register
-        );
-    }
-}
-
-fn exit(exit_code: &mut i64, memory: &mut Memory, debug_print: bool) {
-    *exit_code = memory.registers[0] as i64;
-    memory.registers[constants::PC] += 1;
-
-    if debug_print {
-        println!("Exiting with code {}", exit_code);
-    }
-}
-
-fn set_flags(memory: &mut Memory, value: i64) {
-    memory.flags.zero = value == 0;
-    memory.flags.positive = value > 0;
-}
-
-fn sign_extend(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let data_size = 1 << ((byte1 & 0x30) >> 4);
-    let register = byte1 & 0x0F;
-
-    let value = memory.registers[register as usize] as i64;
-
-    let extended_value = match data_size {
-        1 => value as i8 as i64,
-        2 => value as i16 as i64,
-        4 => value as i32 as i64,
-        8 => value,
-        _ => panic!("Invalid data size: {}", data_size),
-    };
-
-    memory.registers[register as usize] = extended_value as u64;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Sign extended register {} with value {} ({} bytes)",
-            register, value, data_size
-        );
-    }
-}
-
-fn lea(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let address = read_address(pc + 2, memory);
-
-    memory.registers[register as usize] = address;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!("Loaded address {} into register {}", address, register);
-    }
-}
-
-fn pushmem(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let data_size = memory.registers[register as usize];
-
-    memory.registers[constants::SP] -= data_size;
-    let dest_address = memory.registers[constants::SP] as usize;
-    let src_address = read_address(pc + 2, memory) as usize;
-
-    memory.memory_copy(src_address, dest_address, data_size as usize);
-
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Pushed {} bytes from address {} onto the stack at address {}",
-            data_size, src_address, dest_address
-        );
-    }
-}
-
-fn popmem(pc: usize, memory: &mut Memory, is_peek: bool, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let data_size = memory.registers[register as usize];
-
-    let src_address = memory.registers[constants::SP] as usize;
-    let dest_address = read_address(pc + 2, memory) as usize;
-
-    memory.memory_copy(src_address, dest_address, data_size as usize);
-
-    if !is_peek {
-        memory.registers[constants::SP] += data_size;
-    }
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        if is_peek {
-            println!(
-                "Peeked {} bytes from the stack at address {} into address {}",
-                data_size, src_address, dest_address
-            );
-        } else {
-            println!(
-                "Popped {} bytes from the stack at address {} into address {}",
-                data_size, src_address, dest_address
-            );
+    /// Runs the program like `run`. The loop is monomorphized for the observer, so tracing and
+    /// profiling are compiled out when running with `NoObserver`.
+    pub(crate) fn run_observed<O: Observer>(&mut self, observer: &mut O) -> Result<i64, VmTrap> {
+        loop {
+            if let Some(exit_code) = self.step_observed(observer)? {
+                return Ok(exit_code);
+            }
         }
     }
-}
-
-fn alloc(pc: usize, memory: &mut Memory, heap: &mut Heap, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let size_register = (byte1 & 0x0F) as usize;
-    let size = memory.registers[size_register] as i64;
-
-    let address_register = ((byte1 & 0xF0) >> 4) as usize;
-
-    let address = heap.malloc(memory, size as u64).unwrap_or_else(|| {
-        heap.print_blocks(memory);
-        panic!("Failed to allocate {} bytes", size);
-    });
-    memory.registers[address_register] = address as u64;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!("Allocated {} bytes at address {}", size, address);
-    }
-}
-
-fn free(pc: usize, memory: &mut Memory, heap: &mut Heap, debug_print: bool) {
-    let address_register = memory.data[pc + 1] as usize;
-    let address = memory.registers[address_register] as usize;
-
-    heap.free(memory, address);
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!("Freed memory at address {}", address);
-    }
-}
-
-fn file_open(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1];
-    let address = read_address(pc + 2, memory) as usize;
-
-    let filename = memory.read_string(address);
-
-    let file = std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&filename)
-        .unwrap();
-
-    let file_id = memory.files.len();
-    memory.files.push(Some(file));
-    memory.registers[register as usize] = file_id as u64;
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!("Opened file '{}' with ID {}", filename, file_id);
-    }
-}
-
-fn file_close(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let register = memory.data[pc + 1] as usize;
-    let file_id = memory.registers[register] as usize;
-    let file = std::mem::replace(&mut memory.files[file_id], None).unwrap();
-    file.sync_all().unwrap();
-    file.metadata().unwrap();
-    file.sync_data().unwrap();
-    drop(file);
-    memory.registers[constants::PC] += 2;
-    let mut new_len = memory.files.len();
-    while new_len > 0 && memory.files[new_len - 1].is_none() {
-        new_len -= 1;
-    }
-    memory.files.truncate(new_len);
 
-    if debug_print {
-        println!("Closed file with ID {}", file_id);
+    /// Executes a single instruction, returning the exit code if it was an `exit`.
+    pub fn step(&mut self) -> Result<Option<i64>, VmTrap> {
+        self.step_observed(&mut NoObserver)
     }
-}
 
-fn file_read(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let file_id_register = (byte1 & 0x0F) as usize;
-    let size_register = ((byte1 & 0xF0) >> 4) as usize;
-    let file_id = memory.registers[file_id_register] as usize;
-    let size = memory.registers[size_register] as usize;
-    let address = read_address(pc + 2, memory) as usize;
-
-    let file = memory.files[file_id].as_mut().unwrap();
-    let mut buffer = vec![0; size];
-    let read_bytes = file.read(&mut buffer).unwrap();
-    memory.write_bytes(address, &buffer);
-    memory.registers[constants::PC] += 2;
-    memory.registers[size_register] = read_bytes as u64;
-
-    if debug_print {
-        println!(
-            "Read {} bytes from file with ID {} into address {}",
-            size, file_id, address
-        );
-    }
-}
-
-fn file_write(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let file_id_register = (byte1 & 0x0F) as usize;
-    let size_register = ((byte1 & 0xF0) >> 4) as usize;
-    let file_id = memory.registers[file_id_register] as usize;
-    let size = memory.registers[size_register] as usize;
-    let address = read_address(pc + 2, memory) as usize;
-
-    let buffer = memory.read_bytes(address, size);
-    let file = memory.files[file_id].as_mut().unwrap();
-    file.write_all(&buffer).unwrap();
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Wrote {} bytes to file with ID {} from address {}",
-            size, file_id, address
-        );
-    }
-}
-
-fn mem_copy(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let size_register = memory.data[pc + 1];
-    let size = memory.registers[size_register as usize] as usize;
-    let dest_address = read_address(pc + 2, memory) as usize;
-    let new_pc = memory.registers[constants::PC] as usize;
-    let src_address = read_address(new_pc + 2, memory) as usize;
-
-    memory.memory_copy(src_address, dest_address, size);
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Copied {} bytes from address {} to address {}",
-            size, src_address, dest_address
-        );
+    #[inline(always)]
+    fn step_observed<O: Observer>(&mut self, observer: &mut O) -> Result<Option<i64>, VmTrap> {
+        let pc = self.memory.registers[constants::PC];
+        if self.executed >= self.next_limit_check {
+            self.check_limits(pc)?;
+        }
+        let decoded_on_demand;
+        let decoded = match self.program.get(pc) {
+            Some(decoded) => decoded,
+            None => {
+                decoded_on_demand = decode(&self.memory, pc as usize)
+                    .map_err(|kind| trap_at(kind, pc, &self.memory))?;
+                &decoded_on_demand
+            }
+        };
+        observer.before_instruction(pc, &decoded.instruction, &self.memory);
+        self.memory.registers[constants::PC] = pc + decoded.length as u64;
+        let result = interpreter::execute(
+            &decoded.instruction,
+            &mut self.memory,
+            &mut self.heap,
+            &mut self.host_functions,
+        )
+        .map_err(|kind| trap_at(kind, pc, &self.memory))?;
+        self.executed += 1;
+        observer.after_instruction(pc, &decoded.instruction, &self.memory);
+        Ok(result)
+    }
+
+    #[cold]
+    fn check_limits(&mut self, pc: u64) -> Result<(), VmTrap> {
+        let executed = self.executed;
+        if executed >= self.fuel_end {
+            return Err(trap_at(
+                VmTrapKind::OutOfFuel { executed },
+                pc,
+                &self.memory,
+            ));
+        }
+        let next_deadline_check = match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                return Err(trap_at(VmTrapKind::Timeout { executed }, pc, &self.memory));
+            }
+            Some(_) => executed.saturating_add(DEADLINE_CHECK_INTERVAL),
+            None => u64::MAX,
+        };
+        self.next_limit_check = self.fuel_end.min(next_deadline_check);
+        Ok(())
     }
 }
 
-fn mem_set(pc: usize, memory: &mut Memory, debug_print: bool) {
-    let byte1 = memory.data[pc + 1];
-    let size_register = (byte1 & 0x0F) as usize;
-    let value_register = ((byte1 & 0xF0) >> 4) as usize;
-    let value = memory.registers[value_register] as u8;
-    let size = memory.registers[size_register] as usize;
-    let address = read_address(pc + 2, memory) as usize;
-
-    memory.memory_set(address, value, size);
-    memory.registers[constants::PC] += 2;
-
-    if debug_print {
-        println!(
-            "Set {} bytes at address {} to value {}",
-            size, address, value
-        );
-    }
+/// Builds the trap for a fault raised by the instruction at `pc`.
+fn trap_at(kind: VmTrapKind, pc: u64, memory: &Memory) -> VmTrap {
+    let mut registers = memory.registers;
+    registers[constants::PC] = pc;
+    let mut trap = VmTrap::new(kind, pc, registers);
+    trap.backtrace = backtrace(memory, pc);
+    trap
 }
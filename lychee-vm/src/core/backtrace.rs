@@ -0,0 +1,70 @@
+use crate::core::constants;
+use crate::core::decoder::{decode, Instruction};
+use crate::core::memory::Memory;
+use lychee_compiler::{BinopType, LineTable, DATA_SIZE_64};
+
+/// The number of frames `format_backtrace` prints before leaving out the rest, which keeps the
+/// backtrace of a stack overflow readable.
+const MAX_PRINTED_FRAMES: usize = 64;
+
+/// Returns `pc` followed by the return addresses of the active calls, innermost first.
+///
+/// The frames are found by following the BP chain: every function starts with `push #64 bp`
+/// and `mov bp sp`, so BP points at the saved BP of the caller with the return address right
+/// above it. The walk ends at the initial BP, the end of the stack.
+pub fn backtrace(memory: &Memory, pc: u64) -> Vec<u64> {
+    let mut frames = vec![pc];
+    // Before those two instructions have run, BP still points at the frame of the caller and
+    // the return address is found relative to SP instead.
+    let sp = memory.registers[constants::SP];
+    let bp_register = constants::BP as u8;
+    let return_address_slot = match decode(memory, pc as usize).map(|x| x.instruction) {
+        Ok(Instruction::Push { size: 8, register }) if register == bp_register => Some(sp),
+        Ok(Instruction::Binop {
+            op: BinopType::Mov,
+            dest,
+            source,
+        }) if dest == bp_register && source == constants::SP as u8 => sp.checked_add(8),
+        _ => None,
+    };
+    if let Some(slot) = return_address_slot {
+        if let Ok(return_address) = memory.read_u64_le(slot as usize, DATA_SIZE_64) {
+            frames.push(return_address);
+        }
+    }
+    let mut bp = memory.registers[constants::BP];
+    while bp.saturating_add(16) <= memory.stack_end {
+        let (Ok(caller_bp), Ok(return_address)) = (
+            memory.read_u64_le(bp as usize, DATA_SIZE_64),
+            memory.read_u64_le(bp as usize + 8, DATA_SIZE_64),
+        ) else {
+            break;
+        };
+        frames.push(return_address);
+        // The stack grows down, anything else means the chain is corrupted.
+        if caller_bp <= bp {
+            break;
+        }
+        bp = caller_bp;
+    }
+    frames
+}
+
+/// Formats the frames of a backtrace as `at file:line in function` lines.
+pub fn format_backtrace(frames: &[u64], line_table: &LineTable) -> String {
+    let mut text = String::new();
+    for (index, address) in frames.iter().enumerate() {
+        if index == MAX_PRINTED_FRAMES {
+            text.push_str(&format!("  ... {} more frames\n", frames.len() - index));
+            break;
+        }
+        // A return address points after the call, which may already belong to the next line.
+        let address = if index == 0 {
+            *address
+        } else {
+            address.saturating_sub(1)
+        };
+        text.push_str(&format!("  at {}\n", line_table.describe(address)));
+    }
+    text
+}
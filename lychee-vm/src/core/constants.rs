@@ -3,3 +3,8 @@ use lychee_compiler::RegisterCode;
 pub const BP: usize = RegisterCode::BP as usize;
 pub const SP: usize = RegisterCode::SP as usize;
 pub const PC: usize = RegisterCode::PC as usize;
+
+pub const REGISTER_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "bp", "sp",
+    "pc",
+];
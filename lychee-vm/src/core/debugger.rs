@@ -0,0 +1,310 @@
+use crate::core::backtrace::{backtrace, format_backtrace};
+use crate::core::constants::{self, REGISTER_NAMES};
+use crate::core::decoder::{decode, Instruction};
+use crate::core::trap::VmTrap;
+use crate::core::Machine;
+use lychee_compiler::{LineTable, ObjectFile, SymbolTable, VmConfig};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "Commands:
+  break <address|label>    (b)   set a breakpoint
+  delete <address|label>   (d)   remove a breakpoint
+  breakpoints              (bl)  list breakpoints
+  step [count]             (s)   execute single instructions
+  next                     (n)   step over a call
+  finish                   (f)   run until the current function returns
+  continue                 (c)   run until a breakpoint, exit or fault
+  registers                (r)   show registers and flags
+  examine <address> [len]  (x)   hexdump memory, the address may be a register
+  heap                     (h)   list heap blocks
+  where                    (w)   show the current location
+  backtrace                (bt)  show the active calls and their source lines
+  quit                     (q)   leave the debugger
+An empty line repeats the previous command.";
+
+enum DebuggerStatus {
+    Running,
+    Exited(i64),
+    Trapped(VmTrap),
+}
+
+pub struct Debugger {
+    machine: Machine,
+    symbols: SymbolTable,
+    line_table: LineTable,
+    breakpoints: BTreeSet<u64>,
+    status: DebuggerStatus,
+}
+
+pub fn debug(program: &ObjectFile, vm_config: &VmConfig) -> Result<(), VmTrap> {
+    let machine = Machine::new(program, vm_config, false)?;
+    let mut debugger = Debugger {
+        machine,
+        symbols: program.symbols.clone(),
+        line_table: program.line_table.clone(),
+        breakpoints: BTreeSet::new(),
+        status: DebuggerStatus::Running,
+    };
+    debugger.command_loop();
+    Ok(())
+}
+
+impl Debugger {
+    fn command_loop(&mut self) {
+        println!("Lychee debugger, type 'help' for a list of commands.");
+        self.print_location();
+        let stdin = std::io::stdin();
+        let mut last_command = String::new();
+        loop {
+            print!("(lychee) ");
+            std::io::stdout().flush().unwrap();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            let line = line.trim();
+            let command = if line.is_empty() {
+                last_command.clone()
+            } else {
+                line.to_string()
+            };
+            if !self.execute_command(&command) {
+                break;
+            }
+            last_command = command;
+        }
+    }
+
+    /// Executes a debugger command, returning `false` if the debugger should quit.
+    fn execute_command(&mut self, command: &str) -> bool {
+        let parts = command.split_whitespace().collect::<Vec<&str>>();
+        let Some(name) = parts.first() else {
+            return true;
+        };
+        match *name {
+            "b" | "break" => self.set_breakpoint(parts.get(1), true),
+            "d" | "delete" => self.set_breakpoint(parts.get(1), false),
+            "bl" | "breakpoints" => self.print_breakpoints(),
+            "s" | "step" => {
+                let count = parts.get(1).and_then(|x| x.parse().ok()).unwrap_or(1);
+                for _ in 0..count {
+                    if !self.step() {
+                        break;
+                    }
+                }
+                self.print_location();
+            }
+            "n" | "next" => {
+                self.step_over();
+                self.print_location();
+            }
+            "f" | "finish" => {
+                let depth = self.machine.memory.call_depth;
+                self.run_while(|debugger| debugger.machine.memory.call_depth >= depth);
+                self.print_location();
+            }
+            "c" | "continue" => {
+                self.run_while(|_| true);
+                self.print_location();
+            }
+            "r" | "registers" => self.print_registers(),
+            "x" | "examine" => self.examine(parts.get(1), parts.get(2)),
+            "h" | "heap" => self.machine.heap.print_blocks(&self.machine.memory),
+            "w" | "where" => self.print_location(),
+            "bt" | "backtrace" => self.print_backtrace(),
+            "help" => println!("{}", HELP),
+            "q" | "quit" => return false,
+            _ => println!("Unknown command '{}', type 'help' for help.", name),
+        }
+        true
+    }
+
+    fn pc(&self) -> u64 {
+        self.machine.memory.registers[constants::PC]
+    }
+
+    fn current_instruction(&self) -> Option<Instruction> {
+        decode(&self.machine.memory, self.pc() as usize)
+            .ok()
+            .map(|decoded| decoded.instruction)
+    }
+
+    /// Executes a single instruction, returning `false` if the program is no longer running.
+    fn step(&mut self) -> bool {
+        if !matches!(self.status, DebuggerStatus::Running) {
+            println!("The program is not running.");
+            return false;
+        }
+        match self.machine.step() {
+            Ok(None) => return true,
+            Ok(Some(exit_code)) => {
+                println!("Program exited with code {}.", exit_code);
+                self.status = DebuggerStatus::Exited(exit_code);
+            }
+            Err(trap) => {
+                println!("{}", trap);
+                print!("{}", format_backtrace(&trap.backtrace, &self.line_table));
+                self.status = DebuggerStatus::Trapped(trap);
+            }
+        }
+        false
+    }
+
+    /// Steps at least once and keeps going while `condition` holds and no breakpoint is hit.
+    fn run_while(&mut self, condition: impl Fn(&Debugger) -> bool) {
+        while self.step() && condition(self) {
+            if self.breakpoints.contains(&self.pc()) {
+                println!("Hit breakpoint at {}.", self.describe_address(self.pc()));
+                break;
+            }
+        }
+    }
+
+    fn step_over(&mut self) {
+        let is_call = matches!(self.current_instruction(), Some(Instruction::Call { .. }));
+        if is_call {
+            let depth = self.machine.memory.call_depth;
+            self.run_while(|debugger| debugger.machine.memory.call_depth > depth);
+        } else {
+            self.step();
+        }
+    }
+
+    fn resolve_location(&self, location: &str) -> Vec<u64> {
+        match parse_number(location) {
+            Some(address) => vec![address],
+            None => self.symbols.find(location),
+        }
+    }
+
+    fn set_breakpoint(&mut self, location: Option<&&str>, enabled: bool) {
+        let Some(location) = location else {
+            println!("Expected an address or label.");
+            return;
+        };
+        let addresses = self.resolve_location(location);
+        if addresses.is_empty() {
+            println!("No label matches '{}'.", location);
+        }
+        for address in addresses {
+            let description = self.describe_address(address);
+            if enabled && self.breakpoints.insert(address) {
+                println!("Breakpoint set at {}.", description);
+            } else if !enabled && self.breakpoints.remove(&address) {
+                println!("Breakpoint removed at {}.", description);
+            }
+        }
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints.");
+        }
+        for address in &self.breakpoints {
+            println!("  {}", self.describe_address(*address));
+        }
+    }
+
+    fn describe_address(&self, address: u64) -> String {
+        match self.symbols.symbolize(address) {
+            Some((label, 0)) => format!("{} <{}>", address, label),
+            Some((label, offset)) => format!("{} <{}+{}>", address, label, offset),
+            None => address.to_string(),
+        }
+    }
+
+    fn print_location(&self) {
+        match &self.status {
+            DebuggerStatus::Running => {
+                let pc = self.pc();
+                match self.current_instruction() {
+                    Some(instruction) => println!(
+                        "PC {}, {:?}, call depth {}",
+                        self.describe_address(pc),
+                        instruction,
+                        self.machine.memory.call_depth
+                    ),
+                    None => println!("PC {} does not hold a valid instruction", pc),
+                }
+            }
+            DebuggerStatus::Exited(exit_code) => {
+                println!("The program exited with code {}.", exit_code)
+            }
+            DebuggerStatus::Trapped(trap) => println!("The program faulted: {}", trap.kind),
+        }
+    }
+
+    fn print_backtrace(&self) {
+        let frames = match &self.status {
+            DebuggerStatus::Running => backtrace(&self.machine.memory, self.pc()),
+            DebuggerStatus::Exited(_) => {
+                println!("The program is not running.");
+                return;
+            }
+            DebuggerStatus::Trapped(trap) => trap.backtrace.clone(),
+        };
+        print!("{}", format_backtrace(&frames, &self.line_table));
+    }
+
+    fn print_registers(&self) {
+        for (name, value) in REGISTER_NAMES.iter().zip(self.machine.memory.registers) {
+            println!("{:>4} 0x{:016X} {}", name, value, value as i64);
+        }
+        let flags = self.machine.memory.flags;
+        println!(
+            "Flags: zero={}, positive={}, carry={}, overflow={}",
+            flags.zero, flags.positive, flags.carry, flags.overflow
+        );
+    }
+
+    fn examine(&self, address: Option<&&str>, length: Option<&&str>) {
+        let address = address.and_then(|address| {
+            REGISTER_NAMES
+                .iter()
+                .position(|name| name == address)
+                .map(|register| self.machine.memory.registers[register])
+                .or_else(|| self.resolve_location(address).first().copied())
+        });
+        let Some(address) = address else {
+            println!("Expected an address, register or label.");
+            return;
+        };
+        let length = length.and_then(|x| parse_number(x)).unwrap_or(64);
+        let Ok(bytes) = self
+            .machine
+            .memory
+            .read_bytes(address as usize, length as usize)
+        else {
+            println!("Address range is outside of memory.");
+            return;
+        };
+        for (index, chunk) in bytes.chunks(16).enumerate() {
+            let hex = chunk
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<String>>()
+                .join(" ");
+            let ascii = chunk
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7E => *byte as char,
+                    _ => '.',
+                })
+                .collect::<String>();
+            println!(
+                "{:08X}  {:<47}  {}",
+                address + index as u64 * 16,
+                hex,
+                ascii
+            );
+        }
+    }
+}
+
+fn parse_number(str: &str) -> Option<u64> {
+    match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => str.parse().ok(),
+    }
+}
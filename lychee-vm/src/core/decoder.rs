@@ -0,0 +1,697 @@
+use crate::core::memory::Memory;
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::{BinopType, FlagConditionType, FloatBinopType, UnopType, DATA_SIZE_64};
+use serde::Serialize;
+
+const BINOP_TYPES: [BinopType; 15] = [
+    BinopType::Mov,
+    BinopType::Add,
+    BinopType::Sub,
+    BinopType::Mul,
+    BinopType::Div,
+    BinopType::Mod,
+    BinopType::And,
+    BinopType::Or,
+    BinopType::Xor,
+    BinopType::Shl,
+    BinopType::Shr,
+    BinopType::Cmp,
+    BinopType::UnsignedDiv,
+    BinopType::UnsignedMod,
+    BinopType::UnsignedShr,
+];
+
+const FLOAT_BINOP_TYPES: [FloatBinopType; 5] = [
+    FloatBinopType::Add,
+    FloatBinopType::Sub,
+    FloatBinopType::Mul,
+    FloatBinopType::Div,
+    FloatBinopType::Cmp,
+];
+
+const UNOP_TYPES: [UnopType; 4] = [UnopType::Not, UnopType::Neg, UnopType::Inc, UnopType::Dec];
+
+const FLAG_CONDITION_TYPES: [FlagConditionType; 13] = [
+    FlagConditionType::Always,
+    FlagConditionType::Zero,
+    FlagConditionType::NotZero,
+    FlagConditionType::Greater,
+    FlagConditionType::GreaterEquals,
+    FlagConditionType::Less,
+    FlagConditionType::LessEquals,
+    FlagConditionType::Above,
+    FlagConditionType::AboveEquals,
+    FlagConditionType::Below,
+    FlagConditionType::BelowEquals,
+    FlagConditionType::Overflow,
+    FlagConditionType::NotOverflow,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Address {
+    Immediate(u64),
+    Register(u8),
+    RegisterOffset(u8, i64),
+    RegisterScaledIndex(u8, u8, i64),
+}
+
+impl Address {
+    pub fn resolve(&self, registers: &[u64; 16]) -> u64 {
+        match *self {
+            Address::Immediate(address) => address,
+            Address::Register(register) => registers[register as usize],
+            Address::RegisterOffset(register, offset) => {
+                registers[register as usize].wrapping_add_signed(offset)
+            }
+            Address::RegisterScaledIndex(register, index_register, scale) => {
+                let offset = scale.wrapping_mul(registers[index_register as usize] as i64);
+                registers[register as usize].wrapping_add_signed(offset)
+            }
+        }
+    }
+}
+
+/// A single instruction with all of its operands decoded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Instruction {
+    Exit,
+    Load {
+        size: u8,
+        register: u8,
+        address: Address,
+    },
+    Store {
+        size: u8,
+        register: u8,
+        address: Address,
+    },
+    Push {
+        size: u8,
+        register: u8,
+    },
+    Pop {
+        size: u8,
+        register: u8,
+    },
+    Binop {
+        op: BinopType,
+        dest: u8,
+        source: u8,
+    },
+    BinopImmediate {
+        op: BinopType,
+        dest: u8,
+        value: i64,
+    },
+    Jump {
+        condition: FlagConditionType,
+        address: Address,
+    },
+    Set {
+        condition: FlagConditionType,
+        register: u8,
+    },
+    Unop {
+        op: UnopType,
+        register: u8,
+    },
+    Call {
+        address: Address,
+    },
+    Ret,
+    ReadStdin {
+        register: u8,
+        address: Address,
+    },
+    WriteStdout {
+        register: u8,
+        address: Address,
+    },
+    Rand {
+        register: u8,
+    },
+    SeedRand {
+        register: u8,
+    },
+    SignExtend {
+        size: u8,
+        register: u8,
+    },
+    Lea {
+        register: u8,
+        address: Address,
+    },
+    PushMem {
+        register: u8,
+        address: Address,
+    },
+    PopMem {
+        register: u8,
+        address: Address,
+    },
+    PeekMem {
+        register: u8,
+        address: Address,
+    },
+    Alloc {
+        size_register: u8,
+        address_register: u8,
+    },
+    Free {
+        register: u8,
+    },
+    Realloc {
+        size_register: u8,
+        address_register: u8,
+    },
+    FileOpen {
+        register: u8,
+        address: Address,
+    },
+    FileClose {
+        register: u8,
+    },
+    FileRead {
+        file_register: u8,
+        size_register: u8,
+        address: Address,
+    },
+    FileWrite {
+        file_register: u8,
+        size_register: u8,
+        address: Address,
+    },
+    /// Opens a file with the mode held in the register, which is replaced by the handle.
+    FileOpenMode {
+        register: u8,
+        address: Address,
+    },
+    FileSeek {
+        file_register: u8,
+        position_register: u8,
+    },
+    FileTell {
+        register: u8,
+    },
+    FileSize {
+        register: u8,
+    },
+    FileRemove {
+        register: u8,
+        address: Address,
+    },
+    FileRename {
+        register: u8,
+        from: Address,
+        to: Address,
+    },
+    MemCopy {
+        size_register: u8,
+        dest: Address,
+        src: Address,
+    },
+    MemSet {
+        size_register: u8,
+        value_register: u8,
+        address: Address,
+    },
+    HostCall {
+        address_register: u8,
+        size_register: u8,
+        id: u64,
+    },
+    FloatBinop {
+        op: FloatBinopType,
+        size: u8,
+        dest: u8,
+        source: u8,
+    },
+    FloatNeg {
+        size: u8,
+        register: u8,
+    },
+    IntToFloat {
+        size: u8,
+        register: u8,
+    },
+    FloatToInt {
+        size: u8,
+        register: u8,
+    },
+    /// Converts between `float` and `double`, the size is the one of the result.
+    FloatConvert {
+        size: u8,
+        register: u8,
+    },
+    /// Stops the program with a trap, used by `panic` to get a backtrace.
+    Abort,
+    ArgCount {
+        register: u8,
+    },
+    /// Replaces the index of an argument in the register with its length in bytes.
+    ArgLength {
+        register: u8,
+    },
+    /// Copies up to `size_register` bytes of an argument to memory and sets `size_register` to
+    /// the number of bytes copied.
+    ArgRead {
+        index_register: u8,
+        size_register: u8,
+        address: Address,
+    },
+}
+
+impl Instruction {
+    /// The assembler mnemonic of the instruction.
+    pub fn mnemonic(&self) -> &'static str {
+        const BINOP_MNEMONICS: [&str; 15] = [
+            "mov", "add", "sub", "mul", "div", "mod", "and", "or", "xor", "shl", "shr", "cmp",
+            "udiv", "umod", "ushr",
+        ];
+        const BINOP_IMMEDIATE_MNEMONICS: [&str; 15] = [
+            "movi", "addi", "subi", "muli", "divi", "modi", "andi", "ori", "xori", "shli", "shri",
+            "cmpi", "udivi", "umodi", "ushri",
+        ];
+        const JUMP_MNEMONICS: [&str; 13] = [
+            "jmp", "jz", "jnz", "jg", "jge", "jl", "jle", "ja", "jae", "jb", "jbe", "jo", "jno",
+        ];
+        const SET_MNEMONICS: [&str; 13] = [
+            "set", "setz", "setnz", "setg", "setge", "setl", "setle", "seta", "setae", "setb",
+            "setbe", "seto", "setno",
+        ];
+        const UNOP_MNEMONICS: [&str; 4] = ["not", "neg", "inc", "dec"];
+        const FLOAT_BINOP_MNEMONICS: [&str; 5] = ["fadd", "fsub", "fmul", "fdiv", "fcmp"];
+
+        match self {
+            Instruction::Exit => "exit",
+            Instruction::Load { .. } => "load",
+            Instruction::Store { .. } => "store",
+            Instruction::Push { .. } => "push",
+            Instruction::Pop { .. } => "pop",
+            Instruction::Binop { op, .. } => BINOP_MNEMONICS[*op as usize],
+            Instruction::BinopImmediate { op, .. } => BINOP_IMMEDIATE_MNEMONICS[*op as usize],
+            Instruction::Jump { condition, .. } => JUMP_MNEMONICS[*condition as usize],
+            Instruction::Set { condition, .. } => SET_MNEMONICS[*condition as usize],
+            Instruction::Unop { op, .. } => UNOP_MNEMONICS[*op as usize],
+            Instruction::Call { .. } => "call",
+            Instruction::Ret => "ret",
+            Instruction::ReadStdin { .. } => "read",
+            Instruction::WriteStdout { .. } => "write",
+            Instruction::Rand { .. } => "rand",
+            Instruction::SeedRand { .. } => "seedrand",
+            Instruction::HostCall { .. } => "hostcall",
+            Instruction::SignExtend { .. } => "signext",
+            Instruction::Lea { .. } => "lea",
+            Instruction::PushMem { .. } => "pushmem",
+            Instruction::PopMem { .. } => "popmem",
+            Instruction::PeekMem { .. } => "peekmem",
+            Instruction::Alloc { .. } => "alloc",
+            Instruction::Free { .. } => "free",
+            Instruction::Realloc { .. } => "realloc",
+            Instruction::FileOpen { .. } => "fileopen",
+            Instruction::FileClose { .. } => "fileclose",
+            Instruction::FileRead { .. } => "fileread",
+            Instruction::FileWrite { .. } => "filewrite",
+            Instruction::FileOpenMode { .. } => "fileopenmode",
+            Instruction::FileSeek { .. } => "fileseek",
+            Instruction::FileTell { .. } => "filetell",
+            Instruction::FileSize { .. } => "filesize",
+            Instruction::FileRemove { .. } => "fileremove",
+            Instruction::FileRename { .. } => "filerename",
+            Instruction::MemCopy { .. } => "memcopy",
+            Instruction::MemSet { .. } => "memset",
+            Instruction::FloatBinop { op, .. } => FLOAT_BINOP_MNEMONICS[*op as usize],
+            Instruction::FloatNeg { .. } => "fneg",
+            Instruction::IntToFloat { .. } => "itof",
+            Instruction::FloatToInt { .. } => "ftoi",
+            Instruction::FloatConvert { .. } => "fcvt",
+            Instruction::Abort => "abort",
+            Instruction::ArgCount { .. } => "argc",
+            Instruction::ArgLength { .. } => "arglen",
+            Instruction::ArgRead { .. } => "argread",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+    pub length: u8,
+}
+
+/// Decodes instructions by reading from memory, keeping track of the instruction length.
+struct Decoder<'a> {
+    memory: &'a Memory,
+    pc: usize,
+    offset: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> VmResult<u8> {
+        let byte = self.memory.read_byte(self.pc + self.offset)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn i64(&mut self) -> VmResult<i64> {
+        let value = self
+            .memory
+            .read_i64_le(self.pc + self.offset, DATA_SIZE_64)?;
+        self.offset += 8;
+        Ok(value)
+    }
+
+    /// Reads a byte holding a single register operand.
+    fn register(&mut self) -> VmResult<u8> {
+        let register = self.byte()?;
+        if register >= 16 {
+            return Err(VmTrapKind::InvalidRegister(register));
+        }
+        Ok(register)
+    }
+
+    /// Reads a byte holding a size in bits 4-5 and a register in the low nibble.
+    fn size_register(&mut self) -> VmResult<(u8, u8)> {
+        let byte = self.byte()?;
+        Ok((1 << ((byte & 0x30) >> 4), byte & 0x0F))
+    }
+
+    /// Reads a byte holding the size of a `float` or `double` in bits 4-5 and a register in the
+    /// low nibble.
+    fn float_size_register(&mut self, opcode: u8) -> VmResult<(u8, u8)> {
+        let (size, register) = self.size_register()?;
+        if size != 4 && size != 8 {
+            return Err(VmTrapKind::InvalidOpcode(opcode));
+        }
+        Ok((size, register))
+    }
+
+    /// Reads a byte holding two registers, returning the low nibble first.
+    fn two_registers(&mut self) -> VmResult<(u8, u8)> {
+        let byte = self.byte()?;
+        Ok((byte & 0x0F, (byte & 0xF0) >> 4))
+    }
+
+    fn address(&mut self) -> VmResult<Address> {
+        let first_byte = self.byte()?;
+        let register = (first_byte & 0xF0) >> 4;
+        Ok(match first_byte & 0b11 {
+            0 => Address::Immediate(self.i64()? as u64),
+            1 => Address::Register(register),
+            2 => Address::RegisterOffset(register, self.i64()?),
+            _ => {
+                // The assembler stores the base register in the low nibble.
+                let (register, index_register) = self.two_registers()?;
+                Address::RegisterScaledIndex(register, index_register, self.i64()?)
+            }
+        })
+    }
+
+    fn instruction(&mut self) -> VmResult<Instruction> {
+        let opcode = self.byte()?;
+        Ok(match opcode {
+            0x00 => Instruction::Exit,
+            0x01 | 0x02 => {
+                let (size, register) = self.size_register()?;
+                let address = self.address()?;
+                if opcode == 0x01 {
+                    Instruction::Load {
+                        size,
+                        register,
+                        address,
+                    }
+                } else {
+                    Instruction::Store {
+                        size,
+                        register,
+                        address,
+                    }
+                }
+            }
+            0x03 => {
+                let (size, register) = self.size_register()?;
+                Instruction::Push { size, register }
+            }
+            0x04 => {
+                let (size, register) = self.size_register()?;
+                Instruction::Pop { size, register }
+            }
+            0x05..=0x10 | 0x44..=0x46 => {
+                let (dest, source) = self.two_registers()?;
+                let op = BINOP_TYPES[extended_index(opcode, 0x05, 0x44, 12)];
+                Instruction::Binop { op, dest, source }
+            }
+            0x11..=0x1C | 0x47..=0x49 => {
+                let dest = self.byte()? & 0x0F;
+                let value = self.i64()?;
+                let op = BINOP_TYPES[extended_index(opcode, 0x11, 0x47, 12)];
+                Instruction::BinopImmediate { op, dest, value }
+            }
+            0x1D..=0x23 | 0x4A..=0x4F => Instruction::Jump {
+                condition: FLAG_CONDITION_TYPES[extended_index(opcode, 0x1D, 0x4A, 7)],
+                address: self.address()?,
+            },
+            0x24..=0x2A | 0x50..=0x55 => Instruction::Set {
+                condition: FLAG_CONDITION_TYPES[extended_index(opcode, 0x24, 0x50, 7)],
+                register: self.register()?,
+            },
+            0x2B..=0x2E => Instruction::Unop {
+                op: UNOP_TYPES[(opcode - 0x2B) as usize],
+                register: self.register()?,
+            },
+            0x2F => Instruction::Call {
+                address: self.address()?,
+            },
+            0x30 => Instruction::Ret,
+            0x31 => Instruction::ReadStdin {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x32 => Instruction::WriteStdout {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x33 => Instruction::Rand {
+                register: self.register()?,
+            },
+            0x34 => {
+                let (size, register) = self.size_register()?;
+                Instruction::SignExtend { size, register }
+            }
+            0x35 => Instruction::Lea {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x36 => Instruction::PushMem {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x37 => Instruction::PopMem {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x38 => Instruction::PeekMem {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x39 => {
+                let (size_register, address_register) = self.two_registers()?;
+                Instruction::Alloc {
+                    size_register,
+                    address_register,
+                }
+            }
+            0x3A => Instruction::Free {
+                register: self.register()?,
+            },
+            0x3B => Instruction::FileOpen {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x3C => Instruction::FileClose {
+                register: self.register()?,
+            },
+            0x3D | 0x3E => {
+                let (file_register, size_register) = self.two_registers()?;
+                let address = self.address()?;
+                if opcode == 0x3D {
+                    Instruction::FileRead {
+                        file_register,
+                        size_register,
+                        address,
+                    }
+                } else {
+                    Instruction::FileWrite {
+                        file_register,
+                        size_register,
+                        address,
+                    }
+                }
+            }
+            0x3F => Instruction::MemCopy {
+                size_register: self.register()?,
+                dest: self.address()?,
+                src: self.address()?,
+            },
+            0x40 => {
+                let (size_register, value_register) = self.two_registers()?;
+                Instruction::MemSet {
+                    size_register,
+                    value_register,
+                    address: self.address()?,
+                }
+            }
+            0x41 => Instruction::SeedRand {
+                register: self.register()?,
+            },
+            0x42 => {
+                let (address_register, size_register) = self.two_registers()?;
+                Instruction::HostCall {
+                    address_register,
+                    size_register,
+                    id: self.i64()? as u64,
+                }
+            }
+            0x43 => {
+                let (size_register, address_register) = self.two_registers()?;
+                Instruction::Realloc {
+                    size_register,
+                    address_register,
+                }
+            }
+            0x56..=0x5A => {
+                let (size, dest) = self.float_size_register(opcode)?;
+                Instruction::FloatBinop {
+                    op: FLOAT_BINOP_TYPES[(opcode - 0x56) as usize],
+                    size,
+                    dest,
+                    source: self.register()?,
+                }
+            }
+            0x5B..=0x5E => {
+                let (size, register) = self.float_size_register(opcode)?;
+                match opcode {
+                    0x5B => Instruction::FloatNeg { size, register },
+                    0x5C => Instruction::IntToFloat { size, register },
+                    0x5D => Instruction::FloatToInt { size, register },
+                    _ => Instruction::FloatConvert { size, register },
+                }
+            }
+            0x5F => Instruction::Abort,
+            0x60 => Instruction::ArgCount {
+                register: self.register()?,
+            },
+            0x61 => Instruction::ArgLength {
+                register: self.register()?,
+            },
+            0x62 => {
+                let (index_register, size_register) = self.two_registers()?;
+                Instruction::ArgRead {
+                    index_register,
+                    size_register,
+                    address: self.address()?,
+                }
+            }
+            0x63 => Instruction::FileOpenMode {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x64 => {
+                let (file_register, position_register) = self.two_registers()?;
+                Instruction::FileSeek {
+                    file_register,
+                    position_register,
+                }
+            }
+            0x65 => Instruction::FileTell {
+                register: self.register()?,
+            },
+            0x66 => Instruction::FileSize {
+                register: self.register()?,
+            },
+            0x67 => Instruction::FileRemove {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            0x68 => Instruction::FileRename {
+                register: self.register()?,
+                from: self.address()?,
+                to: self.address()?,
+            },
+            _ => return Err(VmTrapKind::InvalidOpcode(opcode)),
+        })
+    }
+}
+
+pub fn decode(memory: &Memory, pc: usize) -> VmResult<DecodedInstruction> {
+    let mut decoder = Decoder {
+        memory,
+        pc,
+        offset: 0,
+    };
+    let instruction = decoder.instruction()?;
+    Ok(DecodedInstruction {
+        instruction,
+        length: decoder.offset as u8,
+    })
+}
+
+/// The code section decoded once up front, indexed by the address of each instruction.
+///
+/// The code is decoded with a linear sweep. Writes to the code trap, see
+/// `Memory::writable_range`, so stores never invalidate the decoded instructions. Addresses that
+/// do not start a decoded instruction are decoded from memory when they are executed.
+pub struct DecodedProgram {
+    instructions: Vec<DecodedInstruction>,
+    index: Vec<u32>,
+}
+
+impl DecodedProgram {
+    const NO_INSTRUCTION: u32 = u32::MAX;
+
+    pub fn new(memory: &Memory, program_size: usize) -> DecodedProgram {
+        let mut instructions = Vec::new();
+        let mut index = vec![Self::NO_INSTRUCTION; program_size];
+        let mut pc = 0;
+        while pc < program_size {
+            match decode(memory, pc) {
+                Ok(decoded) => {
+                    index[pc] = instructions.len() as u32;
+                    pc += decoded.length as usize;
+                    instructions.push(decoded);
+                }
+                Err(_) => pc += 1,
+            }
+        }
+        DecodedProgram {
+            instructions,
+            index,
+        }
+    }
+
+    /// The size of the decoded code in bytes.
+    pub fn size(&self) -> usize {
+        self.index.len()
+    }
+
+    #[inline(always)]
+    pub fn get(&self, pc: u64) -> Option<&DecodedInstruction> {
+        match self.index.get(pc as usize) {
+            Some(&index) if index != Self::NO_INSTRUCTION => {
+                Some(&self.instructions[index as usize])
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The index of the binop or condition encoded by `opcode`, which lies either in the range
+/// starting at `base` or in the range added later starting at `extended_base`, which continues
+/// after the first `base_count` entries.
+fn extended_index(opcode: u8, base: u8, extended_base: u8, base_count: u8) -> usize {
+    if opcode >= extended_base {
+        (opcode - extended_base + base_count) as usize
+    } else {
+        (opcode - base) as usize
+    }
+}
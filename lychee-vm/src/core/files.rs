@@ -0,0 +1,713 @@
+use crate::core::snapshot::FileSnapshot;
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::{
+    FilePolicy, FILE_ERROR_DENIED, FILE_ERROR_EXISTS, FILE_ERROR_INVALID, FILE_ERROR_IO,
+    FILE_ERROR_NOT_FOUND, FILE_MODE_APPEND, FILE_MODE_CREATE_NEW, FILE_MODE_READ,
+    FILE_MODE_READ_WRITE, FILE_MODE_WRITE,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// A file opened by the file opcodes. Files are seekable so their position can be restored when
+/// resuming from a snapshot.
+pub trait VmFile: Read + Write + Seek {
+    /// The current position in the file, without moving it.
+    fn position(&self) -> std::io::Result<u64>;
+
+    /// Called when the program closes the file.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl VmFile for File {
+    fn position(&self) -> std::io::Result<u64> {
+        (&*self).stream_position()
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// How a program opens a file, one of the `FILE_MODE_*` codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMode {
+    ReadWrite,
+    Read,
+    Write,
+    Append,
+    CreateNew,
+}
+
+impl FileMode {
+    pub fn from_code(code: u64) -> Option<FileMode> {
+        match code {
+            FILE_MODE_READ_WRITE => Some(FileMode::ReadWrite),
+            FILE_MODE_READ => Some(FileMode::Read),
+            FILE_MODE_WRITE => Some(FileMode::Write),
+            FILE_MODE_APPEND => Some(FileMode::Append),
+            FILE_MODE_CREATE_NEW => Some(FileMode::CreateNew),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u64 {
+        match self {
+            FileMode::ReadWrite => FILE_MODE_READ_WRITE,
+            FileMode::Read => FILE_MODE_READ,
+            FileMode::Write => FILE_MODE_WRITE,
+            FileMode::Append => FILE_MODE_APPEND,
+            FileMode::CreateNew => FILE_MODE_CREATE_NEW,
+        }
+    }
+
+    /// The mode to reopen a file with when resuming from a snapshot. Truncating and exclusive
+    /// creation only happen when the file is first opened.
+    fn reopen(self) -> FileMode {
+        match self {
+            FileMode::Write | FileMode::CreateNew => FileMode::ReadWrite,
+            mode => mode,
+        }
+    }
+}
+
+/// Opens, removes and renames the files used by the file opcodes. Paths are given as by the
+/// program, and errors are reported to it as one of the `FILE_ERROR_*` codes.
+pub trait FileSystem {
+    /// Opens the file at `path`, returning it and whether the program may write to it.
+    fn open(&mut self, path: &str, mode: FileMode) -> std::io::Result<(Box<dyn VmFile>, bool)>;
+
+    fn remove(&mut self, path: &str) -> std::io::Result<()>;
+
+    fn rename(&mut self, from: &str, to: &str) -> std::io::Result<()>;
+}
+
+struct OpenFile {
+    path: String,
+    file: Box<dyn VmFile>,
+    mode: FileMode,
+    writable: bool,
+}
+
+/// The files opened by a program.
+///
+/// Failures the guest can cause or recover from, such as opening a denied path or writing to a
+/// read-only file, are reported to it as one of the negative `FILE_ERROR_*` codes. Reading from or
+/// writing to such a code returns it again, and closing it does nothing, so programs that do not
+/// check the result of an open keep running. Only using a handle that was never open traps.
+pub struct FileTable {
+    files: Vec<Option<OpenFile>>,
+    pub(crate) file_system: Box<dyn FileSystem>,
+}
+
+impl FileTable {
+    pub fn new(file_system: Box<dyn FileSystem>) -> FileTable {
+        FileTable {
+            files: Vec::new(),
+            file_system,
+        }
+    }
+
+    /// Opens the file at the guest path `path`, returning its handle or an error code.
+    pub fn open(&mut self, path: &str, mode: FileMode) -> i64 {
+        match self.file_system.open(path, mode) {
+            Ok((file, writable)) => {
+                let path = path.to_string();
+                self.files.push(Some(OpenFile {
+                    path,
+                    file,
+                    mode,
+                    writable,
+                }));
+                self.files.len() as i64 - 1
+            }
+            Err(error) => error_code(&error),
+        }
+    }
+
+    pub fn close(&mut self, file_id: u64) -> VmResult<()> {
+        if is_error_code(file_id) {
+            return Ok(());
+        }
+        let file = self.get(file_id)?;
+        file.file.sync()?;
+        self.files[file_id as usize] = None;
+        while self.files.last().is_some_and(Option::is_none) {
+            self.files.pop();
+        }
+        Ok(())
+    }
+
+    /// Reads into `buffer`, returning the number of bytes read, which is 0 at the end of the
+    /// file, or an error code.
+    pub fn read(&mut self, file_id: u64, buffer: &mut [u8]) -> VmResult<i64> {
+        if is_error_code(file_id) {
+            return Ok(file_id as i64);
+        }
+        let result = self.get(file_id)?.file.read(buffer);
+        Ok(result.map_or_else(|error| error_code(&error), |read| read as i64))
+    }
+
+    /// Writes all of `buffer`, returning the number of bytes written or an error code.
+    pub fn write(&mut self, file_id: u64, buffer: &[u8]) -> VmResult<i64> {
+        if is_error_code(file_id) {
+            return Ok(file_id as i64);
+        }
+        let file = self.get(file_id)?;
+        if !file.writable {
+            return Ok(FILE_ERROR_DENIED);
+        }
+        let result = file.file.write_all(buffer);
+        Ok(result.map_or_else(|error| error_code(&error), |_| buffer.len() as i64))
+    }
+
+    /// Moves to `position` bytes from the start of the file, returning the new position or an
+    /// error code. Moving past the end is allowed, and writing there fills the gap with zeros.
+    pub fn seek(&mut self, file_id: u64, position: i64) -> VmResult<i64> {
+        if is_error_code(file_id) {
+            return Ok(file_id as i64);
+        }
+        let file = self.get(file_id)?;
+        if position < 0 {
+            return Ok(FILE_ERROR_INVALID);
+        }
+        let result = file.file.seek(SeekFrom::Start(position as u64));
+        Ok(result.map_or_else(|error| error_code(&error), |position| position as i64))
+    }
+
+    /// Returns the position in the file or an error code.
+    pub fn tell(&mut self, file_id: u64) -> VmResult<i64> {
+        if is_error_code(file_id) {
+            return Ok(file_id as i64);
+        }
+        let result = self.get(file_id)?.file.stream_position();
+        Ok(result.map_or_else(|error| error_code(&error), |position| position as i64))
+    }
+
+    /// Returns the length of the file in bytes or an error code, keeping the position.
+    pub fn size(&mut self, file_id: u64) -> VmResult<i64> {
+        if is_error_code(file_id) {
+            return Ok(file_id as i64);
+        }
+        let file = &mut self.get(file_id)?.file;
+        let result = file.stream_position().and_then(|position| {
+            let size = file.seek(SeekFrom::End(0))?;
+            file.seek(SeekFrom::Start(position))?;
+            Ok(size)
+        });
+        Ok(result.map_or_else(|error| error_code(&error), |size| size as i64))
+    }
+
+    /// Removes the file at the guest path `path`, returning 0 or an error code.
+    pub fn remove(&mut self, path: &str) -> i64 {
+        let result = self.file_system.remove(path);
+        result.map_or_else(|error| error_code(&error), |_| 0)
+    }
+
+    /// Renames the file at the guest path `from` to `to`, replacing any file there, and
+    /// returns 0 or an error code.
+    pub fn rename(&mut self, from: &str, to: &str) -> i64 {
+        let result = self.file_system.rename(from, to);
+        result.map_or_else(|error| error_code(&error), |_| 0)
+    }
+
+    /// Records the path and position of every open file, keeping the handles of closed files.
+    pub fn snapshot(&self) -> VmResult<Vec<Option<FileSnapshot>>> {
+        let mut files = Vec::with_capacity(self.files.len());
+        for file in &self.files {
+            files.push(match file {
+                Some(file) => Some(FileSnapshot {
+                    path: file.path.clone(),
+                    mode: file.mode,
+                    offset: file.file.position()?,
+                }),
+                None => None,
+            });
+        }
+        Ok(files)
+    }
+
+    /// Reopens the files recorded by `snapshot` under the same handles. Files opened for writing
+    /// or created are reopened for reading and writing, so they are not truncated again.
+    pub fn restore(&mut self, files: &[Option<FileSnapshot>]) -> VmResult<()> {
+        self.files.clear();
+        for file in files {
+            let Some(file) = file else {
+                self.files.push(None);
+                continue;
+            };
+            let mode = file.mode.reopen();
+            let (mut opened, writable) =
+                (self.file_system.open(&file.path, mode)).map_err(|error| {
+                    VmTrapKind::HostIo(format!("failed to reopen {}: {}", file.path, error))
+                })?;
+            opened.seek(SeekFrom::Start(file.offset))?;
+            self.files.push(Some(OpenFile {
+                path: file.path.clone(),
+                file: opened,
+                mode: file.mode,
+                writable,
+            }));
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, file_id: u64) -> VmResult<&mut OpenFile> {
+        self.files
+            .get_mut(file_id as usize)
+            .and_then(Option::as_mut)
+            .ok_or(VmTrapKind::BadFileHandle { file_id })
+    }
+}
+
+/// The host file system, restricted by a `FilePolicy`.
+pub struct HostFileSystem {
+    deny_all: bool,
+    /// The canonical root directory, if guest paths are confined to one.
+    root: Option<PathBuf>,
+    /// The canonical allowlist entries and whether they grant write access.
+    allowlist: Vec<(PathBuf, bool)>,
+}
+
+impl HostFileSystem {
+    pub fn new(policy: &FilePolicy) -> HostFileSystem {
+        let root = policy
+            .root
+            .as_ref()
+            .map(|root| root.canonicalize().unwrap_or_else(|_| root.clone()));
+        let mut file_system = HostFileSystem {
+            deny_all: policy.deny_all,
+            root,
+            allowlist: Vec::new(),
+        };
+        let entries = (policy.read_only.iter().map(|path| (path, false)))
+            .chain(policy.read_write.iter().map(|path| (path, true)));
+        file_system.allowlist = entries
+            .filter_map(|(path, writable)| Some((file_system.host_path(path)?, writable)))
+            .collect();
+        // An allowlist whose entries all escape the root must still deny everything.
+        file_system.deny_all |= file_system.allowlist.is_empty()
+            && !(policy.read_only.is_empty() && policy.read_write.is_empty());
+        file_system
+    }
+
+    /// Decides where a guest path points on the host and whether it may be written, or `None`
+    /// if it may not be accessed at all.
+    fn resolve(&self, path: &Path) -> Option<(PathBuf, bool)> {
+        if self.deny_all {
+            return None;
+        }
+        let path = self.host_path(path)?;
+        if self.allowlist.is_empty() {
+            return Some((path, true));
+        }
+        let (_, writable) = self
+            .allowlist
+            .iter()
+            .filter(|(entry, _)| path.starts_with(entry))
+            .max_by_key(|(entry, _)| entry.components().count())?;
+        Some((path, *writable))
+    }
+
+    /// Turns a guest path into a canonical host path, or `None` if it escapes the root.
+    fn host_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = match &self.root {
+            Some(root) => {
+                let mut relative = PathBuf::new();
+                for component in path.components() {
+                    match component {
+                        Component::Normal(name) => relative.push(name),
+                        Component::ParentDir => {
+                            if !relative.pop() {
+                                return None;
+                            }
+                        }
+                        Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+                    }
+                }
+                root.join(relative)
+            }
+            None => std::path::absolute(path).ok()?,
+        };
+        let path = canonicalize_existing(&path)?;
+        match &self.root {
+            Some(root) if !path.starts_with(root) => None,
+            _ => Some(path),
+        }
+    }
+
+    /// Resolves a guest path that the program wants to modify.
+    fn resolve_writable(&self, path: &str) -> std::io::Result<PathBuf> {
+        match self.resolve(Path::new(path)) {
+            Some((path, true)) => Ok(path),
+            _ => Err(ErrorKind::PermissionDenied.into()),
+        }
+    }
+}
+
+impl FileSystem for HostFileSystem {
+    fn open(&mut self, path: &str, mode: FileMode) -> std::io::Result<(Box<dyn VmFile>, bool)> {
+        let Some((path, writable)) = self.resolve(Path::new(path)) else {
+            return Err(ErrorKind::PermissionDenied.into());
+        };
+        let mut options = OpenOptions::new();
+        let writable = match mode {
+            FileMode::ReadWrite => {
+                options.read(true).write(writable).create(writable);
+                writable
+            }
+            FileMode::Read => {
+                options.read(true);
+                false
+            }
+            _ if !writable => return Err(ErrorKind::PermissionDenied.into()),
+            FileMode::Write => {
+                options.write(true).create(true).truncate(true);
+                true
+            }
+            FileMode::Append => {
+                options.append(true).create(true);
+                true
+            }
+            FileMode::CreateNew => {
+                options.read(true).write(true).create_new(true);
+                true
+            }
+        };
+        Ok((Box::new(options.open(path)?), writable))
+    }
+
+    fn remove(&mut self, path: &str) -> std::io::Result<()> {
+        std::fs::remove_file(self.resolve_writable(path)?)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> std::io::Result<()> {
+        std::fs::rename(self.resolve_writable(from)?, self.resolve_writable(to)?)
+    }
+}
+
+/// A file system kept in memory, which creates files when they are first opened for writing.
+/// Clones share the same files, so the contents can be inspected after the program ran.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: Rc<RefCell<HashMap<String, MemoryFileData>>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> MemoryFileSystem {
+        MemoryFileSystem::default()
+    }
+
+    pub fn insert(&self, path: &str, contents: Vec<u8>) {
+        let file = Rc::new(RefCell::new(contents));
+        self.files.borrow_mut().insert(path.to_string(), file);
+    }
+
+    pub fn contents(&self, path: &str) -> Option<Vec<u8>> {
+        let files = self.files.borrow();
+        files.get(path).map(|file| file.borrow().clone())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn open(&mut self, path: &str, mode: FileMode) -> std::io::Result<(Box<dyn VmFile>, bool)> {
+        let mut files = self.files.borrow_mut();
+        let exists = files.contains_key(path);
+        if mode == FileMode::Read && !exists {
+            return Err(ErrorKind::NotFound.into());
+        }
+        if mode == FileMode::CreateNew && exists {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        let data = files.entry(path.to_string()).or_default().clone();
+        if mode == FileMode::Write {
+            data.borrow_mut().clear();
+        }
+        let file = MemoryFile {
+            data,
+            position: 0,
+            append: mode == FileMode::Append,
+        };
+        Ok((Box::new(file), mode != FileMode::Read))
+    }
+
+    fn remove(&mut self, path: &str) -> std::io::Result<()> {
+        let removed = self.files.borrow_mut().remove(path);
+        removed.map(|_| ()).ok_or(ErrorKind::NotFound.into())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> std::io::Result<()> {
+        let mut files = self.files.borrow_mut();
+        let file = files.remove(from).ok_or(ErrorKind::NotFound)?;
+        files.insert(to.to_string(), file);
+        Ok(())
+    }
+}
+
+type MemoryFileData = Rc<RefCell<Vec<u8>>>;
+
+/// The largest a file in a `MemoryFileSystem` may grow, so seeking far past the end and writing
+/// there fails instead of allocating the gap.
+const MEMORY_FILE_MAX_SIZE: usize = 1 << 28;
+
+struct MemoryFile {
+    data: MemoryFileData,
+    position: usize,
+    /// Whether every write goes to the end of the file.
+    append: bool,
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.data.borrow();
+        let remaining = data.get(self.position..).unwrap_or_default();
+        let read = remaining.len().min(buffer.len());
+        buffer[..read].copy_from_slice(&remaining[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let mut data = self.data.borrow_mut();
+        if self.append {
+            self.position = data.len();
+        }
+        let end = (self.position.checked_add(buffer.len()))
+            .filter(|end| *end <= MEMORY_FILE_MAX_SIZE)
+            .ok_or(ErrorKind::FileTooLarge)?;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[self.position..end].copy_from_slice(buffer);
+        self.position = end;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.data.borrow().len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => (self.position as u64).checked_add_signed(offset),
+        }
+        .ok_or(ErrorKind::InvalidInput)?;
+        self.position = position as usize;
+        Ok(position)
+    }
+}
+
+impl VmFile for MemoryFile {
+    fn position(&self) -> std::io::Result<u64> {
+        Ok(self.position as u64)
+    }
+}
+
+/// Canonicalizes the longest existing ancestor of `path`, so symbolic links are followed for the
+/// part of the path that exists, and normalizes the rest. Returns `None` for paths through a
+/// dangling symbolic link, since creating the file would follow it to wherever it points.
+fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    let canonical = loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            break canonical;
+        }
+        if existing.symlink_metadata().is_ok() {
+            return None;
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(component)) => {
+                missing.push(component);
+                existing = parent;
+            }
+            _ => break PathBuf::new(),
+        }
+    };
+    let mut result = canonical;
+    for component in missing.into_iter().rev() {
+        match component {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::Normal(name) => result.push(name),
+            _ => {}
+        }
+    }
+    Some(result)
+}
+
+fn is_error_code(file_id: u64) -> bool {
+    [
+        FILE_ERROR_DENIED,
+        FILE_ERROR_NOT_FOUND,
+        FILE_ERROR_IO,
+        FILE_ERROR_EXISTS,
+        FILE_ERROR_INVALID,
+    ]
+    .contains(&(file_id as i64))
+}
+
+fn error_code(error: &std::io::Error) -> i64 {
+    match error.kind() {
+        ErrorKind::NotFound => FILE_ERROR_NOT_FOUND,
+        ErrorKind::PermissionDenied => FILE_ERROR_DENIED,
+        ErrorKind::AlreadyExists => FILE_ERROR_EXISTS,
+        ErrorKind::InvalidInput => FILE_ERROR_INVALID,
+        _ => FILE_ERROR_IO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DENIED: Result<bool, ErrorKind> = Err(ErrorKind::PermissionDenied);
+
+    /// Creates an empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let name = format!("lychee-files-{}-{}", std::process::id(), name);
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Opens `path`, returning whether it may be written.
+    fn open(
+        files: &mut HostFileSystem,
+        path: impl AsRef<Path>,
+        mode: FileMode,
+    ) -> Result<bool, ErrorKind> {
+        let path = path.as_ref().to_str().unwrap();
+        let result = files.open(path, mode);
+        result
+            .map(|(_, writable)| writable)
+            .map_err(|error| error.kind())
+    }
+
+    #[test]
+    fn root_confines_paths() {
+        let dir = test_dir("root");
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        let mut files = HostFileSystem::new(&FilePolicy {
+            root: Some(root.clone()),
+            ..FilePolicy::default()
+        });
+
+        assert_eq!(open(&mut files, "a.txt", FileMode::Write), Ok(true));
+        assert!(root.join("a.txt").exists());
+        // Absolute paths are taken relative to the root.
+        assert_eq!(open(&mut files, "/b.txt", FileMode::Write), Ok(true));
+        assert!(root.join("b.txt").exists());
+        assert_eq!(open(&mut files, "sub/../c.txt", FileMode::Write), Ok(true));
+
+        for path in ["../a.txt", "sub/../../a.txt", "/../a.txt"] {
+            assert_eq!(open(&mut files, path, FileMode::Write), DENIED, "{}", path);
+        }
+        let removed = files.remove("../root/a.txt").map(|_| true);
+        assert_eq!(removed.map_err(|error| error.kind()), DENIED);
+        let renamed = files.rename("a.txt", "../a.txt").map(|_| true);
+        assert_eq!(renamed.map_err(|error| error.kind()), DENIED);
+        assert!(!dir.join("a.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn root_rejects_symbolic_links_out_of_it() {
+        let dir = test_dir("links");
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(dir.join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), root.join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing.txt"), root.join("dangling.txt")).unwrap();
+        let mut files = HostFileSystem::new(&FilePolicy {
+            root: Some(root),
+            ..FilePolicy::default()
+        });
+
+        for path in ["outside/a.txt", "dangling.txt"] {
+            for mode in [FileMode::ReadWrite, FileMode::Write, FileMode::Append] {
+                assert_eq!(open(&mut files, path, mode), DENIED, "{}", path);
+            }
+        }
+        assert!(!dir.join("outside/a.txt").exists());
+        assert!(!dir.join("missing.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn allowlist_picks_the_longest_entry() {
+        let dir = test_dir("allowlist");
+        std::fs::create_dir_all(dir.join("data/read")).unwrap();
+        std::fs::write(dir.join("data/read/a.txt"), "a").unwrap();
+        std::fs::write(dir.join("other.txt"), "b").unwrap();
+        let mut files = HostFileSystem::new(&FilePolicy {
+            read_only: vec![dir.join("data/read")],
+            read_write: vec![dir.join("data")],
+            ..FilePolicy::default()
+        });
+
+        assert_eq!(
+            open(&mut files, dir.join("data/b.txt"), FileMode::Write),
+            Ok(true)
+        );
+        let read_only = dir.join("data/read/a.txt");
+        assert_eq!(open(&mut files, &read_only, FileMode::ReadWrite), Ok(false));
+        assert_eq!(open(&mut files, &read_only, FileMode::Write), DENIED);
+        let parent = dir.join("data/read/../c.txt");
+        assert_eq!(open(&mut files, parent, FileMode::Write), Ok(true));
+        let escaped = dir.join("data/../other.txt");
+        assert_eq!(open(&mut files, escaped, FileMode::Read), DENIED);
+        assert_eq!(
+            open(&mut files, dir.join("other.txt"), FileMode::Read),
+            DENIED
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn allowlist_outside_the_root_denies_everything() {
+        let dir = test_dir("allowlist-root");
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        let mut files = HostFileSystem::new(&FilePolicy {
+            root: Some(root),
+            read_write: vec![PathBuf::from("../")],
+            ..FilePolicy::default()
+        });
+        assert_eq!(open(&mut files, "a.txt", FileMode::Write), DENIED);
+        assert_eq!(open(&mut files, "../a.txt", FileMode::Write), DENIED);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_files_fill_gaps_up_to_a_limit() {
+        let file_system = MemoryFileSystem::new();
+        let mut files = FileTable::new(Box::new(file_system.clone()));
+        let file = files.open("a.txt", FileMode::Write) as u64;
+        assert_eq!(files.write(file, b"ab").unwrap(), 2);
+        assert_eq!(files.seek(file, 4).unwrap(), 4);
+        assert_eq!(files.write(file, b"c").unwrap(), 1);
+        assert_eq!(file_system.contents("a.txt").unwrap(), b"ab\0\0c");
+
+        for position in [i64::MAX, MEMORY_FILE_MAX_SIZE as i64] {
+            assert_eq!(files.seek(file, position).unwrap(), position);
+            assert_eq!(files.write(file, b"d").unwrap(), FILE_ERROR_IO);
+        }
+        assert_eq!(files.size(file).unwrap(), 5);
+    }
+}
@@ -1,139 +1,686 @@
+use crate::core::layout::MemoryLayout;
 use crate::core::memory::Memory;
+use crate::core::trap::{VmResult, VmTrapKind};
 use lychee_compiler::{DATA_SIZE_32, DATA_SIZE_64};
+use std::fmt::Display;
 
+/// Number of segregated free lists. Class `n` holds the free blocks whose size has its highest
+/// bit at `n + 5`, and the last class holds all larger blocks.
+const SIZE_CLASSES: usize = 28;
+/// Marks the end of a list of blocks.
+const NO_BLOCK: i64 = -1;
+/// Payload sizes are rounded up to this, which keeps payloads aligned.
+const PAYLOAD_ALIGNMENT: u64 = 8;
+/// Offsets of the free list links in the header of a free block.
+const FREE_NEXT: usize = 16;
+const FREE_PREV: usize = 20;
+
+/// Statistics about the allocations of a program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub allocations: u64,
+    pub frees: u64,
+    /// Bytes in allocated blocks, excluding their headers.
+    pub live_bytes: u64,
+    pub peak_live_bytes: u64,
+    /// Bytes in free blocks, including their headers.
+    pub free_bytes: u64,
+    pub largest_free_block: u64,
+    /// The current size of the heap, which grows when it runs out of free blocks.
+    pub heap_size: u64,
+}
+
+impl HeapStats {
+    /// The share of the free memory that cannot be used for an allocation of all of it, from 0
+    /// for a single free block to almost 1 for many small ones.
+    pub fn fragmentation(&self) -> f64 {
+        if self.free_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free_block as f64 / self.free_bytes as f64
+    }
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Heap statistics:")?;
+        writeln!(f, "  allocations:        {}", self.allocations)?;
+        writeln!(f, "  frees:              {}", self.frees)?;
+        writeln!(f, "  live bytes:         {}", self.live_bytes)?;
+        writeln!(f, "  peak live bytes:    {}", self.peak_live_bytes)?;
+        writeln!(f, "  free bytes:         {}", self.free_bytes)?;
+        writeln!(f, "  largest free block: {}", self.largest_free_block)?;
+        writeln!(f, "  heap size:          {}", self.heap_size)?;
+        writeln!(
+            f,
+            "  fragmentation:      {:.1}%",
+            self.fragmentation() * 100.0
+        )
+    }
+}
+
+/// The allocator behind the `alloc` and `free` opcodes.
+///
+/// The heap is a sequence of blocks, each starting with a 24 byte header: the block size as
+/// 8 bytes, negated while the block is allocated, followed by the addresses of the next and
+/// the previous block in memory, and of the next and the previous block in its free list, as
+/// 4 bytes each. Free blocks are kept in segregated free lists by size class, so `malloc` only
+/// searches the blocks of one class, and `free` merges with the neighbouring blocks in
+/// constant time. The links live in the header, so freeing a block leaves its payload intact.
+///
+/// The heap ends the memory. When no free block is large enough, it grows by extending the
+/// memory, at least doubling in size, until it reaches `max_size`.
 #[derive(Debug)]
 pub struct Heap {
     pub offset: usize,
     pub size: usize,
+    pub max_size: usize,
     pub header_size: usize,
+    free_lists: [i64; SIZE_CLASSES],
+    /// The block at the end of the heap, which a new block is linked after when it grows.
+    last_block: usize,
+    stats: HeapStats,
 }
 
-/*pub struct Block {
-    pub size: u64,
-    pub next: u32,
-    pub prev: u32,
-}*/
-
 impl Heap {
-    pub fn new(memory: &mut Memory, offset: usize, size: usize) -> Heap {
-        memory.write_i64_le(offset, size as i64, DATA_SIZE_64);
-        memory.write_i64_le(offset + 8, -1, DATA_SIZE_32);
-        memory.write_i64_le(offset + 12, -1, DATA_SIZE_32);
+    pub fn new(memory: &mut Memory, layout: &MemoryLayout) -> VmResult<Heap> {
+        let mut heap = Heap::empty(layout);
+        let (offset, size) = (heap.offset, heap.size);
+        heap.write_header(memory, offset, size as i64, NO_BLOCK, NO_BLOCK)?;
+        heap.push_free(memory, offset, size as u64)?;
+        Ok(heap)
+    }
+
+    /// Recreates the allocator state for a heap whose blocks are already in memory, as when
+    /// resuming from a snapshot, keeping the counters from `stats`. The free lists are rebuilt,
+    /// which only rewrites the headers of free blocks.
+    pub fn restore(memory: &mut Memory, layout: &MemoryLayout, stats: HeapStats) -> VmResult<Heap> {
+        let mut heap = Heap::empty(layout);
+        heap.stats.allocations = stats.allocations;
+        heap.stats.frees = stats.frees;
+        heap.stats.peak_live_bytes = stats.peak_live_bytes;
+        let mut current = heap.offset as i64;
+        for _ in 0..=heap.max_blocks() {
+            if current == NO_BLOCK {
+                return Ok(heap);
+            }
+            let address = current as usize;
+            heap.last_block = address;
+            let block_size = heap.read_size(memory, address)?;
+            if block_size < 0 {
+                heap.stats.live_bytes += block_size.unsigned_abs() - heap.header_size as u64;
+            } else {
+                heap.push_free(memory, address, block_size as u64)?;
+            }
+            current = heap.read_link(memory, address + 8)?;
+        }
+        Err(VmTrapKind::HeapCorrupted {
+            address: current as u64,
+        })
+    }
+
+    fn empty(layout: &MemoryLayout) -> Heap {
         Heap {
-            offset,
-            size,
-            header_size: 16,
+            offset: layout.heap_offset,
+            size: layout.heap_size,
+            max_size: layout.max_memory_size - layout.heap_offset,
+            header_size: 24,
+            free_lists: [NO_BLOCK; SIZE_CLASSES],
+            last_block: layout.heap_offset,
+            stats: HeapStats::default(),
         }
     }
-    pub fn malloc(&mut self, memory: &mut Memory, size: u64) -> Option<usize> {
-        let block_size = size + self.header_size as u64;
-        let block_address = self.find_free_block(memory, block_size)?;
-        self.split_block(memory, block_address, block_size);
-        self.toggle_free(memory, block_address);
-        Some(block_address + self.header_size)
+
+    pub fn malloc(&mut self, memory: &mut Memory, size: u64) -> VmResult<usize> {
+        let block_size = self.block_size(size)?;
+        let mut block_address = self.find_free_block(memory, block_size)?;
+        if block_address.is_none() && self.grow(memory, block_size)? {
+            block_address = self.find_free_block(memory, block_size)?;
+        }
+        let block_address = block_address.ok_or(VmTrapKind::HeapExhausted { requested: size })?;
+        let available = self.read_size(memory, block_address)? as u64;
+        self.remove_free(memory, block_address, available)?;
+        let block_size = self.split_block(memory, block_address, available, block_size)?;
+        memory.write_i64_le(block_address, -(block_size as i64), DATA_SIZE_64)?;
+
+        self.stats.allocations += 1;
+        self.stats.live_bytes += block_size - self.header_size as u64;
+        self.stats.peak_live_bytes = self.stats.peak_live_bytes.max(self.stats.live_bytes);
+        Ok(block_address + self.header_size)
     }
 
-    pub fn free(&mut self, memory: &mut Memory, address: usize) {
+    pub fn free(&mut self, memory: &mut Memory, address: usize) -> VmResult<()> {
+        if !self.is_used_block(memory, address)? {
+            return Err(VmTrapKind::InvalidFree {
+                address: address as u64,
+            });
+        }
         let block_address = address - self.header_size;
-        self.toggle_free(memory, block_address);
-        self.merge_blocks(memory, block_address);
+        let block_size = self.read_size(memory, block_address)?.unsigned_abs();
+        self.stats.frees += 1;
+        self.stats.live_bytes -= block_size - self.header_size as u64;
+        memory.write_i64_le(block_address, block_size as i64, DATA_SIZE_64)?;
+        self.merge_blocks(memory, block_address)
     }
 
-    fn find_free_block(&self, memory: &Memory, size: u64) -> Option<usize> {
-        let mut current = self.offset;
-        loop {
-            let block_size = memory.read_i64_le(current, DATA_SIZE_64);
-            if block_size >= size as i64 {
-                return Some(current);
+    /// Resizes the block at `address` to `size` bytes, keeping its contents up to the smaller of
+    /// both sizes. The block grows in place if the block after it is free and large enough, and
+    /// is only moved to a new block otherwise. A null address allocates a new block.
+    pub fn realloc(&mut self, memory: &mut Memory, address: usize, size: u64) -> VmResult<usize> {
+        if address == 0 {
+            return self.malloc(memory, size);
+        }
+        if !self.is_used_block(memory, address)? {
+            return Err(VmTrapKind::InvalidRealloc {
+                address: address as u64,
+            });
+        }
+        let block_size = self.block_size(size)?;
+        let block_address = address - self.header_size;
+        let old_size = self.read_size(memory, block_address)?.unsigned_abs();
+
+        let mut available = old_size;
+        let next = self.read_link(memory, block_address + 8)?;
+        if available < block_size && next != NO_BLOCK {
+            let next_size = self.read_size(memory, next as usize)?;
+            if next_size > 0 && available + next_size as u64 >= block_size {
+                self.remove_free(memory, next as usize, next_size as u64)?;
+                self.unlink_next(memory, block_address, next as usize)?;
+                available += next_size as u64;
             }
-            let next = memory.read_i64_le(current + 8, DATA_SIZE_32);
-            if next == -1 {
-                return None;
+        }
+
+        if available < block_size {
+            let new_address = self.malloc(memory, size)?;
+            let payload_size = old_size as usize - self.header_size;
+            memory.memory_copy(address, new_address, payload_size)?;
+            self.free(memory, address)?;
+            // A moved block counts as a single allocation.
+            self.stats.allocations -= 1;
+            self.stats.frees -= 1;
+            return Ok(new_address);
+        }
+
+        let new_size = self.split_block(memory, block_address, available, block_size)?;
+        memory.write_i64_le(block_address, -(new_size as i64), DATA_SIZE_64)?;
+        if new_size < available {
+            // The rest was added to the free lists, but may border another free block.
+            let rest = block_address + new_size as usize;
+            self.remove_free(memory, rest, available - new_size)?;
+            self.merge_blocks(memory, rest)?;
+        }
+        self.stats.live_bytes = self.stats.live_bytes + new_size - old_size;
+        self.stats.peak_live_bytes = self.stats.peak_live_bytes.max(self.stats.live_bytes);
+        Ok(address)
+    }
+
+    /// Returns the allocation statistics, walking the free lists for the free memory.
+    pub fn stats(&self, memory: &Memory) -> HeapStats {
+        let mut stats = self.stats;
+        stats.heap_size = self.size as u64;
+        // Stops at the number of blocks that fit in the heap, in case the lists were corrupted.
+        let mut remaining = self.max_blocks();
+        for &head in &self.free_lists {
+            let mut current = head;
+            while current != NO_BLOCK && remaining > 0 {
+                remaining -= 1;
+                let address = current as usize;
+                let (Ok(block_size), Ok(next)) = (
+                    memory.read_i64_le(address, DATA_SIZE_64),
+                    memory.read_i64_le(address + FREE_NEXT, DATA_SIZE_32),
+                ) else {
+                    break;
+                };
+                stats.free_bytes += block_size as u64;
+                stats.largest_free_block = stats.largest_free_block.max(block_size as u64);
+                current = next;
             }
-            current = next as usize;
         }
+        stats
+    }
+
+    /// Extends the heap so that its last block can hold `block_size` bytes, returning false if
+    /// that would exceed the maximum size.
+    fn grow(&mut self, memory: &mut Memory, block_size: u64) -> VmResult<bool> {
+        let last_size = self.read_size(memory, self.last_block)?;
+        let minimum_block_size = self.header_size + PAYLOAD_ALIGNMENT as usize;
+        let needed = (block_size as usize)
+            .saturating_sub(last_size.max(0) as usize)
+            .max(minimum_block_size);
+        let available = self.max_size - self.size;
+        if needed > available {
+            return Ok(false);
+        }
+        let growth = needed.max(self.size).min(available);
+        let block_address = self.offset + self.size;
+        memory.data.resize(memory.data.len() + growth, 0);
+        self.size += growth;
+        self.write_header(
+            memory,
+            block_address,
+            growth as i64,
+            NO_BLOCK,
+            self.last_block as i64,
+        )?;
+        memory.write_i64_le(self.last_block + 8, block_address as i64, DATA_SIZE_32)?;
+        self.last_block = block_address;
+        self.merge_blocks(memory, block_address)?;
+        Ok(true)
+    }
+
+    /// The size of the block holding a payload of `size` bytes, including its header.
+    fn block_size(&self, size: u64) -> VmResult<u64> {
+        size.max(PAYLOAD_ALIGNMENT)
+            .checked_next_multiple_of(PAYLOAD_ALIGNMENT)
+            .and_then(|size| size.checked_add(self.header_size as u64))
+            .filter(|block_size| *block_size <= self.max_size as u64)
+            .ok_or(VmTrapKind::HeapExhausted { requested: size })
+    }
+
+    /// Checks that `address` was returned by `malloc` and has not been freed since, by
+    /// validating the block header and its links to the neighbouring blocks.
+    fn is_used_block(&self, memory: &Memory, address: usize) -> VmResult<bool> {
+        if address < self.offset + self.header_size || address >= self.offset + self.size {
+            return Ok(false);
+        }
+        let block_address = address - self.header_size;
+        if memory.read_i64_le(block_address, DATA_SIZE_64)? >= 0 {
+            return Ok(false);
+        }
+        let next = memory.read_i64_le(block_address + 8, DATA_SIZE_32)?;
+        if next != NO_BLOCK && !self.is_block_start(next)
+            || self.read_size(memory, block_address).is_err()
+        {
+            return Ok(false);
+        }
+        let prev = memory.read_i64_le(block_address + 12, DATA_SIZE_32)?;
+        let prev_valid = if prev == NO_BLOCK {
+            block_address == self.offset
+        } else {
+            self.is_block_start(prev)
+                && memory.read_i64_le(prev as usize + 8, DATA_SIZE_32)? == block_address as i64
+        };
+        let next_valid = next == NO_BLOCK
+            || self.is_block_start(next)
+                && memory.read_i64_le(next as usize + 12, DATA_SIZE_32)? == block_address as i64;
+        Ok(prev_valid && next_valid)
+    }
+
+    fn is_block_start(&self, address: i64) -> bool {
+        address >= self.offset as i64 && address < (self.offset + self.size) as i64
+    }
+
+    /// The most blocks the heap can be split into, which bounds the walks over the block list
+    /// and the free lists.
+    fn max_blocks(&self) -> usize {
+        self.size / (self.header_size + PAYLOAD_ALIGNMENT as usize)
+    }
+
+    /// Reads the size of the block at `block_address`, negative while it is allocated. The
+    /// headers are in memory the program can write to, so the size is only trusted if the block
+    /// holds its header and ends where the next block starts.
+    fn read_size(&self, memory: &Memory, block_address: usize) -> VmResult<i64> {
+        let size = memory.read_i64_le(block_address, DATA_SIZE_64)?;
+        let end = match self.read_link(memory, block_address + 8)? {
+            NO_BLOCK => self.offset + self.size,
+            next => next as usize,
+        };
+        let size_valid = size.unsigned_abs() >= self.header_size as u64
+            && (block_address as u64).checked_add(size.unsigned_abs()) == Some(end as u64);
+        if !size_valid {
+            return Err(VmTrapKind::HeapCorrupted {
+                address: block_address as u64,
+            });
+        }
+        Ok(size)
+    }
+
+    /// Reads a link to another block from a header, trapping if it points outside the heap.
+    fn read_link(&self, memory: &Memory, address: usize) -> VmResult<i64> {
+        let link = memory.read_i64_le(address, DATA_SIZE_32)?;
+        if link != NO_BLOCK && !self.is_block_start(link) {
+            return Err(VmTrapKind::HeapCorrupted {
+                address: address as u64,
+            });
+        }
+        Ok(link)
+    }
+
+    /// Finds a free block of at least `size` bytes. Only the first class searched can hold
+    /// blocks that are too small, every block of the larger classes fits.
+    fn find_free_block(&self, memory: &Memory, size: u64) -> VmResult<Option<usize>> {
+        let class = size_class(size);
+        let mut current = self.free_lists[class];
+        for _ in 0..=self.max_blocks() {
+            if current == NO_BLOCK {
+                break;
+            }
+            if self.read_size(memory, current as usize)? >= size as i64 {
+                return Ok(Some(current as usize));
+            }
+            current = self.read_link(memory, current as usize + FREE_NEXT)?;
+        }
+        if current != NO_BLOCK {
+            return Err(VmTrapKind::HeapCorrupted {
+                address: current as u64,
+            });
+        }
+        Ok(self.free_lists[class + 1..]
+            .iter()
+            .find(|&&head| head != NO_BLOCK)
+            .map(|&head| head as usize))
     }
 
-    fn split_block(&self, memory: &mut Memory, block_address: usize, size: u64) {
-        let block_size = memory.read_i64_le(block_address, DATA_SIZE_64);
-        if block_size <= size as i64 + self.header_size as i64 {
-            return;
+    /// Shrinks the free block at `block_address` to `size` bytes if the rest is large enough
+    /// to form a block of its own, which is added to the free lists. Returns the new size.
+    fn split_block(
+        &mut self,
+        memory: &mut Memory,
+        block_address: usize,
+        block_size: u64,
+        size: u64,
+    ) -> VmResult<u64> {
+        let minimum_block_size = self.header_size as u64 + PAYLOAD_ALIGNMENT;
+        if block_size < size + minimum_block_size {
+            return Ok(block_size);
         }
-        let next = memory.read_i64_le(block_address + 8, DATA_SIZE_32);
-        let prev = memory.read_i64_le(block_address + 12, DATA_SIZE_32);
+        let next = self.read_link(memory, block_address + 8)?;
+        let prev = self.read_link(memory, block_address + 12)?;
         let new_block_address = block_address + size as usize;
-        let new_block_size = block_size - size as i64;
-        memory.write_i64_le(block_address, size as i64, DATA_SIZE_64);
-        memory.write_i64_le(block_address + 8, new_block_address as i64, DATA_SIZE_32);
-        memory.write_i64_le(block_address + 12, prev, DATA_SIZE_32);
-        memory.write_i64_le(new_block_address, new_block_size, DATA_SIZE_64);
-        memory.write_i64_le(new_block_address + 8, next, DATA_SIZE_32);
-        memory.write_i64_le(new_block_address + 12, block_address as i64, DATA_SIZE_32);
-    }
-
-    fn merge_blocks(&self, memory: &mut Memory, block_address: usize) {
-        let block_size = memory.read_i64_le(block_address, DATA_SIZE_64);
-        let next = memory.read_i64_le(block_address + 8, DATA_SIZE_32);
-        if next != -1 {
-            let next_block_address = next as usize;
-            let next_block_size = memory.read_i64_le(next_block_address, DATA_SIZE_64);
-            if next_block_size > 0 {
-                memory.write_i64_le(block_address, block_size + next_block_size, DATA_SIZE_64);
-                let new_next = memory.read_i64_le(next_block_address + 8, DATA_SIZE_32);
-                memory.write_i64_le(block_address + 8, new_next, DATA_SIZE_32);
-                if new_next != -1 {
-                    memory.write_i64_le(new_next as usize + 12, block_address as i64, DATA_SIZE_32);
-                }
+        let new_block_size = block_size - size;
+        self.write_header(
+            memory,
+            block_address,
+            size as i64,
+            new_block_address as i64,
+            prev,
+        )?;
+        self.write_header(
+            memory,
+            new_block_address,
+            new_block_size as i64,
+            next,
+            block_address as i64,
+        )?;
+        if next == NO_BLOCK {
+            self.last_block = new_block_address;
+        } else {
+            memory.write_i64_le(next as usize + 12, new_block_address as i64, DATA_SIZE_32)?;
+        }
+        self.push_free(memory, new_block_address, new_block_size)?;
+        Ok(size)
+    }
+
+    /// Merges the newly freed block at `block_address` with its free neighbours and adds the
+    /// result to the free lists.
+    fn merge_blocks(&mut self, memory: &mut Memory, block_address: usize) -> VmResult<()> {
+        let mut block_address = block_address;
+        let mut block_size = self.read_size(memory, block_address)? as u64;
+
+        let next = self.read_link(memory, block_address + 8)?;
+        if next != NO_BLOCK {
+            let next_size = self.read_size(memory, next as usize)?;
+            if next_size > 0 {
+                self.remove_free(memory, next as usize, next_size as u64)?;
+                block_size += next_size as u64;
+                self.unlink_next(memory, block_address, next as usize)?;
             }
         }
 
-        let block_size = memory.read_i64_le(block_address, DATA_SIZE_64);
-        let prev = memory.read_i64_le(block_address + 12, DATA_SIZE_32);
-        if prev != -1 {
-            let prev_block_address = prev as usize;
-            let prev_block_size = memory.read_i64_le(prev_block_address, DATA_SIZE_64);
-            if prev_block_size > 0 {
-                memory.write_i64_le(
-                    prev_block_address,
-                    prev_block_size + block_size,
-                    DATA_SIZE_64,
-                );
-                let new_next = memory.read_i64_le(block_address + 8, DATA_SIZE_32);
-                memory.write_i64_le(prev_block_address + 8, new_next, DATA_SIZE_32);
-                if new_next != -1 {
-                    memory.write_i64_le(
-                        new_next as usize + 12,
-                        prev_block_address as i64,
-                        DATA_SIZE_32,
-                    );
-                }
+        let prev = self.read_link(memory, block_address + 12)?;
+        if prev != NO_BLOCK {
+            let prev_size = self.read_size(memory, prev as usize)?;
+            if prev_size > 0 {
+                self.remove_free(memory, prev as usize, prev_size as u64)?;
+                block_size += prev_size as u64;
+                self.unlink_next(memory, prev as usize, block_address)?;
+                block_address = prev as usize;
             }
         }
+
+        memory.write_i64_le(block_address, block_size as i64, DATA_SIZE_64)?;
+        self.push_free(memory, block_address, block_size)
+    }
+
+    /// Removes the block `next` from the block list, after the block `block_address`.
+    fn unlink_next(
+        &mut self,
+        memory: &mut Memory,
+        block_address: usize,
+        next: usize,
+    ) -> VmResult<()> {
+        let new_next = self.read_link(memory, next + 8)?;
+        memory.write_i64_le(block_address + 8, new_next, DATA_SIZE_32)?;
+        if new_next == NO_BLOCK {
+            self.last_block = block_address;
+        } else {
+            memory.write_i64_le(new_next as usize + 12, block_address as i64, DATA_SIZE_32)?;
+        }
+        Ok(())
+    }
+
+    fn write_header(
+        &self,
+        memory: &mut Memory,
+        block_address: usize,
+        size: i64,
+        next: i64,
+        prev: i64,
+    ) -> VmResult<()> {
+        memory.write_i64_le(block_address, size, DATA_SIZE_64)?;
+        memory.write_i64_le(block_address + 8, next, DATA_SIZE_32)?;
+        memory.write_i64_le(block_address + 12, prev, DATA_SIZE_32)
+    }
+
+    fn push_free(&mut self, memory: &mut Memory, block_address: usize, size: u64) -> VmResult<()> {
+        let class = size_class(size);
+        let head = self.free_lists[class];
+        memory.write_i64_le(block_address + FREE_NEXT, head, DATA_SIZE_32)?;
+        memory.write_i64_le(block_address + FREE_PREV, NO_BLOCK, DATA_SIZE_32)?;
+        if head != NO_BLOCK {
+            memory.write_i64_le(
+                head as usize + FREE_PREV,
+                block_address as i64,
+                DATA_SIZE_32,
+            )?;
+        }
+        self.free_lists[class] = block_address as i64;
+        Ok(())
     }
 
-    fn toggle_free(&mut self, memory: &mut Memory, block_address: usize) {
-        let block_size = memory.read_i64_le(block_address, DATA_SIZE_64);
-        memory.write_i64_le(block_address, -block_size, DATA_SIZE_64);
+    fn remove_free(
+        &mut self,
+        memory: &mut Memory,
+        block_address: usize,
+        size: u64,
+    ) -> VmResult<()> {
+        let next = self.read_link(memory, block_address + FREE_NEXT)?;
+        let prev = self.read_link(memory, block_address + FREE_PREV)?;
+        if prev == NO_BLOCK {
+            self.free_lists[size_class(size)] = next;
+        } else {
+            memory.write_i64_le(prev as usize + FREE_NEXT, next, DATA_SIZE_32)?;
+        }
+        if next != NO_BLOCK {
+            memory.write_i64_le(next as usize + FREE_PREV, prev, DATA_SIZE_32)?;
+        }
+        Ok(())
     }
 
     pub fn print_blocks(&self, memory: &Memory) {
         let mut current = self.offset;
-        loop {
-            let block_size = memory.read_i64_le(current, DATA_SIZE_64);
-            let next = memory.read_i64_le(current + 8, DATA_SIZE_32);
-            let prev = memory.read_i64_le(current + 12, DATA_SIZE_32);
+        for _ in 0..=self.max_blocks() {
+            let header = (
+                memory.read_i64_le(current, DATA_SIZE_64),
+                memory.read_i64_le(current + 8, DATA_SIZE_32),
+                memory.read_i64_le(current + 12, DATA_SIZE_32),
+            );
+            let (Ok(block_size), Ok(next), Ok(prev)) = header else {
+                println!("Block: {:?}, Header out of bounds", current);
+                break;
+            };
             println!(
                 "Block: {:?}, Size: {:?}, Next: {:?}, Prev: {:?}",
                 current, block_size, next, prev
             );
-            if block_size.abs() < 1000 {
-                let content = memory.data[current..current + block_size.abs() as usize].to_vec();
-                println!("Content: {:?}", content);
+            if block_size.unsigned_abs() < 1000 {
+                if let Ok(content) = memory.read_bytes(current, block_size.unsigned_abs() as usize)
+                {
+                    println!("Content: {:?}", content);
+                }
             }
-            if next == -1 {
+            if next == NO_BLOCK {
                 break;
             }
             current = next as usize;
         }
     }
 }
+
+/// The free list holding blocks of `size` bytes.
+fn size_class(size: u64) -> usize {
+    let bits = 63 - size.max(1).leading_zeros() as usize;
+    bits.saturating_sub(5).min(SIZE_CLASSES - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lychee_compiler::VmConfig;
+
+    /// A heap of 4 KiB after 4 KiB of other memory, which may grow to 16 KiB.
+    fn heap() -> (Memory, Heap) {
+        let layout = MemoryLayout {
+            memory_size: 0x2000,
+            max_memory_size: 0x5000,
+            heap_offset: 0x1000,
+            heap_size: 0x1000,
+            stack_start: 0,
+            stack_end: 0x1000,
+        };
+        let mut memory = Memory::new(&layout, Vec::new(), &VmConfig::default());
+        let heap = Heap::new(&mut memory, &layout).unwrap();
+        (memory, heap)
+    }
+
+    #[test]
+    fn free_merges_neighbouring_blocks() {
+        let (mut memory, mut heap) = heap();
+        let a = heap.malloc(&mut memory, 16).unwrap();
+        let b = heap.malloc(&mut memory, 16).unwrap();
+        let c = heap.malloc(&mut memory, 16).unwrap();
+        assert!(a < b && b < c);
+
+        heap.free(&mut memory, a).unwrap();
+        assert_eq!(heap.malloc(&mut memory, 8).unwrap(), a);
+        heap.free(&mut memory, a).unwrap();
+        heap.free(&mut memory, c).unwrap();
+        heap.free(&mut memory, b).unwrap();
+
+        let stats = heap.stats(&memory);
+        assert_eq!(
+            (stats.allocations, stats.frees, stats.live_bytes),
+            (4, 4, 0)
+        );
+        assert_eq!(stats.free_bytes, 0x1000);
+        assert_eq!(stats.largest_free_block, 0x1000);
+        assert_eq!(heap.malloc(&mut memory, 0x1000 - 24).unwrap(), a);
+    }
+
+    #[test]
+    fn free_rejects_addresses_that_are_not_allocated() {
+        let (mut memory, mut heap) = heap();
+        let a = heap.malloc(&mut memory, 16).unwrap();
+        for address in [0, a + 8, 0x3000] {
+            let error = heap.free(&mut memory, address).unwrap_err();
+            assert_eq!(
+                error,
+                VmTrapKind::InvalidFree {
+                    address: address as u64
+                }
+            );
+        }
+        heap.free(&mut memory, a).unwrap();
+        let error = heap.free(&mut memory, a).unwrap_err();
+        assert_eq!(error, VmTrapKind::InvalidFree { address: a as u64 });
+    }
+
+    #[test]
+    fn realloc_grows_in_place_or_moves() {
+        let (mut memory, mut heap) = heap();
+        let a = heap.malloc(&mut memory, 16).unwrap();
+        let b = heap.malloc(&mut memory, 16).unwrap();
+        memory.write_bytes(a, b"lychee").unwrap();
+
+        // The block after `a` is in use, so growing moves it.
+        let moved = heap.realloc(&mut memory, a, 64).unwrap();
+        assert_ne!(moved, a);
+        assert_eq!(memory.read_bytes(moved, 6).unwrap(), b"lychee");
+
+        // Freeing the moved block leaves `b` followed by free memory, so it grows in place.
+        heap.free(&mut memory, moved).unwrap();
+        let grown = heap.realloc(&mut memory, b, 40).unwrap();
+        assert_eq!(grown, b);
+        assert_eq!(heap.realloc(&mut memory, grown, 8).unwrap(), b);
+
+        let stats = heap.stats(&memory);
+        assert_eq!((stats.allocations, stats.frees), (2, 1));
+        assert_eq!(stats.live_bytes, 8);
+        assert_eq!(stats.free_bytes, 0x1000 - 8 - 24);
+    }
+
+    #[test]
+    fn heap_grows_up_to_its_maximum_size() {
+        let (mut memory, mut heap) = heap();
+        let a = heap.malloc(&mut memory, 0x1800).unwrap();
+        assert_eq!(heap.size, 0x2000);
+        assert_eq!(memory.data.len(), 0x3000);
+        memory.write_bytes(a + 0x17ff, &[1]).unwrap();
+
+        let error = heap.malloc(&mut memory, 0x4000).unwrap_err();
+        assert_eq!(error, VmTrapKind::HeapExhausted { requested: 0x4000 });
+        let b = heap.malloc(&mut memory, 0x1800).unwrap();
+        assert_eq!(heap.size, 0x4000);
+        assert!(b > a);
+    }
+
+    #[test]
+    fn corrupted_headers_trap() {
+        let (mut memory, mut heap) = heap();
+        let a = heap.malloc(&mut memory, 16).unwrap();
+        let b = heap.malloc(&mut memory, 16).unwrap();
+        memory.write_i64_le(a - 24, -1, DATA_SIZE_64).unwrap();
+        let error = heap.free(&mut memory, a).unwrap_err();
+        assert_eq!(error, VmTrapKind::InvalidFree { address: a as u64 });
+
+        // Points the free block after `b` back at itself.
+        let free_block = b + 16;
+        memory
+            .write_i64_le(free_block + FREE_NEXT, free_block as i64, DATA_SIZE_32)
+            .unwrap();
+        // A block of the same size class that does not fit makes `malloc` walk the list.
+        let error = heap.malloc(&mut memory, 4000).unwrap_err();
+        let address = free_block as u64;
+        assert_eq!(error, VmTrapKind::HeapCorrupted { address });
+        let block_size = 0x1000 - 2 * 40;
+        assert_eq!(heap.stats(&memory).largest_free_block, block_size);
+
+        let layout = MemoryLayout {
+            memory_size: 0x2000,
+            max_memory_size: 0x5000,
+            heap_offset: 0x1000,
+            heap_size: 0x1000,
+            stack_start: 0,
+            stack_end: 0x1000,
+        };
+        memory
+            .write_i64_le(b - 24 + 8, (b - 24) as i64, DATA_SIZE_32)
+            .unwrap();
+        let error = Heap::restore(&mut memory, &layout, HeapStats::default()).unwrap_err();
+        assert!(
+            matches!(error, VmTrapKind::HeapCorrupted { .. }),
+            "{}",
+            error
+        );
+    }
+}
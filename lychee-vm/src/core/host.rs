@@ -0,0 +1,111 @@
+use crate::core::memory::Memory;
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::host_function_id;
+use std::collections::HashMap;
+
+/// A Rust function called by the program through an `extern` declaration.
+pub type HostFunction = Box<dyn FnMut(&mut HostCall) -> VmResult<()>>;
+
+/// The host functions registered with a machine, keyed by the id of their name.
+#[derive(Default)]
+pub struct HostFunctions {
+    functions: HashMap<u64, HostFunction>,
+}
+
+impl HostFunctions {
+    pub fn register(&mut self, name: &str, function: HostFunction) {
+        self.functions.insert(host_function_id(name), function);
+    }
+
+    /// Calls the function with the id `id`, whose arguments are the `size` bytes at `address`.
+    pub fn call(&mut self, id: u64, memory: &mut Memory, address: u64, size: u64) -> VmResult<()> {
+        let function = self
+            .functions
+            .get_mut(&id)
+            .ok_or(VmTrapKind::UnknownHostFunction { id })?;
+        function(&mut HostCall {
+            memory,
+            address,
+            size,
+            remaining: size,
+        })
+    }
+}
+
+/// A call from the program to a host function, giving access to its arguments, its result and
+/// the memory of the program.
+///
+/// The arguments are read in the order they are declared in, and each reader has to match the
+/// size of the declared type: `char` and `bool` are 1 byte, `int` is 4 bytes, and `long` and
+/// pointers are 8 bytes. Structs are passed as bytes, with their last field at the lowest
+/// address. Functions returning a struct write it with `return_bytes`, all other results are
+/// returned with `set_return`.
+pub struct HostCall<'a> {
+    memory: &'a mut Memory,
+    address: u64,
+    size: u64,
+    /// The number of argument bytes not read yet. The first argument was pushed first, so the
+    /// arguments are read from the end.
+    remaining: u64,
+}
+
+impl HostCall<'_> {
+    /// Reads the next argument, which is `size` bytes long.
+    pub fn arg_bytes(&mut self, size: usize) -> VmResult<Vec<u8>> {
+        if size as u64 > self.remaining {
+            return Err(VmTrapKind::HostFunction(format!(
+                "argument of {} bytes read with {} bytes left",
+                size, self.remaining
+            )));
+        }
+        self.remaining -= size as u64;
+        self.memory
+            .read_bytes((self.address + self.remaining) as usize, size)
+    }
+
+    pub fn arg_char(&mut self) -> VmResult<u8> {
+        Ok(self.arg_bytes(1)?[0])
+    }
+
+    pub fn arg_bool(&mut self) -> VmResult<bool> {
+        Ok(self.arg_char()? != 0)
+    }
+
+    pub fn arg_int(&mut self) -> VmResult<i32> {
+        let bytes = self.arg_bytes(4)?;
+        Ok(i32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn arg_long(&mut self) -> VmResult<i64> {
+        let bytes = self.arg_bytes(8)?;
+        Ok(i64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn arg_pointer(&mut self) -> VmResult<u64> {
+        Ok(self.arg_long()? as u64)
+    }
+
+    /// Returns a value that fits into a register.
+    pub fn set_return(&mut self, value: u64) {
+        self.memory.registers[0] = value;
+    }
+
+    /// Returns a struct by writing it to the return slot above the arguments.
+    pub fn return_bytes(&mut self, bytes: &[u8]) -> VmResult<()> {
+        let address = self.address + self.size;
+        self.memory.write_bytes(address as usize, bytes)
+    }
+
+    /// Reads the null-terminated string at `address`.
+    pub fn read_string(&self, address: u64) -> VmResult<String> {
+        self.memory.read_string(address as usize)
+    }
+
+    pub fn read_bytes(&self, address: u64, size: usize) -> VmResult<Vec<u8>> {
+        self.memory.read_bytes(address as usize, size)
+    }
+
+    pub fn write_bytes(&mut self, address: u64, bytes: &[u8]) -> VmResult<()> {
+        self.memory.write_bytes(address as usize, bytes)
+    }
+}
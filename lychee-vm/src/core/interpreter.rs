@@ -0,0 +1,527 @@
+use crate::core::constants;
+use crate::core::decoder::{Address, Instruction};
+use crate::core::files::FileMode;
+use crate::core::heap::Heap;
+use crate::core::host::HostFunctions;
+use crate::core::memory::{Flags, Memory};
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::{
+    BinopType, FlagConditionType, FloatBinopType, UnopType, DATA_SIZE_64, FILE_ERROR_INVALID,
+};
+use rand::{RngCore, SeedableRng};
+use std::io::{Read, Write};
+
+/// Executes a decoded instruction. The PC must already point to the next instruction.
+/// Returns the exit code if the instruction was an `exit`.
+#[inline(always)]
+pub fn execute(
+    instruction: &Instruction,
+    memory: &mut Memory,
+    heap: &mut Heap,
+    host_functions: &mut HostFunctions,
+) -> VmResult<Option<i64>> {
+    match *instruction {
+        Instruction::Exit => {
+            memory.stdout.flush()?;
+            return Ok(Some(memory.registers[0] as i64));
+        }
+        Instruction::Load {
+            size,
+            register,
+            address,
+        } => {
+            let address = address.resolve(&memory.registers);
+            let value = memory.read_i64_le(address as usize, size)?;
+            set_register(memory, register, value as u64)?;
+        }
+        Instruction::Store {
+            size,
+            register,
+            address,
+        } => {
+            let address = address.resolve(&memory.registers);
+            let value = memory.registers[register as usize] as i64;
+            memory.write_i64_le(address as usize, value, size)?;
+        }
+        Instruction::Push { size, register } => {
+            let value = memory.registers[register as usize] as i64;
+            let address = decrement_sp(memory, size as u64)?;
+            memory.write_i64_le(address as usize, value, size)?;
+        }
+        Instruction::Pop { size, register } => {
+            let address = memory.registers[constants::SP];
+            let value = memory.read_i64_le(address as usize, size)?;
+            set_register(memory, register, value as u64)?;
+            let sp = address.wrapping_add(size as u64);
+            set_register(memory, constants::SP as u8, sp)?;
+        }
+        Instruction::Binop { op, dest, source } => {
+            let value = memory.registers[source as usize] as i64;
+            binop(memory, op, dest, value)?;
+        }
+        Instruction::BinopImmediate { op, dest, value } => binop(memory, op, dest, value)?,
+        Instruction::Jump { condition, address } => {
+            if condition_holds(&memory.flags, condition) {
+                memory.registers[constants::PC] = address.resolve(&memory.registers);
+            }
+        }
+        Instruction::Set {
+            condition,
+            register,
+        } => {
+            let value = condition_holds(&memory.flags, condition);
+            set_register(memory, register, value as u64)?;
+        }
+        Instruction::Unop { op, register } => unop(memory, op, register)?,
+        Instruction::Call { address } => {
+            let address = address.resolve(&memory.registers);
+            let return_pc = memory.registers[constants::PC];
+            let sp = decrement_sp(memory, 8)?;
+            memory.write_u64_le(sp as usize, return_pc, DATA_SIZE_64)?;
+            memory.registers[constants::PC] = address;
+            memory.call_depth += 1;
+        }
+        Instruction::Ret => {
+            let sp = memory.registers[constants::SP];
+            memory.registers[constants::PC] = memory.read_u64_le(sp as usize, DATA_SIZE_64)?;
+            memory.registers[constants::SP] += 8;
+            memory.call_depth = memory.call_depth.saturating_sub(1);
+        }
+        Instruction::ReadStdin { register, address } => {
+            let read_bytes = memory.registers[register as usize] as usize;
+            let address = address.resolve(&memory.registers) as usize;
+            let range = memory.writable_range(address, read_bytes)?;
+            memory.stdin.read_exact(&mut memory.data[range])?;
+        }
+        Instruction::WriteStdout { register, address } => {
+            let write_bytes = memory.registers[register as usize] as usize;
+            let address = address.resolve(&memory.registers) as usize;
+            let buffer = memory.read_bytes(address, write_bytes)?;
+            memory.stdout.write_all(&buffer)?;
+        }
+        Instruction::Rand { register } => {
+            let value = memory.rng.next_u64();
+            set_register(memory, register, value)?;
+        }
+        Instruction::SeedRand { register } => {
+            let seed = memory.registers[register as usize];
+            memory.rng = rand_chacha::ChaCha12Rng::seed_from_u64(seed);
+        }
+        Instruction::SignExtend { size, register } => {
+            let value = memory.registers[register as usize] as i64;
+            let extended_value = match size {
+                1 => value as i8 as i64,
+                2 => value as i16 as i64,
+                4 => value as i32 as i64,
+                _ => value,
+            };
+            set_register(memory, register, extended_value as u64)?;
+        }
+        Instruction::Lea { register, address } => {
+            set_register(memory, register, address.resolve(&memory.registers))?;
+        }
+        Instruction::PushMem { register, address } => {
+            let data_size = memory.registers[register as usize];
+            let dest_address = decrement_sp(memory, data_size)? as usize;
+            let src_address = address.resolve(&memory.registers) as usize;
+            memory.memory_copy(src_address, dest_address, data_size as usize)?;
+        }
+        Instruction::PopMem { register, address } => {
+            popmem(memory, register, address)?;
+            let sp = memory.registers[constants::SP];
+            let sp = sp.wrapping_add(memory.registers[register as usize]);
+            set_register(memory, constants::SP as u8, sp)?;
+        }
+        Instruction::PeekMem { register, address } => popmem(memory, register, address)?,
+        Instruction::Alloc {
+            size_register,
+            address_register,
+        } => {
+            let size = memory.registers[size_register as usize];
+            let address = heap.malloc(memory, size)?;
+            set_register(memory, address_register, address as u64)?;
+        }
+        Instruction::Free { register } => {
+            let address = memory.registers[register as usize] as usize;
+            heap.free(memory, address)?;
+        }
+        Instruction::Realloc {
+            size_register,
+            address_register,
+        } => {
+            let size = memory.registers[size_register as usize];
+            let address = memory.registers[address_register as usize] as usize;
+            let address = heap.realloc(memory, address, size)?;
+            set_register(memory, address_register, address as u64)?;
+        }
+        Instruction::FileOpen { register, address } => {
+            let address = address.resolve(&memory.registers) as usize;
+            let path = memory.read_string(address)?;
+            let file_id = memory.files.open(&path, FileMode::ReadWrite);
+            set_register(memory, register, file_id as u64)?;
+        }
+        Instruction::FileOpenMode { register, address } => {
+            let address = address.resolve(&memory.registers) as usize;
+            let path = memory.read_string(address)?;
+            let file_id = match FileMode::from_code(memory.registers[register as usize]) {
+                Some(mode) => memory.files.open(&path, mode),
+                None => FILE_ERROR_INVALID,
+            };
+            set_register(memory, register, file_id as u64)?;
+        }
+        Instruction::FileSeek {
+            file_register,
+            position_register,
+        } => {
+            let file_id = memory.registers[file_register as usize];
+            let position = memory.registers[position_register as usize] as i64;
+            let position = memory.files.seek(file_id, position)?;
+            set_register(memory, position_register, position as u64)?;
+        }
+        Instruction::FileTell { register } => {
+            let file_id = memory.registers[register as usize];
+            let position = memory.files.tell(file_id)?;
+            set_register(memory, register, position as u64)?;
+        }
+        Instruction::FileSize { register } => {
+            let file_id = memory.registers[register as usize];
+            let size = memory.files.size(file_id)?;
+            set_register(memory, register, size as u64)?;
+        }
+        Instruction::FileRemove { register, address } => {
+            let address = address.resolve(&memory.registers) as usize;
+            let path = memory.read_string(address)?;
+            let result = memory.files.remove(&path);
+            set_register(memory, register, result as u64)?;
+        }
+        Instruction::FileRename { register, from, to } => {
+            let from = memory.read_string(from.resolve(&memory.registers) as usize)?;
+            let to = memory.read_string(to.resolve(&memory.registers) as usize)?;
+            let result = memory.files.rename(&from, &to);
+            set_register(memory, register, result as u64)?;
+        }
+        Instruction::FileClose { register } => {
+            let file_id = memory.registers[register as usize];
+            memory.files.close(file_id)?;
+        }
+        Instruction::FileRead {
+            file_register,
+            size_register,
+            address,
+        } => {
+            let file_id = memory.registers[file_register as usize];
+            let size = memory.registers[size_register as usize] as usize;
+            let address = address.resolve(&memory.registers) as usize;
+            let range = memory.writable_range(address, size)?;
+            let read_bytes = memory.files.read(file_id, &mut memory.data[range])?;
+            set_register(memory, size_register, read_bytes as u64)?;
+        }
+        Instruction::FileWrite {
+            file_register,
+            size_register,
+            address,
+        } => {
+            let file_id = memory.registers[file_register as usize];
+            let size = memory.registers[size_register as usize] as usize;
+            let address = address.resolve(&memory.registers) as usize;
+            let buffer = memory.read_bytes(address, size)?;
+            let written_bytes = memory.files.write(file_id, &buffer)?;
+            set_register(memory, size_register, written_bytes as u64)?;
+        }
+        Instruction::MemCopy {
+            size_register,
+            dest,
+            src,
+        } => {
+            let size = memory.registers[size_register as usize] as usize;
+            let dest_address = dest.resolve(&memory.registers) as usize;
+            let src_address = src.resolve(&memory.registers) as usize;
+            memory.memory_copy(src_address, dest_address, size)?;
+        }
+        Instruction::MemSet {
+            size_register,
+            value_register,
+            address,
+        } => {
+            let value = memory.registers[value_register as usize] as u8;
+            let size = memory.registers[size_register as usize] as usize;
+            let address = address.resolve(&memory.registers) as usize;
+            memory.memory_set(address, value, size)?;
+        }
+        Instruction::HostCall {
+            address_register,
+            size_register,
+            id,
+        } => {
+            let address = memory.registers[address_register as usize];
+            let size = memory.registers[size_register as usize];
+            host_functions.call(id, memory, address, size)?;
+        }
+        Instruction::FloatBinop {
+            op,
+            size,
+            dest,
+            source,
+        } => float_binop(memory, op, size, dest, source)?,
+        Instruction::FloatNeg { size, register } => {
+            let value = read_float(memory, size, register);
+            write_float(memory, size, register, -value)?;
+        }
+        Instruction::IntToFloat { size, register } => {
+            let value = memory.registers[register as usize] as i64;
+            let bits = match size {
+                4 => (value as f32).to_bits() as u64,
+                _ => (value as f64).to_bits(),
+            };
+            set_register(memory, register, bits)?;
+        }
+        Instruction::FloatToInt { size, register } => {
+            // Rounds towards zero and saturates, NaN becomes 0.
+            let value = read_float(memory, size, register);
+            set_register(memory, register, value as i64 as u64)?;
+        }
+        Instruction::FloatConvert { size, register } => {
+            let other_size = if size == 4 { 8 } else { 4 };
+            let value = read_float(memory, other_size, register);
+            write_float(memory, size, register, value)?;
+        }
+        Instruction::Abort => {
+            memory.stdout.flush()?;
+            return Err(VmTrapKind::Abort);
+        }
+        Instruction::ArgCount { register } => {
+            set_register(memory, register, memory.args.len() as u64)?;
+        }
+        Instruction::ArgLength { register } => {
+            let arg = argument(memory, register)?;
+            set_register(memory, register, arg.len() as u64)?;
+        }
+        Instruction::ArgRead {
+            index_register,
+            size_register,
+            address,
+        } => {
+            let arg = argument(memory, index_register)?.as_bytes();
+            let size = (memory.registers[size_register as usize] as usize).min(arg.len());
+            let address = address.resolve(&memory.registers) as usize;
+            let bytes = arg[..size].to_vec();
+            memory.write_bytes(address, &bytes)?;
+            set_register(memory, size_register, size as u64)?;
+        }
+    }
+    Ok(None)
+}
+
+/// The argument whose index is held in `register`.
+fn argument(memory: &Memory, register: u8) -> VmResult<&String> {
+    let index = memory.registers[register as usize];
+    memory
+        .args
+        .get(index as usize)
+        .ok_or(VmTrapKind::InvalidArgumentIndex { index })
+}
+
+#[inline(always)]
+fn condition_holds(flags: &Flags, condition: FlagConditionType) -> bool {
+    match condition {
+        FlagConditionType::Always => true,
+        FlagConditionType::Zero => flags.zero,
+        FlagConditionType::NotZero => !flags.zero,
+        FlagConditionType::Greater => !flags.zero && !flags.negative(),
+        FlagConditionType::GreaterEquals => !flags.negative(),
+        FlagConditionType::Less => flags.negative(),
+        FlagConditionType::LessEquals => flags.zero || flags.negative(),
+        FlagConditionType::Above => !flags.carry && !flags.zero,
+        FlagConditionType::AboveEquals => !flags.carry,
+        FlagConditionType::Below => flags.carry,
+        FlagConditionType::BelowEquals => flags.carry || flags.zero,
+        FlagConditionType::Overflow => flags.overflow,
+        FlagConditionType::NotOverflow => !flags.overflow,
+    }
+}
+
+/// Traps if `stack_pointer` lies below the guard boundary between the program and the stack.
+fn check_stack_pointer(memory: &Memory, stack_pointer: u64) -> VmResult<()> {
+    if stack_pointer < memory.stack_limit {
+        return Err(VmTrapKind::StackOverflow {
+            stack_pointer,
+            call_depth: memory.call_depth,
+        });
+    }
+    Ok(())
+}
+
+/// Writes a register, checking the stack pointer like `decrement_sp` if the register is SP.
+#[inline(always)]
+fn set_register(memory: &mut Memory, register: u8, value: u64) -> VmResult<()> {
+    if register as usize == constants::SP {
+        check_stack_pointer(memory, value)?;
+    }
+    memory.registers[register as usize] = value;
+    Ok(())
+}
+
+fn decrement_sp(memory: &mut Memory, bytes: u64) -> VmResult<u64> {
+    let sp = memory.registers[constants::SP].saturating_sub(bytes);
+    check_stack_pointer(memory, sp)?;
+    memory.registers[constants::SP] = sp;
+    Ok(sp)
+}
+
+fn set_flags(memory: &mut Memory, value: i64, carry: bool, overflow: bool) {
+    memory.flags.zero = value == 0;
+    memory.flags.positive = value > 0;
+    memory.flags.carry = carry;
+    memory.flags.overflow = overflow;
+}
+
+#[inline(always)]
+fn binop(memory: &mut Memory, op_type: BinopType, dest: u8, right_value: i64) -> VmResult<()> {
+    let left_value = memory.registers[dest as usize] as i64;
+
+    let (left_unsigned, right_unsigned) = (left_value as u64, right_value as u64);
+
+    if matches!(
+        op_type,
+        BinopType::Div | BinopType::Mod | BinopType::UnsignedDiv | BinopType::UnsignedMod
+    ) && right_value == 0
+    {
+        return Err(VmTrapKind::DivisionByZero);
+    }
+
+    // Results wrap around on overflow. Dividing `i64::MIN` by -1 gives `i64::MIN` with a
+    // remainder of 0. Shifting by 64 or more, or by a negative amount, shifts out every bit, so
+    // only copies of the sign bit remain after an arithmetic right shift.
+    let shift = u32::try_from(right_value).ok().filter(|&shift| shift < 64);
+    let (result, carry, overflow) = match op_type {
+        BinopType::Mov => (right_value, false, false),
+        BinopType::Add => {
+            let (result, overflow) = left_value.overflowing_add(right_value);
+            let (_, carry) = left_unsigned.overflowing_add(right_unsigned);
+            (result, carry, overflow)
+        }
+        BinopType::Sub | BinopType::Cmp => {
+            let (result, overflow) = left_value.overflowing_sub(right_value);
+            (result, left_unsigned < right_unsigned, overflow)
+        }
+        BinopType::Mul => {
+            let (result, overflow) = left_value.overflowing_mul(right_value);
+            let (_, carry) = left_unsigned.overflowing_mul(right_unsigned);
+            (result, carry, overflow)
+        }
+        BinopType::Div => {
+            let (result, overflow) = left_value.overflowing_div(right_value);
+            (result, false, overflow)
+        }
+        BinopType::Mod => {
+            let (result, overflow) = left_value.overflowing_rem(right_value);
+            (result, false, overflow)
+        }
+        BinopType::UnsignedDiv => ((left_unsigned / right_unsigned) as i64, false, false),
+        BinopType::UnsignedMod => ((left_unsigned % right_unsigned) as i64, false, false),
+        BinopType::And => (left_value & right_value, false, false),
+        BinopType::Or => (left_value | right_value, false, false),
+        BinopType::Xor => (left_value ^ right_value, false, false),
+        BinopType::Shl => (shift.map_or(0, |shift| left_value << shift), false, false),
+        BinopType::Shr => (left_value >> shift.unwrap_or(63), false, false),
+        BinopType::UnsignedShr => {
+            let result = shift.map_or(0, |shift| left_unsigned >> shift);
+            (result as i64, false, false)
+        }
+    };
+
+    if memory.checked_arithmetic && op_type != BinopType::Cmp {
+        let is_shift = matches!(
+            op_type,
+            BinopType::Shl | BinopType::Shr | BinopType::UnsignedShr
+        );
+        if overflow || (is_shift && shift.is_none()) {
+            return Err(VmTrapKind::ArithmeticOverflow);
+        }
+    }
+
+    set_flags(memory, result, carry, overflow);
+
+    if op_type != BinopType::Cmp {
+        set_register(memory, dest, result as u64)?;
+    }
+    Ok(())
+}
+
+/// Reads the `float` in the low 32 bits or the `double` in all 64 bits of a register.
+fn read_float(memory: &Memory, size: u8, register: u8) -> f64 {
+    let bits = memory.registers[register as usize];
+    match size {
+        4 => f32::from_bits(bits as u32) as f64,
+        _ => f64::from_bits(bits),
+    }
+}
+
+fn write_float(memory: &mut Memory, size: u8, register: u8, value: f64) -> VmResult<()> {
+    let bits = match size {
+        4 => (value as f32).to_bits() as u64,
+        _ => value.to_bits(),
+    };
+    set_register(memory, register, bits)
+}
+
+fn float_binop(
+    memory: &mut Memory,
+    op_type: FloatBinopType,
+    size: u8,
+    dest: u8,
+    source: u8,
+) -> VmResult<()> {
+    // Rounding the `double` result of a `float` operation to a `float` gives the same result as
+    // computing it in single precision.
+    let left = read_float(memory, size, dest);
+    let right = read_float(memory, size, source);
+    let result = match op_type {
+        FloatBinopType::Add => left + right,
+        FloatBinopType::Sub => left - right,
+        FloatBinopType::Mul => left * right,
+        FloatBinopType::Div => left / right,
+        FloatBinopType::Cmp => {
+            memory.flags = Flags {
+                zero: left == right,
+                positive: left > right,
+                carry: left < right,
+                overflow: false,
+            };
+            return Ok(());
+        }
+    };
+    write_float(memory, size, dest, result)
+}
+
+fn unop(memory: &mut Memory, op_type: UnopType, register: u8) -> VmResult<()> {
+    let value = memory.registers[register as usize] as i64;
+    let (result, carry, overflow) = match op_type {
+        UnopType::Not => (!value, false, false),
+        UnopType::Neg => {
+            let (result, overflow) = value.overflowing_neg();
+            (result, value != 0, overflow)
+        }
+        UnopType::Inc => {
+            let (result, overflow) = value.overflowing_add(1);
+            (result, value as u64 == u64::MAX, overflow)
+        }
+        UnopType::Dec => {
+            let (result, overflow) = value.overflowing_sub(1);
+            (result, value == 0, overflow)
+        }
+    };
+    if memory.checked_arithmetic && overflow {
+        return Err(VmTrapKind::ArithmeticOverflow);
+    }
+    set_flags(memory, result, carry, overflow);
+
+    set_register(memory, register, result as u64)
+}
+
+fn popmem(memory: &mut Memory, register: u8, address: Address) -> VmResult<()> {
+    let data_size = memory.registers[register as usize];
+    let src_address = memory.registers[constants::SP] as usize;
+    let dest_address = address.resolve(&memory.registers) as usize;
+    memory.memory_copy(src_address, dest_address, data_size as usize)
+}
@@ -0,0 +1,73 @@
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::VmMemoryConfig;
+
+const HEAP_ALIGNMENT: usize = 16;
+const MIN_HEAP_SIZE: usize = 32;
+const MIN_STACK_SIZE: usize = 64;
+/// The heap stores block addresses as 4 bytes.
+const MAX_MEMORY_SIZE: usize = i32::MAX as usize;
+
+/// The address space of the VM: the program at address zero, followed by the stack, which
+/// grows down from `stack_end` into the region starting at `stack_start`, and the heap. The heap
+/// comes last, so it can grow by extending the memory, up to `max_memory_size` bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryLayout {
+    /// The size of the memory up to the end of the heap.
+    pub memory_size: usize,
+    pub max_memory_size: usize,
+    pub heap_offset: usize,
+    pub heap_size: usize,
+    pub stack_start: usize,
+    pub stack_end: usize,
+}
+
+impl MemoryLayout {
+    pub fn new(program_size: usize, config: &VmMemoryConfig) -> VmResult<MemoryLayout> {
+        let invalid = |message: String| Err(VmTrapKind::InvalidMemoryLayout(message));
+        let to_usize = |value: u64| usize::try_from(value).unwrap_or(usize::MAX);
+        let max_memory_size = to_usize(config.memory_size);
+        let heap_size = to_usize(config.heap_size);
+        let stack_size = to_usize(config.stack_size);
+
+        if max_memory_size > MAX_MEMORY_SIZE {
+            return invalid(format!(
+                "memory size {} is larger than the maximum of {} bytes",
+                max_memory_size, MAX_MEMORY_SIZE
+            ));
+        }
+        if heap_size < MIN_HEAP_SIZE {
+            return invalid(format!(
+                "heap size {} is smaller than the minimum of {} bytes",
+                heap_size, MIN_HEAP_SIZE
+            ));
+        }
+        if stack_size < MIN_STACK_SIZE {
+            return invalid(format!(
+                "stack size {} is smaller than the minimum of {} bytes",
+                stack_size, MIN_STACK_SIZE
+            ));
+        }
+
+        let stack_end = program_size.checked_add(stack_size);
+        let heap_offset = stack_end.and_then(|end| end.checked_next_multiple_of(HEAP_ALIGNMENT));
+        let memory_size = heap_offset.and_then(|offset| offset.checked_add(heap_size));
+        match (stack_end, heap_offset, memory_size) {
+            (Some(stack_end), Some(heap_offset), Some(memory_size))
+                if memory_size <= max_memory_size =>
+            {
+                Ok(MemoryLayout {
+                    memory_size,
+                    max_memory_size,
+                    heap_offset,
+                    heap_size,
+                    stack_start: program_size,
+                    stack_end,
+                })
+            }
+            _ => invalid(format!(
+                "program ({} bytes), stack ({} bytes) and heap ({} bytes) do not fit into {} bytes of memory",
+                program_size, stack_size, heap_size, max_memory_size
+            )),
+        }
+    }
+}
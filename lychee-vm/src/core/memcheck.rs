@@ -0,0 +1,645 @@
+use crate::core::constants;
+use crate::core::decoder::{Address, Instruction};
+use crate::core::heap::Heap;
+use crate::core::memory::Memory;
+use crate::core::observer::Observer;
+use lychee_compiler::{SymbolTable, DATA_SIZE_64};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Number of addresses recorded for a call stack, enough to get past the std wrappers around
+/// the builtin functions.
+const STACK_DEPTH: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ErrorKind {
+    InvalidFree,
+    DoubleFree,
+    UseAfterFree,
+    OutOfBounds,
+    UninitializedRead,
+}
+
+impl ErrorKind {
+    fn description(self) -> &'static str {
+        match self {
+            ErrorKind::InvalidFree => "Invalid free",
+            ErrorKind::DoubleFree => "Double free",
+            ErrorKind::UseAfterFree => "Use after free",
+            ErrorKind::OutOfBounds => "Out of bounds access",
+            ErrorKind::UninitializedRead => "Read of uninitialized memory",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// A read whose value is used, so it must not read uninitialized bytes.
+    Read,
+    /// A read that only copies the bytes, such as pushing a struct, so uninitialized bytes are
+    /// allowed.
+    Copy,
+    Write,
+}
+
+impl Access {
+    fn description(self) -> &'static str {
+        match self {
+            Access::Read | Access::Copy => "read",
+            Access::Write => "write",
+        }
+    }
+}
+
+struct Block {
+    size: u64,
+    allocated_at: Vec<u64>,
+    freed_at: Option<Vec<u64>>,
+}
+
+struct PendingAllocation {
+    size: u64,
+    stack: Vec<u64>,
+    /// The block resized by a `realloc`, or zero.
+    old_address: u64,
+}
+
+struct MemcheckError {
+    kind: ErrorKind,
+    stack: Vec<u64>,
+    /// A description of the first occurrence.
+    details: String,
+    /// The call stacks that allocated and freed the block of the first occurrence.
+    block_stacks: Vec<(&'static str, Vec<u64>)>,
+    count: u64,
+}
+
+/// Checks the heap usage of a program against shadow metadata: every allocation is recorded
+/// with its call stack, and each heap byte is marked once it has been written. Frees of
+/// addresses that were not allocated, accesses outside of the allocated or to freed blocks, and
+/// reads of bytes that were never written are reported, as are the blocks still allocated when
+/// the program ends.
+///
+/// The builtin functions have no frame of their own, so for the instructions only they use the
+/// return address on top of the stack is recorded as their caller.
+pub struct Memcheck {
+    symbols: SymbolTable,
+    heap_start: u64,
+    heap_end: u64,
+    blocks: BTreeMap<u64, Block>,
+    /// Whether each heap byte was written since it was allocated.
+    initialized: Vec<bool>,
+    errors: Vec<MemcheckError>,
+    error_indices: HashMap<(ErrorKind, Vec<u64>), usize>,
+    /// The allocation being executed.
+    pending_allocation: Option<PendingAllocation>,
+}
+
+impl Memcheck {
+    pub fn new(symbols: SymbolTable) -> Memcheck {
+        Memcheck {
+            symbols,
+            heap_start: 0,
+            heap_end: 0,
+            blocks: BTreeMap::new(),
+            initialized: Vec::new(),
+            errors: Vec::new(),
+            error_indices: HashMap::new(),
+            pending_allocation: None,
+        }
+    }
+
+    /// The number of distinct errors found, counting repeated errors with the same call stack
+    /// once.
+    pub fn error_count(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// A human-readable list of the errors and of the blocks that were never freed, grouped by
+    /// the call stack that allocated them.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for error in &self.errors {
+            write!(report, "{}: {}", error.kind.description(), error.details).unwrap();
+            if error.count > 1 {
+                write!(report, " ({} times)", error.count).unwrap();
+            }
+            writeln!(report).unwrap();
+            self.write_stack(&mut report, &error.stack);
+            for (event, stack) in &error.block_stacks {
+                writeln!(report, "  Block {} at", event).unwrap();
+                self.write_stack(&mut report, stack);
+            }
+        }
+
+        let mut leaks: HashMap<&[u64], (u64, u64)> = HashMap::new();
+        for block in self
+            .blocks
+            .values()
+            .filter(|block| block.freed_at.is_none())
+        {
+            let leak = leaks.entry(&block.allocated_at).or_default();
+            leak.0 += 1;
+            leak.1 += block.size;
+        }
+        let mut leaks = leaks.into_iter().collect::<Vec<_>>();
+        leaks.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+        let leaked_blocks = leaks.iter().map(|(_, (blocks, _))| blocks).sum::<u64>();
+        let leaked_bytes = leaks.iter().map(|(_, (_, bytes))| bytes).sum::<u64>();
+        writeln!(
+            report,
+            "Memcheck: {} errors, {} bytes leaked in {} blocks",
+            self.errors.len(),
+            leaked_bytes,
+            leaked_blocks
+        )
+        .unwrap();
+        for (stack, (blocks, bytes)) in leaks {
+            writeln!(report, "{} bytes in {} blocks allocated at", bytes, blocks).unwrap();
+            self.write_stack(&mut report, stack);
+        }
+        report
+    }
+
+    fn write_stack(&self, report: &mut String, stack: &[u64]) {
+        for address in stack {
+            writeln!(report, "    {}", self.describe_address(*address)).unwrap();
+        }
+    }
+
+    fn describe_address(&self, address: u64) -> String {
+        match self.symbols.symbolize(address) {
+            Some((label, 0)) => format!("{} <{}>", address, label),
+            Some((label, offset)) => format!("{} <{}+{}>", address, label, offset),
+            None => address.to_string(),
+        }
+    }
+
+    /// The call stack of the instruction at `pc`, found by following the saved base pointers.
+    fn call_stack(pc: u64, instruction: &Instruction, memory: &Memory) -> Vec<u64> {
+        let mut stack = vec![pc];
+        let read = |address: u64| memory.read_u64_le(address as usize, DATA_SIZE_64).ok();
+        if is_builtin_instruction(instruction) {
+            stack.extend(read(memory.registers[constants::SP]));
+        }
+        let mut bp = memory.registers[constants::BP];
+        while stack.len() < STACK_DEPTH {
+            let (Some(next_bp), Some(return_address)) = (read(bp), read(bp.wrapping_add(8))) else {
+                break;
+            };
+            stack.push(return_address);
+            if next_bp <= bp {
+                break;
+            }
+            bp = next_bp;
+        }
+        stack
+    }
+
+    fn report_error(
+        &mut self,
+        kind: ErrorKind,
+        stack: Vec<u64>,
+        details: String,
+        block: Option<u64>,
+    ) {
+        let key = (kind, stack);
+        if let Some(&index) = self.error_indices.get(&key) {
+            self.errors[index].count += 1;
+            return;
+        }
+        let mut block_stacks = Vec::new();
+        if let Some(block) = block.and_then(|start| self.blocks.get(&start)) {
+            block_stacks.push(("allocated", block.allocated_at.clone()));
+            block_stacks.extend(block.freed_at.clone().map(|stack| ("freed", stack)));
+        }
+        self.errors.push(MemcheckError {
+            kind,
+            stack: key.1.clone(),
+            details,
+            block_stacks,
+            count: 1,
+        });
+        self.error_indices.insert(key, self.errors.len() - 1);
+    }
+
+    /// The block containing `address`, whether it is allocated or was freed.
+    fn block_at(&self, address: u64) -> Option<(u64, &Block)> {
+        let (&start, block) = self.blocks.range(..=address).next_back()?;
+        (address < start + block.size.max(1)).then_some((start, block))
+    }
+
+    fn heap_index(&self, address: u64) -> Option<usize> {
+        (self.heap_start..self.heap_end)
+            .contains(&address)
+            .then(|| (address - self.heap_start) as usize)
+    }
+
+    fn is_initialized(&self, address: u64) -> bool {
+        self.heap_index(address)
+            .is_none_or(|index| self.initialized[index])
+    }
+
+    fn set_initialized(&mut self, address: u64, size: u64, initialized: bool) {
+        for address in address..address.saturating_add(size) {
+            if let Some(index) = self.heap_index(address) {
+                self.initialized[index] = initialized;
+            }
+        }
+    }
+
+    /// Checks an access of `size` bytes at `address`, returning whether it stays within an
+    /// allocated block.
+    fn check_access(
+        &mut self,
+        pc: u64,
+        instruction: &Instruction,
+        memory: &Memory,
+        address: u64,
+        size: u64,
+        access: Access,
+    ) -> bool {
+        let end = address.saturating_add(size);
+        if size == 0 || end <= self.heap_start || address >= self.heap_end {
+            return true;
+        }
+        let action = format!("{} of {} bytes at {}", access.description(), size, address);
+        let (kind, details, block) = match self.block_at(address) {
+            Some((start, block)) if block.freed_at.is_some() => (
+                ErrorKind::UseAfterFree,
+                format!(
+                    "{}, {} bytes into a freed block of {} bytes",
+                    action,
+                    address - start,
+                    block.size
+                ),
+                Some(start),
+            ),
+            Some((start, block)) if end > start + block.size => (
+                ErrorKind::OutOfBounds,
+                format!(
+                    "{}, past the end of a block of {} bytes",
+                    action, block.size
+                ),
+                Some(start),
+            ),
+            Some((start, block)) => {
+                if access != Access::Read || (address..end).all(|a| self.is_initialized(a)) {
+                    return true;
+                }
+                (
+                    ErrorKind::UninitializedRead,
+                    format!("{}, in a block of {} bytes", action, block.size),
+                    Some(start),
+                )
+            }
+            None => (
+                ErrorKind::OutOfBounds,
+                format!("{}, outside of any allocated block", action),
+                None,
+            ),
+        };
+        let stack = Memcheck::call_stack(pc, instruction, memory);
+        self.report_error(kind, stack, details, block);
+        kind == ErrorKind::UninitializedRead
+    }
+
+    fn check_write(
+        &mut self,
+        pc: u64,
+        instruction: &Instruction,
+        memory: &Memory,
+        address: u64,
+        size: u64,
+    ) {
+        if self.check_access(pc, instruction, memory, address, size, Access::Write) {
+            self.set_initialized(address, size, true);
+        }
+    }
+
+    /// Checks a read of the null terminated string at `address`.
+    fn check_string(&mut self, pc: u64, instruction: &Instruction, memory: &Memory, address: u64) {
+        let length = memory
+            .read_string(address as usize)
+            .map_or(0, |string| string.len());
+        let size = length as u64 + 1;
+        self.check_access(pc, instruction, memory, address, size, Access::Read);
+    }
+
+    fn check_free(&mut self, pc: u64, instruction: &Instruction, memory: &Memory, address: u64) {
+        let (kind, details, block) = match self.block_at(address) {
+            Some((start, block)) if start == address => {
+                if block.freed_at.is_none() {
+                    return;
+                }
+                (
+                    ErrorKind::DoubleFree,
+                    format!(
+                        "{} is a block of {} bytes that was already freed",
+                        address, block.size
+                    ),
+                    Some(start),
+                )
+            }
+            Some((start, block)) if block.freed_at.is_none() => (
+                ErrorKind::InvalidFree,
+                format!(
+                    "{} points {} bytes into a block of {} bytes",
+                    address,
+                    address - start,
+                    block.size
+                ),
+                Some(start),
+            ),
+            _ => (
+                ErrorKind::InvalidFree,
+                format!("{} was not returned by malloc", address),
+                None,
+            ),
+        };
+        let stack = Memcheck::call_stack(pc, instruction, memory);
+        self.report_error(kind, stack, details, block);
+    }
+
+    fn allocated(&mut self, address: u64, size: u64, allocated_at: Vec<u64>) {
+        let end = address.saturating_add(size.max(1));
+        let overlapping = self
+            .blocks
+            .range(..end)
+            .rev()
+            .take_while(|(&start, block)| start + block.size.max(1) > address)
+            .map(|(&start, _)| start)
+            .collect::<Vec<_>>();
+        for start in overlapping {
+            self.blocks.remove(&start);
+        }
+        // The block at the same address may have been empty and freed.
+        self.blocks.remove(&address);
+        self.set_initialized(address, size, false);
+        self.blocks.insert(
+            address,
+            Block {
+                size,
+                allocated_at,
+                freed_at: None,
+            },
+        );
+    }
+}
+
+impl Observer for Memcheck {
+    fn attach(&mut self, heap: &Heap, _memory: &Memory) {
+        self.heap_start = heap.offset as u64;
+        self.heap_end = (heap.offset + heap.size) as u64;
+        self.initialized = vec![false; heap.size];
+    }
+
+    fn before_instruction(&mut self, pc: u64, instruction: &Instruction, memory: &Memory) {
+        let registers = &memory.registers;
+        let register = |register: u8| registers[register as usize];
+        let resolve = |address: &Address| address.resolve(registers);
+        match instruction {
+            Instruction::Load { size, address, .. } => {
+                let address = resolve(address);
+                self.check_access(pc, instruction, memory, address, *size as u64, Access::Read);
+            }
+            Instruction::Store { size, address, .. } => {
+                self.check_write(pc, instruction, memory, resolve(address), *size as u64);
+            }
+            Instruction::WriteStdout {
+                register: size,
+                address,
+            } => {
+                let (address, size) = (resolve(address), register(*size));
+                self.check_access(pc, instruction, memory, address, size, Access::Read);
+            }
+            Instruction::FileWrite {
+                size_register,
+                address,
+                ..
+            } => {
+                let (address, size) = (resolve(address), register(*size_register));
+                self.check_access(pc, instruction, memory, address, size, Access::Read);
+            }
+            Instruction::PushMem {
+                register: size,
+                address,
+            } => {
+                let (address, size) = (resolve(address), register(*size));
+                self.check_access(pc, instruction, memory, address, size, Access::Copy);
+            }
+            Instruction::ReadStdin {
+                register: size,
+                address,
+            }
+            | Instruction::PopMem {
+                register: size,
+                address,
+            }
+            | Instruction::PeekMem {
+                register: size,
+                address,
+            } => {
+                self.check_write(pc, instruction, memory, resolve(address), register(*size));
+            }
+            Instruction::FileRead {
+                size_register,
+                address,
+                ..
+            }
+            | Instruction::ArgRead {
+                size_register,
+                address,
+                ..
+            }
+            | Instruction::MemSet {
+                size_register,
+                address,
+                ..
+            } => {
+                let (address, size) = (resolve(address), register(*size_register));
+                self.check_write(pc, instruction, memory, address, size);
+            }
+            Instruction::FileOpen { address, .. }
+            | Instruction::FileOpenMode { address, .. }
+            | Instruction::FileRemove { address, .. } => {
+                self.check_string(pc, instruction, memory, resolve(address));
+            }
+            Instruction::FileRename { from, to, .. } => {
+                self.check_string(pc, instruction, memory, resolve(from));
+                self.check_string(pc, instruction, memory, resolve(to));
+            }
+            Instruction::MemCopy {
+                size_register,
+                dest,
+                src,
+            } => {
+                let (dest, src, size) = (resolve(dest), resolve(src), register(*size_register));
+                self.check_access(pc, instruction, memory, src, size, Access::Copy);
+                if self.check_access(pc, instruction, memory, dest, size, Access::Write) {
+                    let initialized = (src..src.saturating_add(size))
+                        .map(|address| self.is_initialized(address))
+                        .collect::<Vec<_>>();
+                    for (address, initialized) in (dest..).zip(initialized) {
+                        self.set_initialized(address, 1, initialized);
+                    }
+                }
+            }
+            Instruction::Alloc { size_register, .. } => {
+                let stack = Memcheck::call_stack(pc, instruction, memory);
+                self.pending_allocation = Some(PendingAllocation {
+                    size: register(*size_register),
+                    stack,
+                    old_address: 0,
+                });
+            }
+            Instruction::Realloc {
+                size_register,
+                address_register,
+            } => {
+                let old_address = register(*address_register);
+                if old_address != 0 {
+                    self.check_free(pc, instruction, memory, old_address);
+                }
+                let stack = Memcheck::call_stack(pc, instruction, memory);
+                self.pending_allocation = Some(PendingAllocation {
+                    size: register(*size_register),
+                    stack,
+                    old_address,
+                });
+            }
+            Instruction::Free { register: address } => {
+                self.check_free(pc, instruction, memory, register(*address));
+            }
+            _ => {}
+        }
+    }
+
+    fn after_instruction(&mut self, pc: u64, instruction: &Instruction, memory: &Memory) {
+        match instruction {
+            Instruction::Alloc {
+                address_register, ..
+            }
+            | Instruction::Realloc {
+                address_register, ..
+            } => {
+                let address = memory.registers[*address_register as usize];
+                let Some(allocation) = self.pending_allocation.take() else {
+                    return;
+                };
+                let heap_end = memory.data.len() as u64;
+                if heap_end > self.heap_end {
+                    self.initialized
+                        .resize((heap_end - self.heap_start) as usize, false);
+                    self.heap_end = heap_end;
+                }
+                // The contents kept by a `realloc` stay initialized where they were.
+                let old_address = allocation.old_address;
+                let kept = match self.blocks.get_mut(&old_address) {
+                    Some(block) if old_address != 0 => {
+                        if old_address != address {
+                            block.freed_at = Some(allocation.stack.clone());
+                        }
+                        let end = old_address + block.size.min(allocation.size);
+                        (old_address..end)
+                            .map(|address| self.is_initialized(address))
+                            .collect()
+                    }
+                    _ => Vec::new(),
+                };
+                self.allocated(address, allocation.size, allocation.stack);
+                for (address, initialized) in (address..).zip(kept) {
+                    self.set_initialized(address, 1, initialized);
+                }
+            }
+            Instruction::Free { register } => {
+                let address = memory.registers[*register as usize];
+                let freed_at = Memcheck::call_stack(pc, instruction, memory);
+                if let Some(block) = self.blocks.get_mut(&address) {
+                    block.freed_at = Some(freed_at);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether the compiler only emits `instruction` in the builtin functions, which are called
+/// without setting up a frame.
+fn is_builtin_instruction(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::ReadStdin { .. }
+            | Instruction::WriteStdout { .. }
+            | Instruction::Alloc { .. }
+            | Instruction::Free { .. }
+            | Instruction::Realloc { .. }
+            | Instruction::FileOpen { .. }
+            | Instruction::FileRead { .. }
+            | Instruction::FileWrite { .. }
+            | Instruction::FileOpenMode { .. }
+            | Instruction::FileSeek { .. }
+            | Instruction::FileTell { .. }
+            | Instruction::FileSize { .. }
+            | Instruction::FileRemove { .. }
+            | Instruction::FileRename { .. }
+            | Instruction::ArgCount { .. }
+            | Instruction::ArgLength { .. }
+            | Instruction::ArgRead { .. }
+            | Instruction::MemCopy { .. }
+            | Instruction::MemSet { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{VmConfig, VmTrap, VmTrapKind};
+    use lychee_compiler::assembler::assemble_source;
+
+    /// Runs `code` under memcheck, returning the result, the report and the symbols of the
+    /// program.
+    fn check(code: &str) -> (Result<i64, VmTrap>, String, SymbolTable) {
+        let program = assemble_source(&format!("memory 65536 8192 8192\nentry 0\n{}", code));
+        let mut checker = Memcheck::new(program.symbols.clone());
+        let result = crate::memcheck(&program, &VmConfig::default(), &mut checker);
+        (result, checker.report(), program.symbols)
+    }
+
+    #[test]
+    fn reports_double_frees() {
+        let (result, report, _) =
+            check("movi r1 16\nalloc r1 r0\nmov r2 r0\nfree r0\nfree r2\nexit\n");
+        let trap = result.unwrap_err();
+        let address = trap.registers[2];
+        assert_eq!(trap.kind, VmTrapKind::InvalidFree { address });
+        assert!(report.starts_with("Double free: "), "{}", report);
+        assert!(report.contains("Block freed at"), "{}", report);
+        assert!(report.contains("Memcheck: 1 errors, 0 bytes leaked in 0 blocks"));
+    }
+
+    #[test]
+    fn reports_leaks_and_out_of_bounds_accesses() {
+        // The store hits the free list links in the header of the second block, which are unused
+        // while it is allocated.
+        let (result, report, symbols) = check(
+            "movi r1 16\nalloc r1 r0\n_leak:\nalloc r1 r2\nstore #8 r1 [r0;32]\nfree r0\nmovi r0 0\nexit\n",
+        );
+        assert_eq!(result.unwrap(), 0);
+        assert!(report.starts_with("Out of bounds access: "), "{}", report);
+        assert!(
+            report.contains("Memcheck: 1 errors, 16 bytes leaked in 1 blocks"),
+            "{}",
+            report
+        );
+        let leak = symbols.find("_leak")[0];
+        assert!(
+            report.contains(&format!(
+                "16 bytes in 1 blocks allocated at\n    {} <_leak>\n",
+                leak
+            )),
+            "{}",
+            report
+        );
+    }
+}
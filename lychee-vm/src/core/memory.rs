@@ -1,131 +1,184 @@
 use crate::core::constants;
-
-#[derive(Debug)]
+use crate::core::files::{FileTable, HostFileSystem};
+use crate::core::layout::MemoryLayout;
+use crate::core::trap::{VmResult, VmTrapKind};
+use lychee_compiler::{FilePolicy, VmConfig};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::Serialize;
+use std::io::{Read, Write};
+
+/// The flags set by the arithmetic instructions. `carry` is the unsigned overflow of an
+/// addition or the borrow of a subtraction, and `overflow` the signed overflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Flags {
     pub zero: bool,
     pub positive: bool,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    /// Whether the result was negative, before it overflowed.
+    pub fn negative(&self) -> bool {
+        (!self.positive && !self.zero) != self.overflow
+    }
 }
 
 pub struct Memory {
     pub(crate) data: Vec<u8>,
     pub(crate) registers: [u64; 16],
     pub(crate) flags: Flags,
-    pub(crate) files: Vec<Option<std::fs::File>>,
+    pub(crate) files: FileTable,
+    /// The policy the file system was created with, which is kept in snapshots.
+    pub(crate) file_policy: FilePolicy,
+    pub(crate) stdin: Box<dyn Read>,
+    pub(crate) stdout: Box<dyn Write>,
+    /// The size of the code at the start of memory, which the program may not write to.
+    pub(crate) code_size: usize,
+    pub(crate) stack_limit: u64,
+    /// The initial stack pointer, the end of the stack region.
+    pub(crate) stack_end: u64,
+    pub(crate) call_depth: u64,
+    pub(crate) rng: ChaCha12Rng,
+    /// Whether signed overflow and out of range shifts trap instead of wrapping around.
+    pub(crate) checked_arithmetic: bool,
+    /// The command line arguments of the program.
+    pub(crate) args: Vec<String>,
 }
 
 impl Memory {
-    pub fn new(size: usize, program: Vec<u8>) -> Memory {
+    pub fn new(layout: &MemoryLayout, program: Vec<u8>, vm_config: &VmConfig) -> Memory {
+        let size = layout.memory_size;
         let mut memory = Memory {
             data: vec![0; size],
             registers: [0; 16],
-            flags: Flags {
-                zero: false,
-                positive: false,
+            flags: Flags::default(),
+            files: FileTable::new(Box::new(HostFileSystem::new(&vm_config.files))),
+            file_policy: vm_config.files.clone(),
+            stdin: Box::new(std::io::stdin()),
+            stdout: Box::new(std::io::stdout()),
+            code_size: 0,
+            stack_limit: layout.stack_start as u64,
+            stack_end: layout.stack_end as u64,
+            call_depth: 0,
+            rng: match vm_config.seed {
+                Some(seed) => ChaCha12Rng::seed_from_u64(seed),
+                None => ChaCha12Rng::from_entropy(),
             },
-            files: Vec::new(),
+            checked_arithmetic: vm_config.checked_arithmetic,
+            args: vm_config.args.clone(),
         };
         memory.data[..program.len()].copy_from_slice(&program);
 
-        memory.registers[constants::SP] = size as u64;
-        memory.registers[constants::BP] = size as u64;
+        memory.registers[constants::SP] = layout.stack_end as u64;
+        memory.registers[constants::BP] = layout.stack_end as u64;
 
         memory
     }
 
-    pub fn read_u64_le(&self, address: usize, data_size: u8) -> u64 {
-        match data_size {
-            1 => self.data[address] as u64,
-            2 => u16::from_le_bytes([self.data[address], self.data[address + 1]]) as u64,
-            4 => u32::from_le_bytes([
-                self.data[address],
-                self.data[address + 1],
-                self.data[address + 2],
-                self.data[address + 3],
-            ]) as u64,
-            8 => u64::from_le_bytes([
-                self.data[address],
-                self.data[address + 1],
-                self.data[address + 2],
-                self.data[address + 3],
-                self.data[address + 4],
-                self.data[address + 5],
-                self.data[address + 6],
-                self.data[address + 7],
-            ]),
-            _ => panic!("Invalid data size: {}", data_size),
+    /// The range of `bytes` bytes at `address`, or a trap if it does not fit in memory.
+    pub(crate) fn range(&self, address: usize, bytes: usize) -> VmResult<std::ops::Range<usize>> {
+        match address.checked_add(bytes) {
+            Some(end) if end <= self.data.len() => Ok(address..end),
+            _ => Err(VmTrapKind::MemoryAccessViolation {
+                address: address as u64,
+                size: bytes as u64,
+            }),
         }
     }
 
-    pub fn read_i64_le(&self, address: usize, data_size: u8) -> i64 {
-        match data_size {
-            1 => self.data[address] as i8 as i64,
-            2 => i16::from_le_bytes([self.data[address], self.data[address + 1]]) as i64,
-            4 => i32::from_le_bytes([
-                self.data[address],
-                self.data[address + 1],
-                self.data[address + 2],
-                self.data[address + 3],
-            ]) as i64,
-            8 => i64::from_le_bytes([
-                self.data[address],
-                self.data[address + 1],
-                self.data[address + 2],
-                self.data[address + 3],
-                self.data[address + 4],
-                self.data[address + 5],
-                self.data[address + 6],
-                self.data[address + 7],
-            ]),
-            _ => panic!("Invalid data size: {}", data_size),
+    /// Like `range`, but also traps if the range overlaps the code, which is read-only.
+    pub(crate) fn writable_range(
+        &self,
+        address: usize,
+        bytes: usize,
+    ) -> VmResult<std::ops::Range<usize>> {
+        let range = self.range(address, bytes)?;
+        if bytes > 0 && address < self.code_size {
+            return Err(VmTrapKind::CodeWrite {
+                address: address as u64,
+            });
         }
+        Ok(range)
+    }
+
+    pub fn read_byte(&self, address: usize) -> VmResult<u8> {
+        let range = self.range(address, 1)?;
+        Ok(self.data[range.start])
     }
 
-    pub fn write_u64_le(&mut self, address: usize, value: u64, data_size: u8) {
-        let bytes = match data_size {
-            1 => (value as u8).to_le_bytes()[0..1].to_vec(),
-            2 => (value as u16).to_le_bytes()[0..2].to_vec(),
-            4 => (value as u32).to_le_bytes()[0..4].to_vec(),
-            8 => value.to_le_bytes().to_vec(),
+    pub fn read_u64_le(&self, address: usize, data_size: u8) -> VmResult<u64> {
+        let range = self.range(address, data_size as usize)?;
+        let bytes = &self.data[range];
+        // Fixed-size conversions avoid a variable-length copy on every load.
+        Ok(match data_size {
+            1 => bytes[0] as u64,
+            2 => u16::from_le_bytes(bytes.try_into().unwrap()) as u64,
+            4 => u32::from_le_bytes(bytes.try_into().unwrap()) as u64,
+            8 => u64::from_le_bytes(bytes.try_into().unwrap()),
             _ => panic!("Invalid data size: {}", data_size),
-        };
-        self.data[address..address + bytes.len()].copy_from_slice(&bytes);
+        })
     }
 
-    pub fn write_i64_le(&mut self, address: usize, value: i64, data_size: u8) {
-        let bytes = match data_size {
-            1 => (value as i8).to_le_bytes()[0..1].to_vec(),
-            2 => (value as i16).to_le_bytes()[0..2].to_vec(),
-            4 => (value as i32).to_le_bytes()[0..4].to_vec(),
-            8 => value.to_le_bytes().to_vec(),
+    pub fn read_i64_le(&self, address: usize, data_size: u8) -> VmResult<i64> {
+        let value = self.read_u64_le(address, data_size)?;
+        Ok(match data_size {
+            1 => value as i8 as i64,
+            2 => value as i16 as i64,
+            4 => value as i32 as i64,
+            8 => value as i64,
             _ => panic!("Invalid data size: {}", data_size),
-        };
-        self.data[address..address + bytes.len()].copy_from_slice(&bytes);
+        })
+    }
+
+    pub fn write_u64_le(&mut self, address: usize, value: u64, data_size: u8) -> VmResult<()> {
+        let range = self.writable_range(address, data_size as usize)?;
+        let bytes = &mut self.data[range];
+        match data_size {
+            1 => bytes[0] = value as u8,
+            2 => bytes.copy_from_slice(&(value as u16).to_le_bytes()),
+            4 => bytes.copy_from_slice(&(value as u32).to_le_bytes()),
+            8 => bytes.copy_from_slice(&value.to_le_bytes()),
+            _ => panic!("Invalid data size: {}", data_size),
+        }
+        Ok(())
     }
 
-    pub fn read_bytes(&self, address: usize, bytes: usize) -> Vec<u8> {
-        self.data[address..address + bytes].to_vec()
+    pub fn write_i64_le(&mut self, address: usize, value: i64, data_size: u8) -> VmResult<()> {
+        self.write_u64_le(address, value as u64, data_size)
     }
 
-    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) {
-        self.data[address..address + bytes.len()].copy_from_slice(bytes);
+    pub fn read_bytes(&self, address: usize, bytes: usize) -> VmResult<Vec<u8>> {
+        let range = self.range(address, bytes)?;
+        Ok(self.data[range].to_vec())
     }
 
-    pub fn memory_copy(&mut self, src: usize, dest: usize, bytes: usize) {
-        self.data.copy_within(src..src + bytes, dest);
+    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> VmResult<()> {
+        let range = self.writable_range(address, bytes.len())?;
+        self.data[range].copy_from_slice(bytes);
+        Ok(())
     }
 
-    pub fn memory_set(&mut self, address: usize, value: u8, bytes: usize) {
-        for i in 0..bytes {
-            self.data[address + i] = value;
-        }
+    pub fn memory_copy(&mut self, src: usize, dest: usize, bytes: usize) -> VmResult<()> {
+        let src_range = self.range(src, bytes)?;
+        self.writable_range(dest, bytes)?;
+        self.data.copy_within(src_range, dest);
+        Ok(())
+    }
+
+    pub fn memory_set(&mut self, address: usize, value: u8, bytes: usize) -> VmResult<()> {
+        let range = self.writable_range(address, bytes)?;
+        self.data[range].fill(value);
+        Ok(())
     }
 
-    pub fn read_string(&self, address: usize) -> String {
+    pub fn read_string(&self, address: usize) -> VmResult<String> {
         let mut i = address;
-        while self.data[i] != 0 {
+        while self.read_byte(i)? != 0 {
             i += 1;
         }
-        String::from_utf8(self.data[address..i].to_vec()).unwrap()
+        Ok(String::from_utf8_lossy(&self.data[address..i]).into_owned())
     }
 
     pub fn print_registers(&self) {
@@ -133,9 +186,10 @@ impl Memory {
     }
 
     pub fn print_stack(&self) {
-        let sp = self.registers[constants::SP] as usize;
-        let bp = self.registers[constants::BP] as usize;
-        let stack = self.data[sp..].iter().rev().collect::<Vec<&u8>>();
+        let stack_end = self.stack_end as usize;
+        let sp = (self.registers[constants::SP] as usize).min(stack_end);
+        let bp = (self.registers[constants::BP] as usize).clamp(sp, stack_end);
+        let stack = self.data[sp..stack_end].iter().rev().collect::<Vec<&u8>>();
         println!("Stack: {:?}", stack);
         let stack_frame = &self.data[sp..bp].iter().rev().collect::<Vec<&u8>>();
         println!("Stack frame: {:?}", stack_frame);
@@ -0,0 +1,37 @@
+use crate::core::decoder::Instruction;
+use crate::core::heap::Heap;
+use crate::core::memory::Memory;
+
+/// Hooks called by the run loop around every executed instruction. All methods default to doing
+/// nothing, and the run loop is generic over the observer, so unused hooks cost nothing.
+pub trait Observer {
+    /// Called once before the first instruction is executed.
+    fn attach(&mut self, _heap: &Heap, _memory: &Memory) {}
+
+    /// Called before `instruction` at `pc` is executed.
+    fn before_instruction(&mut self, _pc: u64, _instruction: &Instruction, _memory: &Memory) {}
+
+    /// Called after `instruction` at `pc` was executed without faulting.
+    fn after_instruction(&mut self, _pc: u64, _instruction: &Instruction, _memory: &Memory) {}
+}
+
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// Prints every instruction together with the registers and stack after it was executed.
+pub struct DebugPrinter;
+
+impl Observer for DebugPrinter {
+    fn before_instruction(&mut self, pc: u64, instruction: &Instruction, _memory: &Memory) {
+        println!("PC: {}, {:?}", pc, instruction);
+    }
+
+    fn after_instruction(&mut self, _pc: u64, instruction: &Instruction, memory: &Memory) {
+        if *instruction != Instruction::Exit {
+            memory.print_registers();
+            memory.print_stack();
+            println!();
+        }
+    }
+}
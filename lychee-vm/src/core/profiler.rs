@@ -0,0 +1,242 @@
+use crate::core::constants;
+use crate::core::decoder::Instruction;
+use crate::core::memory::Memory;
+use crate::core::observer::Observer;
+use lychee_compiler::SymbolTable;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const REPORT_TOP_ADDRESSES: usize = 20;
+
+/// A node of the call tree. The root is the program entry point. Direct recursion stays in the
+/// same node, so deeply recursive programs do not produce deep call trees.
+struct CallNode {
+    function: u64,
+    parent: usize,
+    children: HashMap<u64, usize>,
+    self_count: u64,
+}
+
+struct Frame {
+    function: u64,
+    entry_count: u64,
+    caller_node: usize,
+}
+
+#[derive(Default)]
+struct FunctionProfile {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+/// Counts executed instructions per opcode and address, and attributes them to functions by
+/// following `call` and `ret`.
+pub struct Profiler {
+    symbols: SymbolTable,
+    total: u64,
+    opcode_counts: HashMap<&'static str, u64>,
+    pc_counts: HashMap<u64, u64>,
+    functions: HashMap<u64, FunctionProfile>,
+    nodes: Vec<CallNode>,
+    current_node: usize,
+    frames: Vec<Frame>,
+    /// How often each function is on the frame stack, so recursion is only counted once.
+    active_frames: HashMap<u64, u32>,
+}
+
+impl Profiler {
+    pub fn new(symbols: SymbolTable) -> Profiler {
+        let mut profiler = Profiler {
+            symbols,
+            total: 0,
+            opcode_counts: HashMap::new(),
+            pc_counts: HashMap::new(),
+            functions: HashMap::new(),
+            nodes: vec![CallNode {
+                function: 0,
+                parent: 0,
+                children: HashMap::new(),
+                self_count: 0,
+            }],
+            current_node: 0,
+            frames: Vec::new(),
+            active_frames: HashMap::new(),
+        };
+        profiler.enter(0);
+        profiler
+    }
+
+    fn enter(&mut self, function: u64) {
+        self.functions.entry(function).or_default().calls += 1;
+        *self.active_frames.entry(function).or_default() += 1;
+        self.frames.push(Frame {
+            function,
+            entry_count: self.total,
+            caller_node: self.current_node,
+        });
+    }
+
+    fn leave(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        self.current_node = frame.caller_node;
+        let active = self.active_frames.get_mut(&frame.function).unwrap();
+        *active -= 1;
+        if *active == 0 {
+            let profile = self.functions.get_mut(&frame.function).unwrap();
+            profile.inclusive += self.total - frame.entry_count;
+        }
+    }
+
+    /// Closes the frames that are still open when the program exits or faults.
+    pub fn finish(&mut self) {
+        while !self.frames.is_empty() {
+            self.leave();
+        }
+    }
+
+    fn function_name(&self, function: u64) -> String {
+        match self.symbols.symbolize(function) {
+            Some((label, 0)) => SymbolTable::function_path(label)
+                .unwrap_or(label)
+                .to_string(),
+            _ if function == 0 => "[entry]".to_string(),
+            _ => format!("0x{:X}", function),
+        }
+    }
+
+    fn describe_address(&self, address: u64) -> String {
+        match self.symbols.symbolize(address) {
+            Some((label, 0)) => format!("{} <{}>", address, label),
+            Some((label, offset)) => format!("{} <{}+{}>", address, label, offset),
+            None => address.to_string(),
+        }
+    }
+
+    /// A human-readable summary of the collected counts.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+        writeln!(report, "Executed instructions: {}", self.total).unwrap();
+
+        let mut opcodes = self.opcode_counts.iter().collect::<Vec<_>>();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(report, "\nInstructions by opcode:").unwrap();
+        for (opcode, count) in opcodes {
+            writeln!(
+                report,
+                "{:>14} {:>6.2}%  {}",
+                count,
+                percent(*count),
+                opcode
+            )
+            .unwrap();
+        }
+
+        let mut addresses = self.pc_counts.iter().collect::<Vec<_>>();
+        addresses.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(report, "\nHottest addresses:").unwrap();
+        for (address, count) in addresses.into_iter().take(REPORT_TOP_ADDRESSES) {
+            let description = self.describe_address(*address);
+            writeln!(
+                report,
+                "{:>14} {:>6.2}%  {}",
+                count,
+                percent(*count),
+                description
+            )
+            .unwrap();
+        }
+
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        writeln!(report, "\nFunctions:").unwrap();
+        writeln!(
+            report,
+            "{:>14} {:>14} {:>8} {:>14} {:>8}  function",
+            "calls", "inclusive", "", "exclusive", ""
+        )
+        .unwrap();
+        for (function, profile) in functions {
+            writeln!(
+                report,
+                "{:>14} {:>14} {:>7.2}% {:>14} {:>7.2}%  {}",
+                profile.calls,
+                profile.inclusive,
+                percent(profile.inclusive),
+                profile.exclusive,
+                percent(profile.exclusive),
+                self.function_name(*function)
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    /// The call stacks in the folded format understood by flamegraph tools, one
+    /// `outer;inner count` line per call stack with its exclusive instruction count.
+    pub fn folded_stacks(&self) -> String {
+        let mut lines = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.self_count == 0 {
+                continue;
+            }
+            let mut path = vec![self.function_name(node.function)];
+            let mut current = index;
+            while current != 0 {
+                current = self.nodes[current].parent;
+                path.push(self.function_name(self.nodes[current].function));
+            }
+            path.reverse();
+            lines.push(format!("{} {}\n", path.join(";"), node.self_count));
+        }
+        lines.sort();
+        lines.concat()
+    }
+}
+
+impl Observer for Profiler {
+    fn after_instruction(&mut self, pc: u64, instruction: &Instruction, memory: &Memory) {
+        self.total += 1;
+        *self
+            .opcode_counts
+            .entry(instruction.mnemonic())
+            .or_default() += 1;
+        *self.pc_counts.entry(pc).or_default() += 1;
+        self.nodes[self.current_node].self_count += 1;
+        if let Some(frame) = self.frames.last() {
+            self.functions.get_mut(&frame.function).unwrap().exclusive += 1;
+        }
+
+        match instruction {
+            Instruction::Call { .. } => {
+                let function = memory.registers[constants::PC];
+                self.enter(function);
+                if self.nodes[self.current_node].function == function {
+                    return;
+                }
+                let next_node = self.nodes.len();
+                let node = *self.nodes[self.current_node]
+                    .children
+                    .entry(function)
+                    .or_insert(next_node);
+                if node == next_node {
+                    self.nodes.push(CallNode {
+                        function,
+                        parent: self.current_node,
+                        children: HashMap::new(),
+                        self_count: 0,
+                    });
+                }
+                self.current_node = node;
+            }
+            // The entry frame is never left, so a stray `ret` cannot unbalance the stack.
+            Instruction::Ret if self.frames.len() > 1 => {
+                self.leave();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::core::files::FileMode;
+use crate::core::heap::HeapStats;
+use crate::core::layout::MemoryLayout;
+use crate::core::memory::Flags;
+use lychee_compiler::{FilePolicy, VmConfig};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::path::PathBuf;
+
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"LYSN";
+const SNAPSHOT_VERSION: u32 = 8;
+/// Memory is stored in pages, so the large zeroed regions of the stack and the heap take a
+/// single byte each.
+const PAGE_SIZE: usize = 0x1000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    Malformed,
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a lychee snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported snapshot version {}, expected version {}",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Malformed => write!(f, "malformed snapshot"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// The path, mode and position of a file open when a snapshot was taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileSnapshot {
+    pub path: String,
+    pub mode: FileMode,
+    pub offset: u64,
+}
+
+/// The complete state of a machine, from which it can be resumed later, possibly in another
+/// process. This includes the state of the random number generator, so a program resumed from
+/// a snapshot sees the same random numbers as the run the snapshot was taken from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) layout: MemoryLayout,
+    /// The size of the code section, which is decoded up front when resuming.
+    pub(crate) code_size: u64,
+    pub(crate) data: Vec<u8>,
+    pub(crate) registers: [u64; 16],
+    pub(crate) flags: Flags,
+    pub(crate) call_depth: u64,
+    pub(crate) executed: u64,
+    /// The allocation counters of the heap. The live bytes and the free lists are recomputed
+    /// from the blocks in memory.
+    pub(crate) heap_stats: HeapStats,
+    pub(crate) rng: ChaCha12Rng,
+    pub(crate) files: Vec<Option<FileSnapshot>>,
+    /// The command line arguments, which a resumed program still sees.
+    pub(crate) args: Vec<String>,
+    pub(crate) file_policy: FilePolicy,
+    pub(crate) checked_arithmetic: bool,
+}
+
+impl Snapshot {
+    /// The number of instructions executed before the snapshot was taken.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// The settings the program was started with that still apply after resuming it: the file
+    /// policy, checked arithmetic and the command line arguments. `Vm::restore` takes them from
+    /// the configuration it is given, so a resumed program is only sandboxed as before if they
+    /// are passed on.
+    pub fn vm_config(&self) -> VmConfig {
+        VmConfig {
+            files: self.file_policy.clone(),
+            checked_arithmetic: self.checked_arithmetic,
+            args: self.args.clone(),
+            ..VmConfig::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend(SNAPSHOT_VERSION.to_le_bytes());
+        for value in [
+            self.layout.memory_size as u64,
+            self.layout.max_memory_size as u64,
+            self.layout.heap_offset as u64,
+            self.layout.heap_size as u64,
+            self.layout.stack_start as u64,
+            self.layout.stack_end as u64,
+            self.code_size,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        self.registers
+            .iter()
+            .for_each(|register| bytes.extend(register.to_le_bytes()));
+        let flags = &self.flags;
+        for flag in [flags.zero, flags.positive, flags.carry, flags.overflow] {
+            bytes.push(flag as u8);
+        }
+        bytes.extend(self.call_depth.to_le_bytes());
+        bytes.extend(self.executed.to_le_bytes());
+        bytes.extend(self.heap_stats.allocations.to_le_bytes());
+        bytes.extend(self.heap_stats.frees.to_le_bytes());
+        bytes.extend(self.heap_stats.peak_live_bytes.to_le_bytes());
+        bytes.extend(self.rng.get_seed());
+        bytes.extend(self.rng.get_stream().to_le_bytes());
+        bytes.extend(self.rng.get_word_pos().to_le_bytes());
+
+        bytes.extend((self.files.len() as u64).to_le_bytes());
+        for file in &self.files {
+            bytes.push(file.is_some() as u8);
+            if let Some(file) = file {
+                bytes.push(file.mode.code() as u8);
+                bytes.extend(file.offset.to_le_bytes());
+                write_string(&mut bytes, &file.path);
+            }
+        }
+        bytes.extend((self.args.len() as u64).to_le_bytes());
+        for arg in &self.args {
+            write_string(&mut bytes, arg);
+        }
+        let policy = &self.file_policy;
+        bytes.push(self.checked_arithmetic as u8);
+        bytes.push(policy.deny_all as u8);
+        bytes.push(policy.root.is_some() as u8);
+        if let Some(root) = &policy.root {
+            write_path(&mut bytes, root);
+        }
+        for paths in [&policy.read_only, &policy.read_write] {
+            bytes.extend((paths.len() as u64).to_le_bytes());
+            paths.iter().for_each(|path| write_path(&mut bytes, path));
+        }
+
+        for page in self.data.chunks(PAGE_SIZE) {
+            let is_zero = page.iter().all(|byte| *byte == 0);
+            bytes.push(!is_zero as u8);
+            if !is_zero {
+                bytes.extend(page);
+            }
+        }
+        bytes
+    }
+
+    /// Reads a snapshot written by `to_bytes`, failing if it is malformed or was written by an
+    /// incompatible version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.bytes(SNAPSHOT_MAGIC.len()) != Some(&SNAPSHOT_MAGIC[..]) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = reader
+            .bytes(4)
+            .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+            .ok_or(SnapshotError::Malformed)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        Self::read_state(&mut reader).ok_or(SnapshotError::Malformed)
+    }
+
+    fn read_state(reader: &mut Reader) -> Option<Snapshot> {
+        let layout = MemoryLayout {
+            memory_size: reader.usize()?,
+            max_memory_size: reader.usize()?,
+            heap_offset: reader.usize()?,
+            heap_size: reader.usize()?,
+            stack_start: reader.usize()?,
+            stack_end: reader.usize()?,
+        };
+        let code_size = reader.u64()?;
+        let mut registers = [0; 16];
+        for register in &mut registers {
+            *register = reader.u64()?;
+        }
+        let flags = Flags {
+            zero: reader.byte()? != 0,
+            positive: reader.byte()? != 0,
+            carry: reader.byte()? != 0,
+            overflow: reader.byte()? != 0,
+        };
+        let call_depth = reader.u64()?;
+        let executed = reader.u64()?;
+        let heap_stats = HeapStats {
+            allocations: reader.u64()?,
+            frees: reader.u64()?,
+            peak_live_bytes: reader.u64()?,
+            ..HeapStats::default()
+        };
+        let mut rng = ChaCha12Rng::from_seed(reader.bytes(32)?.try_into().unwrap());
+        rng.set_stream(reader.u64()?);
+        rng.set_word_pos(u128::from_le_bytes(reader.bytes(16)?.try_into().unwrap()));
+
+        let file_count = reader.usize()?;
+        let mut files = Vec::new();
+        for _ in 0..file_count {
+            if reader.byte()? == 0 {
+                files.push(None);
+                continue;
+            }
+            let mode = FileMode::from_code(reader.byte()? as u64)?;
+            let offset = reader.u64()?;
+            let path = reader.string()?;
+            files.push(Some(FileSnapshot { path, mode, offset }));
+        }
+        let arg_count = reader.usize()?;
+        let mut args = Vec::new();
+        for _ in 0..arg_count {
+            args.push(reader.string()?);
+        }
+        let checked_arithmetic = reader.byte()? != 0;
+        let deny_all = reader.byte()? != 0;
+        let root = match reader.byte()? {
+            0 => None,
+            _ => Some(PathBuf::from(reader.string()?)),
+        };
+        let mut path_lists = [Vec::new(), Vec::new()];
+        for paths in &mut path_lists {
+            for _ in 0..reader.usize()? {
+                paths.push(PathBuf::from(reader.string()?));
+            }
+        }
+        let [read_only, read_write] = path_lists;
+        let file_policy = FilePolicy {
+            deny_all,
+            root,
+            read_only,
+            read_write,
+        };
+
+        let mut data = Vec::new();
+        while data.len() < layout.memory_size {
+            let page_size = PAGE_SIZE.min(layout.memory_size - data.len());
+            match reader.byte()? {
+                0 => data.resize(data.len() + page_size, 0),
+                _ => data.extend(reader.bytes(page_size)?),
+            }
+        }
+        let heap_end = layout.heap_offset.checked_add(layout.heap_size);
+        if reader.position != reader.bytes.len()
+            || code_size > layout.stack_start as u64
+            || heap_end != Some(layout.memory_size)
+            || layout.memory_size > layout.max_memory_size
+        {
+            return None;
+        }
+
+        Some(Snapshot {
+            layout,
+            code_size,
+            data,
+            registers,
+            flags,
+            call_depth,
+            executed,
+            heap_stats,
+            rng,
+            files,
+            args,
+            file_policy,
+            checked_arithmetic,
+        })
+    }
+}
+
+/// Writes a string prefixed with its length.
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend((string.len() as u64).to_le_bytes());
+    bytes.extend(string.as_bytes());
+}
+
+/// Writes a path as a string. Paths that are not valid UTF-8 are converted lossily, so they no
+/// longer match the files they named.
+fn write_path(bytes: &mut Vec<u8>, path: &std::path::Path) {
+    write_string(bytes, &path.to_string_lossy());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(length)?;
+        let bytes = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.usize()?;
+        String::from_utf8(self.bytes(length)?.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryFileSystem, SharedBuffer, Vm, VmConfig};
+    use lychee_compiler::assembler::assemble_source;
+
+    /// Prints three random numbers.
+    const RANDOM: &str = "\
+memory 65536 8192 8192
+entry 0
+movi r0 8
+rand r1
+store #64 r1 4096
+write r0 4096
+rand r1
+store #64 r1 4096
+write r0 4096
+rand r1
+store #64 r1 4096
+write r0 4096
+exit
+";
+
+    fn start(output: &SharedBuffer) -> Vm {
+        let vm_config = VmConfig {
+            seed: Some(7),
+            files: FilePolicy {
+                root: Some(PathBuf::from("/tmp")),
+                read_only: vec![PathBuf::from("/tmp/input")],
+                ..FilePolicy::default()
+            },
+            checked_arithmetic: true,
+            ..VmConfig::default()
+        };
+        let mut vm = Vm::new(&assemble_source(RANDOM), &vm_config).unwrap();
+        vm.set_stdout(Box::new(output.clone()));
+        vm
+    }
+
+    #[test]
+    fn restored_program_runs_to_the_same_output() {
+        let expected = SharedBuffer::new();
+        start(&expected).run().unwrap();
+        assert_eq!(expected.contents().len(), 24);
+
+        // Taking a snapshot does not change how the program continues.
+        let output = SharedBuffer::new();
+        let mut vm = start(&output);
+        vm.run_until(|vm| vm.executed() == 5).unwrap();
+        let snapshot = vm.snapshot().unwrap();
+        vm.run().unwrap();
+        assert_eq!(output.contents(), expected.contents());
+
+        let bytes = snapshot.to_bytes();
+        let restored = Snapshot::from_bytes(&bytes).unwrap();
+        assert!(restored.to_bytes() == bytes);
+        assert_eq!(restored.rng, snapshot.rng);
+        assert_eq!(
+            restored.vm_config().files.read_only,
+            [PathBuf::from("/tmp/input")]
+        );
+        assert!(restored.vm_config().checked_arithmetic);
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Malformed)
+        );
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        assert_eq!(
+            Snapshot::from_bytes(&newer),
+            Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+        assert_eq!(
+            Snapshot::from_bytes(b"LYSM"),
+            Err(SnapshotError::NotASnapshot)
+        );
+
+        let resumed_output = SharedBuffer::new();
+        let file_system = Box::new(MemoryFileSystem::new());
+        let mut resumed = Vm::restore(&restored, &restored.vm_config(), file_system).unwrap();
+        resumed.set_stdout(Box::new(resumed_output.clone()));
+        assert_eq!(resumed.run().unwrap(), 8);
+        assert_eq!(resumed_output.contents(), expected.contents()[8..]);
+    }
+}
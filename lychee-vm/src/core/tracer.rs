@@ -0,0 +1,381 @@
+use crate::core::constants::{self, REGISTER_NAMES};
+use crate::core::decoder::{Address, Instruction};
+use crate::core::memory::{Flags, Memory};
+use crate::core::observer::Observer;
+use serde::Serialize;
+use std::io::Write;
+use std::ops::Range;
+
+/// Limits which executed instructions are written to the trace.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    /// Only trace instructions whose address lies in this range.
+    pub addresses: Option<Range<u64>>,
+    /// Only trace instructions whose step number, counted from 0, lies in this range.
+    pub steps: Option<Range<u64>>,
+}
+
+impl TraceFilter {
+    fn matches(&self, step: u64, pc: u64) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|range| range.contains(&pc))
+            && self
+                .steps
+                .as_ref()
+                .is_none_or(|range| range.contains(&step))
+    }
+}
+
+#[derive(Serialize)]
+struct RegisterWrite {
+    register: &'static str,
+    value: u64,
+}
+
+#[derive(Serialize)]
+struct MemoryWrite {
+    address: u64,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct IoEvent {
+    kind: &'static str,
+    /// The file handle, or the error code returned by a failed `fileopen`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// The destination of a `filerename`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<u64>,
+    /// The size in bytes, or the error code returned by a failed `fileread`, `filewrite` or
+    /// `filesize`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<i64>,
+    /// The position in the file, or the error code returned by a failed `fileseek` or
+    /// `filetell`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<i64>,
+    /// 0, or the error code returned by a failed `fileremove` or `filerename`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+impl IoEvent {
+    fn new(kind: &'static str) -> IoEvent {
+        IoEvent {
+            kind,
+            file: None,
+            path: None,
+            new_path: None,
+            mode: None,
+            address: None,
+            size: None,
+            position: None,
+            result: None,
+            data: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    step: u64,
+    pc: u64,
+    opcode: &'static str,
+    instruction: &'a Instruction,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    registers: Vec<RegisterWrite>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<Flags>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    memory: Vec<MemoryWrite>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    io: Option<IoEvent>,
+}
+
+/// The state captured before a traced instruction runs.
+struct PendingEvent {
+    registers: [u64; 16],
+    flags: Flags,
+    memory_write: Option<(u64, u64)>,
+    path: Option<String>,
+    new_path: Option<String>,
+}
+
+/// Writes one JSON object per executed instruction, describing the instruction and its effects
+/// on registers, flags and memory.
+///
+/// Not every memory write is included: the heap bookkeeping done by `alloc`, `free` and
+/// `realloc`, the copy of the contents when `realloc` moves a block, and whatever host functions
+/// write during a `hostcall` are left out. An instruction that traps gets no event, so the trace
+/// ends with the last instruction that completed.
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: TraceFilter,
+    step: u64,
+    pending: Option<PendingEvent>,
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W, filter: TraceFilter) -> Tracer<W> {
+        Tracer {
+            output,
+            filter,
+            step: 0,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// Flushes the trace, returning the first error that occurred while writing it.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.output.flush(),
+        }
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) {
+        let result = serde_json::to_writer(&mut self.output, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.output.write_all(b"\n"));
+        if let Err(error) = result {
+            self.error = Some(error);
+        }
+    }
+}
+
+/// The memory range an instruction writes to, resolved with the registers before it runs.
+fn memory_write(instruction: &Instruction, registers: &[u64; 16]) -> Option<(u64, u64)> {
+    let sp = registers[constants::SP];
+    let register_value = |register: u8| registers[register as usize];
+    match *instruction {
+        Instruction::Store { size, address, .. } => Some((address.resolve(registers), size as u64)),
+        Instruction::Push { size, .. } => Some((sp.wrapping_sub(size as u64), size as u64)),
+        Instruction::Call { .. } => Some((sp.wrapping_sub(8), 8)),
+        Instruction::PushMem { register, .. } => {
+            let size = register_value(register);
+            Some((sp.wrapping_sub(size), size))
+        }
+        Instruction::PopMem { register, address } | Instruction::PeekMem { register, address } => {
+            Some((address.resolve(registers), register_value(register)))
+        }
+        Instruction::ReadStdin { register, address } => {
+            Some((address.resolve(registers), register_value(register)))
+        }
+        Instruction::FileRead {
+            size_register,
+            address,
+            ..
+        }
+        | Instruction::ArgRead {
+            size_register,
+            address,
+            ..
+        } => Some((address.resolve(registers), register_value(size_register))),
+        Instruction::MemCopy {
+            size_register,
+            dest,
+            ..
+        } => Some((dest.resolve(registers), register_value(size_register))),
+        Instruction::MemSet {
+            size_register,
+            address,
+            ..
+        } => Some((address.resolve(registers), register_value(size_register))),
+        _ => None,
+    }
+}
+
+/// The number of bytes written by instructions that may write less than requested, read from
+/// their size register after they ran. Error codes mean nothing was written.
+fn written_size(instruction: &Instruction, memory: &Memory) -> Option<u64> {
+    match *instruction {
+        Instruction::FileRead { size_register, .. }
+        | Instruction::ArgRead { size_register, .. } => {
+            Some((memory.registers[size_register as usize] as i64).max(0) as u64)
+        }
+        _ => None,
+    }
+}
+
+/// Describes the I/O performed by an instruction, using the registers before and after it ran.
+fn io_event(instruction: &Instruction, before: &PendingEvent, memory: &Memory) -> Option<IoEvent> {
+    let registers = &before.registers;
+    let read_data = |address: u64, size: u64| {
+        memory
+            .read_bytes(address as usize, size as usize)
+            .ok()
+            .map(|bytes| to_hex(&bytes))
+    };
+    let event = match *instruction {
+        Instruction::ReadStdin { register, address } => IoEvent {
+            address: Some(address.resolve(registers)),
+            size: Some(registers[register as usize] as i64),
+            ..IoEvent::new("stdin_read")
+        },
+        Instruction::WriteStdout { register, address } => {
+            let address = address.resolve(registers);
+            let size = registers[register as usize];
+            IoEvent {
+                address: Some(address),
+                size: Some(size as i64),
+                data: read_data(address, size),
+                ..IoEvent::new("stdout_write")
+            }
+        }
+        Instruction::FileOpen { register, .. } => IoEvent {
+            file: Some(memory.registers[register as usize] as i64),
+            path: before.path.clone(),
+            ..IoEvent::new("file_open")
+        },
+        Instruction::FileOpenMode { register, .. } => IoEvent {
+            file: Some(memory.registers[register as usize] as i64),
+            path: before.path.clone(),
+            mode: Some(registers[register as usize]),
+            ..IoEvent::new("file_open")
+        },
+        Instruction::FileClose { register } => IoEvent {
+            file: Some(registers[register as usize] as i64),
+            ..IoEvent::new("file_close")
+        },
+        Instruction::FileSeek {
+            file_register,
+            position_register,
+        } => IoEvent {
+            file: Some(registers[file_register as usize] as i64),
+            position: Some(memory.registers[position_register as usize] as i64),
+            ..IoEvent::new("file_seek")
+        },
+        Instruction::FileTell { register } => IoEvent {
+            file: Some(registers[register as usize] as i64),
+            position: Some(memory.registers[register as usize] as i64),
+            ..IoEvent::new("file_tell")
+        },
+        Instruction::FileSize { register } => IoEvent {
+            file: Some(registers[register as usize] as i64),
+            size: Some(memory.registers[register as usize] as i64),
+            ..IoEvent::new("file_size")
+        },
+        Instruction::FileRemove { register, .. } => IoEvent {
+            path: before.path.clone(),
+            result: Some(memory.registers[register as usize] as i64),
+            ..IoEvent::new("file_remove")
+        },
+        Instruction::FileRename { register, .. } => IoEvent {
+            path: before.path.clone(),
+            new_path: before.new_path.clone(),
+            result: Some(memory.registers[register as usize] as i64),
+            ..IoEvent::new("file_rename")
+        },
+        Instruction::FileRead {
+            file_register,
+            size_register,
+            address,
+        } => IoEvent {
+            file: Some(registers[file_register as usize] as i64),
+            address: Some(address.resolve(registers)),
+            size: Some(memory.registers[size_register as usize] as i64),
+            ..IoEvent::new("file_read")
+        },
+        Instruction::FileWrite {
+            file_register,
+            size_register,
+            address,
+        } => {
+            let address = address.resolve(registers);
+            let size = registers[size_register as usize];
+            IoEvent {
+                file: Some(registers[file_register as usize] as i64),
+                address: Some(address),
+                size: Some(memory.registers[size_register as usize] as i64),
+                data: read_data(address, size),
+                ..IoEvent::new("file_write")
+            }
+        }
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn before_instruction(&mut self, pc: u64, instruction: &Instruction, memory: &Memory) {
+        if self.error.is_some() || !self.filter.matches(self.step, pc) {
+            self.pending = None;
+            return;
+        }
+        let read_path =
+            |address: Address| memory.read_string(address.resolve(&memory.registers) as usize);
+        let (path, new_path) = match *instruction {
+            Instruction::FileOpen { address, .. }
+            | Instruction::FileOpenMode { address, .. }
+            | Instruction::FileRemove { address, .. } => (read_path(address).ok(), None),
+            Instruction::FileRename { from, to, .. } => (read_path(from).ok(), read_path(to).ok()),
+            _ => (None, None),
+        };
+        self.pending = Some(PendingEvent {
+            registers: memory.registers,
+            flags: memory.flags,
+            memory_write: memory_write(instruction, &memory.registers),
+            path,
+            new_path,
+        });
+    }
+
+    fn after_instruction(&mut self, pc: u64, instruction: &Instruction, memory: &Memory) {
+        let step = self.step;
+        self.step += 1;
+        let Some(before) = self.pending.take() else {
+            return;
+        };
+
+        let registers = (0..16)
+            .filter(|&register| register != constants::PC)
+            .filter(|&register| before.registers[register] != memory.registers[register])
+            .map(|register| RegisterWrite {
+                register: REGISTER_NAMES[register],
+                value: memory.registers[register],
+            })
+            .collect();
+        let flags = (before.flags != memory.flags).then_some(memory.flags);
+        let memory_writes = before
+            .memory_write
+            .map(|(address, size)| (address, written_size(instruction, memory).unwrap_or(size)))
+            .filter(|(_, size)| *size > 0)
+            .and_then(|(address, size)| {
+                let bytes = memory.read_bytes(address as usize, size as usize).ok()?;
+                Some(MemoryWrite {
+                    address,
+                    data: to_hex(&bytes),
+                })
+            })
+            .into_iter()
+            .collect();
+
+        let event = TraceEvent {
+            step,
+            pc,
+            opcode: instruction.mnemonic(),
+            instruction,
+            registers,
+            flags,
+            memory: memory_writes,
+            io: io_event(instruction, &before, memory),
+        };
+        self.write_event(&event);
+    }
+}
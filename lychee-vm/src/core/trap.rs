@@ -0,0 +1,140 @@
+use std::fmt::Display;
+
+pub type VmResult<T> = Result<T, VmTrapKind>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VmTrapKind {
+    InvalidOpcode(u8),
+    InvalidRegister(u8),
+    MemoryAccessViolation { address: u64, size: u64 },
+    DivisionByZero,
+    ArithmeticOverflow,
+    StackOverflow { stack_pointer: u64, call_depth: u64 },
+    HeapExhausted { requested: u64 },
+    InvalidFree { address: u64 },
+    InvalidRealloc { address: u64 },
+    HeapCorrupted { address: u64 },
+    BadFileHandle { file_id: u64 },
+    HostIo(String),
+    InvalidMemoryLayout(String),
+    OutOfFuel { executed: u64 },
+    Timeout { executed: u64 },
+    UnknownHostFunction { id: u64 },
+    HostFunction(String),
+    Abort,
+    InvalidArgumentIndex { index: u64 },
+    CodeWrite { address: u64 },
+}
+
+impl VmTrapKind {
+    /// Whether the trap was caused by a limit on the run rather than by the program, in which
+    /// case the machine can be resumed once the limit is raised.
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            VmTrapKind::OutOfFuel { .. } | VmTrapKind::Timeout { .. }
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VmTrap {
+    pub kind: VmTrapKind,
+    pub pc: u64,
+    pub registers: Box<[u64; 16]>,
+    /// The PC followed by the return addresses of the active calls, see `backtrace`. Empty if
+    /// the trap was raised outside of the program.
+    pub backtrace: Vec<u64>,
+}
+
+impl VmTrap {
+    pub fn new(kind: VmTrapKind, pc: u64, registers: [u64; 16]) -> Self {
+        VmTrap {
+            kind,
+            pc,
+            registers: Box::new(registers),
+            backtrace: Vec::new(),
+        }
+    }
+}
+
+impl Display for VmTrapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmTrapKind::InvalidOpcode(opcode) => write!(f, "invalid opcode 0x{:02X}", opcode),
+            VmTrapKind::InvalidRegister(register) => write!(f, "invalid register {}", register),
+            VmTrapKind::MemoryAccessViolation { address, size } => write!(
+                f,
+                "memory access violation: {} bytes at address {}",
+                size, address
+            ),
+            VmTrapKind::DivisionByZero => write!(f, "division by zero"),
+            VmTrapKind::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            VmTrapKind::StackOverflow {
+                stack_pointer,
+                call_depth,
+            } => write!(
+                f,
+                "stack overflow at call depth {} (sp = {})",
+                call_depth, stack_pointer
+            ),
+            VmTrapKind::HeapExhausted { requested } => {
+                write!(f, "heap exhausted: failed to allocate {} bytes", requested)
+            }
+            VmTrapKind::InvalidFree { address } => {
+                write!(f, "invalid free of address {}", address)
+            }
+            VmTrapKind::InvalidRealloc { address } => {
+                write!(f, "invalid realloc of address {}", address)
+            }
+            VmTrapKind::HeapCorrupted { address } => {
+                write!(f, "corrupted heap block header at address {}", address)
+            }
+            VmTrapKind::BadFileHandle { file_id } => write!(f, "bad file handle {}", file_id),
+            VmTrapKind::HostIo(message) => write!(f, "host I/O error: {}", message),
+            VmTrapKind::InvalidMemoryLayout(message) => {
+                write!(f, "invalid memory layout: {}", message)
+            }
+            VmTrapKind::OutOfFuel { executed } => {
+                write!(
+                    f,
+                    "instruction budget exhausted after {} instructions",
+                    executed
+                )
+            }
+            VmTrapKind::Timeout { executed } => {
+                write!(f, "timed out after {} instructions", executed)
+            }
+            VmTrapKind::UnknownHostFunction { id } => {
+                write!(f, "no host function registered with id 0x{:X}", id)
+            }
+            VmTrapKind::HostFunction(message) => write!(f, "host function error: {}", message),
+            VmTrapKind::Abort => write!(f, "program aborted"),
+            VmTrapKind::InvalidArgumentIndex { index } => {
+                write!(f, "no command line argument with index {}", index)
+            }
+            VmTrapKind::CodeWrite { address } => {
+                write!(f, "write to the read-only code at address {}", address)
+            }
+        }
+    }
+}
+
+impl Display for VmTrap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VM trap at PC {}: {}", self.pc, self.kind)?;
+        // The program stopped itself, so its registers are of no interest.
+        if !matches!(self.kind, VmTrapKind::Abort) {
+            write!(f, "\nRegisters: {:?}", self.registers.map(|r| r as i64))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VmTrap {}
+
+impl From<std::io::Error> for VmTrapKind {
+    fn from(error: std::io::Error) -> Self {
+        VmTrapKind::HostIo(error.to_string())
+    }
+}
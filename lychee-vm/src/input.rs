@@ -1,9 +1,37 @@
+use lychee_vm::{bundled_program, ObjectFile, Snapshot, PROGRAM_MAGIC};
 use std::path::PathBuf;
 
-pub fn read_obj_file(input: &PathBuf, debug_print: bool) -> Vec<u8> {
+/// Reads an object file, or the object file bundled into an executable by the compiler's
+/// `--bundle-vm`, exiting with an error if it is not a valid object file of the supported
+/// version.
+pub fn read_obj_file(input: &PathBuf, debug_print: bool) -> ObjectFile {
     if debug_print {
         println!("Reading file: {}", input.to_str().unwrap());
     }
-    let bytes = std::fs::read(input).unwrap();
-    bytes
+    let bytes = read_file(input);
+    let object_bytes = match bundled_program(&bytes) {
+        Some(program) if !bytes.starts_with(&PROGRAM_MAGIC) => program,
+        _ => &bytes,
+    };
+    ObjectFile::from_bytes(object_bytes).unwrap_or_else(|error| {
+        eprintln!("Cannot load {}: {}", input.display(), error);
+        std::process::exit(1);
+    })
+}
+
+/// Reads a snapshot written by `--snapshot-at`, exiting with an error if it is not a valid
+/// snapshot of the supported version.
+pub fn read_snapshot_file(input: &PathBuf) -> Snapshot {
+    let bytes = read_file(input);
+    Snapshot::from_bytes(&bytes).unwrap_or_else(|error| {
+        eprintln!("Cannot load {}: {}", input.display(), error);
+        std::process::exit(1);
+    })
+}
+
+fn read_file(input: &PathBuf) -> Vec<u8> {
+    std::fs::read(input).unwrap_or_else(|error| {
+        eprintln!("Cannot load {}: {}", input.display(), error);
+        std::process::exit(1);
+    })
 }
@@ -1,5 +1,63 @@
 mod core;
+mod vm;
 
-pub fn execute(program: Vec<u8>, debug_print: bool) {
-    core::execute(program, debug_print);
+pub use crate::core::backtrace::format_backtrace;
+pub use crate::core::files::{FileMode, FileSystem, HostFileSystem, MemoryFileSystem, VmFile};
+pub use crate::core::heap::HeapStats;
+pub use crate::core::host::HostCall;
+pub use crate::core::memcheck::Memcheck;
+pub use crate::core::profiler::Profiler;
+pub use crate::core::snapshot::{Snapshot, SnapshotError};
+pub use crate::core::tracer::{TraceFilter, Tracer};
+pub use crate::core::trap::{VmTrap, VmTrapKind};
+pub use crate::vm::{SharedBuffer, Vm};
+pub use lychee_compiler::assembler::disassemble;
+pub use lychee_compiler::{
+    bundled_program, FilePolicy, LineTable, ObjectFile, ObjectFileError, ProgramHeader,
+    SymbolTable, VmConfig, VmMemoryConfig, FILE_ERROR_DENIED, FILE_ERROR_EXISTS,
+    FILE_ERROR_INVALID, FILE_ERROR_IO, FILE_ERROR_NOT_FOUND, FILE_MODE_APPEND,
+    FILE_MODE_CREATE_NEW, FILE_MODE_READ, FILE_MODE_READ_WRITE, FILE_MODE_WRITE, PROGRAM_MAGIC,
+};
+
+pub fn execute(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    debug_print: bool,
+) -> Result<i64, VmTrap> {
+    core::execute(program, vm_config, debug_print)
+}
+
+/// Executes the program while collecting an instruction profile into `profiler`.
+pub fn profile(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    profiler: &mut Profiler,
+) -> Result<i64, VmTrap> {
+    let result = core::execute_observed(program, vm_config, false, profiler);
+    profiler.finish();
+    result
+}
+
+/// Executes the program while checking its heap usage with `memcheck`.
+pub fn memcheck(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    memcheck: &mut Memcheck,
+) -> Result<i64, VmTrap> {
+    core::execute_observed(program, vm_config, false, memcheck)
+}
+
+/// Executes the program while writing an execution trace with `tracer`.
+pub fn trace<W: std::io::Write>(
+    program: &ObjectFile,
+    vm_config: &VmConfig,
+    tracer: &mut Tracer<W>,
+) -> Result<i64, VmTrap> {
+    core::execute_observed(program, vm_config, false, tracer)
+}
+
+/// Runs the program in the interactive debugger, which names addresses with the symbols of
+/// the program.
+pub fn debug(program: &ObjectFile, vm_config: &VmConfig) -> Result<(), VmTrap> {
+    core::debugger::debug(program, vm_config)
 }
@@ -1,19 +1,360 @@
-use clap::Parser;
-use lychee_vm::execute;
+use clap::{Args, Parser, Subcommand};
+use lychee_vm::{
+    debug, disassemble, execute, format_backtrace, memcheck, profile, trace, FilePolicy,
+    HostFileSystem, LineTable, Memcheck, Profiler, TraceFilter, Tracer, Vm, VmConfig, VmTrap,
+};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::time::Duration;
 
 mod input;
 
+/// Exit status when the program trapped.
+const TRAP_EXIT_STATUS: i32 = 1;
+/// Exit status when the program was stopped by `--fuel` or `--timeout`, as used by `timeout(1)`.
+const LIMIT_EXIT_STATUS: i32 = 124;
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    input: PathBuf,
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a program (the default)
+    Run(RunArgs),
+    /// Run a program in the interactive debugger
+    Debug(DebugArgs),
+    /// Print the assembly of a program or of an executable with a bundled program
+    Disasm(DisasmArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    #[arg(required_unless_present = "resume")]
+    input: Option<PathBuf>,
     #[arg(short, long, default_value("false"))]
     debug_print: bool,
+    /// Count executed instructions and print a profile report to stderr.
+    #[arg(long, conflicts_with = "trace")]
+    profile: bool,
+    /// Where to write the folded call stacks of the profile, defaults to the input with a
+    /// `.folded` extension.
+    #[arg(long, requires = "profile")]
+    profile_output: Option<PathBuf>,
+    /// Write a trace of the executed instructions to this file as JSON Lines.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+    /// Only trace instructions in this address range, written as `start..end`.
+    #[arg(long, requires = "trace", value_parser = parse_range)]
+    trace_addresses: Option<Range<u64>>,
+    /// Only trace the executed instructions with these step numbers, written as `start..end`.
+    #[arg(long, requires = "trace", value_parser = parse_range)]
+    trace_steps: Option<Range<u64>>,
+    /// Check heap accesses, frees and reads of uninitialized heap memory, and print the errors
+    /// and a leak report to stderr.
+    #[arg(long, conflicts_with_all = ["debug_print", "profile", "trace", "snapshot_at", "resume", "heap_stats"])]
+    memcheck: bool,
+    /// Write a snapshot of the VM state after executing this many instructions and keep running.
+    #[arg(long, value_parser = parse_size, conflicts_with_all = ["debug_print", "profile", "trace"])]
+    snapshot_at: Option<u64>,
+    /// Where to write the snapshot, defaults to the input with a `.snap` extension.
+    #[arg(long, requires = "snapshot_at")]
+    snapshot_output: Option<PathBuf>,
+    /// Resume from a snapshot instead of starting a program. The memory layout, the file policy
+    /// and checked arithmetic are taken from the snapshot, so the memory size options are
+    /// ignored and the file options can only restrict it further.
+    #[arg(long, conflicts_with_all = ["input", "debug_print", "profile", "trace"])]
+    resume: Option<PathBuf>,
+    /// Print statistics about the heap allocations to stderr when the program ends.
+    #[arg(long, conflicts_with_all = ["debug_print", "profile", "trace"])]
+    heap_stats: bool,
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Arguments passed to the program. A resumed program keeps the arguments it was started
+    /// with.
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        conflicts_with = "resume"
+    )]
+    args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct DebugArgs {
+    input: PathBuf,
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Arguments passed to the program.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
+    input: PathBuf,
+    /// Write the assembly to this file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ConfigArgs {
+    /// Maximum size of the VM memory in bytes, which the heap grows into, overriding the
+    /// program's configuration.
+    #[arg(long, value_parser = parse_size)]
+    memory_size: Option<u64>,
+    /// Initial size of the heap in bytes, overriding the program's configuration.
+    #[arg(long, value_parser = parse_size)]
+    heap_size: Option<u64>,
+    /// Size of the stack in bytes, overriding the program's configuration.
+    #[arg(long, value_parser = parse_size)]
+    stack_size: Option<u64>,
+    /// Seed of the random number generator, overriding the program's configuration.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// TOML file with the policy for the file opcodes, with the keys `deny_all`, `root`,
+    /// `read_only` and `read_write`. The flags below are applied on top of it.
+    #[arg(long, value_parser = parse_file_policy)]
+    file_policy: Option<FilePolicy>,
+    /// Confine the files the program can open to this directory.
+    #[arg(long)]
+    file_root: Option<PathBuf>,
+    /// Only allow reading this file or directory. Once any file is allowed, all others are
+    /// denied.
+    #[arg(long)]
+    allow_read: Vec<PathBuf>,
+    /// Allow reading, writing and creating this file or directory. Once any file is allowed, all
+    /// others are denied.
+    #[arg(long)]
+    allow_write: Vec<PathBuf>,
+    /// Deny the program access to all files.
+    #[arg(long)]
+    deny_files: bool,
+    /// Stop the program after executing this many instructions.
+    #[arg(long, value_parser = parse_size)]
+    fuel: Option<u64>,
+    /// Stop the program after running for this many seconds.
+    #[arg(long, value_parser = parse_seconds)]
+    timeout: Option<Duration>,
+    /// Trap on signed overflow and on shifts by 64 or more bits instead of wrapping around.
+    #[arg(long)]
+    checked_arithmetic: bool,
+}
+
+impl ConfigArgs {
+    fn apply(&self, mut vm_config: VmConfig) -> VmConfig {
+        if let Some(memory_size) = self.memory_size {
+            vm_config.memory.memory_size = memory_size;
+        }
+        if let Some(heap_size) = self.heap_size {
+            vm_config.memory.heap_size = heap_size;
+        }
+        if let Some(stack_size) = self.stack_size {
+            vm_config.memory.stack_size = stack_size;
+        }
+        if let Some(seed) = self.seed {
+            vm_config.seed = Some(seed);
+        }
+        if let Some(file_policy) = &self.file_policy {
+            vm_config.files = file_policy.clone();
+        }
+        if let Some(file_root) = &self.file_root {
+            vm_config.files.root = Some(file_root.clone());
+        }
+        vm_config
+            .files
+            .read_only
+            .extend(self.allow_read.iter().cloned());
+        vm_config
+            .files
+            .read_write
+            .extend(self.allow_write.iter().cloned());
+        vm_config.files.deny_all |= self.deny_files;
+        vm_config.fuel = self.fuel;
+        vm_config.timeout = self.timeout;
+        vm_config.checked_arithmetic = self.checked_arithmetic;
+        vm_config
+    }
+
+    /// Applies the options to the settings a snapshot was taken with. They may only restrict
+    /// the program further, so the options that could open up more files are rejected.
+    fn restrict(&self, mut vm_config: VmConfig) -> Result<VmConfig, String> {
+        if self.file_policy.is_some()
+            || self.file_root.is_some()
+            || !self.allow_read.is_empty()
+            || !self.allow_write.is_empty()
+        {
+            return Err(
+                "--resume keeps the file policy of the snapshot, only --deny-files \
+                can be added to it"
+                    .to_string(),
+            );
+        }
+        vm_config.files.deny_all |= self.deny_files;
+        vm_config.fuel = self.fuel;
+        vm_config.timeout = self.timeout;
+        vm_config.checked_arithmetic |= self.checked_arithmetic;
+        Ok(vm_config)
+    }
+}
+
+fn parse_size(str: &str) -> Result<u64, String> {
+    let result = match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => str.parse::<u64>(),
+    };
+    result.map_err(|e| format!("invalid size '{}': {}", str, e))
+}
+
+fn parse_seconds(str: &str) -> Result<Duration, String> {
+    str.parse::<f64>()
+        .map_err(|e| e.to_string())
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string()))
+        .map_err(|e| format!("invalid duration '{}': {}", str, e))
+}
+
+fn parse_file_policy(str: &str) -> Result<FilePolicy, String> {
+    let text = std::fs::read_to_string(str)
+        .map_err(|e| format!("failed to read file policy '{}': {}", str, e))?;
+    toml::from_str(&text).map_err(|e| format!("invalid file policy '{}': {}", str, e))
+}
+
+fn parse_range(str: &str) -> Result<Range<u64>, String> {
+    let (start, end) = str
+        .split_once("..")
+        .ok_or_else(|| format!("invalid range '{}', expected 'start..end'", str))?;
+    Ok(parse_size(start)?..parse_size(end)?)
+}
+
+/// Runs the program to completion, writing a snapshot to `output` once `snapshot_at`
+/// instructions have been executed, and printing the heap statistics to stderr at the end if
+/// `heap_stats` is set. Returns the exit code of the program.
+fn run_vm(
+    mut vm: Vm,
+    snapshot_at: Option<u64>,
+    output: &PathBuf,
+    heap_stats: bool,
+) -> Result<i64, VmTrap> {
+    let mut run = || {
+        if let Some(snapshot_at) = snapshot_at {
+            vm.run_until(|vm| vm.executed() >= snapshot_at)?;
+            if vm.exit_code().is_none() {
+                let snapshot = vm.snapshot()?;
+                std::fs::write(output, snapshot.to_bytes()).expect("Failed to write snapshot");
+            }
+        }
+        vm.run()
+    };
+    let result = run();
+    if heap_stats {
+        eprint!("{}", vm.heap_stats());
+    }
+    result
 }
 
 fn main() {
-    let args = Args::parse();
-    let program = input::read_obj_file(&args.input, args.debug_print);
-    execute(program, args.debug_print);
+    let cli = Cli::parse();
+    // Snapshots do not keep the line table, so traps after resuming show bare addresses.
+    let mut line_table = LineTable::default();
+    let result = match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) if args.resume.is_some() => {
+            let resume = args.resume.unwrap();
+            let snapshot = input::read_snapshot_file(&resume);
+            let vm_config = args
+                .config
+                .restrict(snapshot.vm_config())
+                .unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                });
+            let file_system = Box::new(HostFileSystem::new(&vm_config.files));
+            let output = args
+                .snapshot_output
+                .unwrap_or_else(|| resume.with_extension("snap"));
+            Vm::restore(&snapshot, &vm_config, file_system)
+                .and_then(|vm| run_vm(vm, args.snapshot_at, &output, args.heap_stats))
+        }
+        Command::Run(args) => {
+            let input = args.input.unwrap();
+            let program = input::read_obj_file(&input, args.debug_print);
+            let mut vm_config = args.config.apply(program.header.config.clone());
+            vm_config.args = args.args;
+            line_table = program.line_table.clone();
+            if args.profile {
+                let mut profiler = Profiler::new(program.symbols.clone());
+                let result = profile(&program, &vm_config, &mut profiler);
+                eprint!("{}", profiler.report());
+                let output = args
+                    .profile_output
+                    .unwrap_or_else(|| input.with_extension("folded"));
+                std::fs::write(&output, profiler.folded_stacks())
+                    .expect("Failed to write profile output");
+                result
+            } else if args.memcheck {
+                let mut checker = Memcheck::new(program.symbols.clone());
+                let result = memcheck(&program, &vm_config, &mut checker);
+                eprint!("{}", checker.report());
+                result
+            } else if let Some(trace_path) = args.trace {
+                let file = std::fs::File::create(&trace_path).expect("Failed to create trace file");
+                let filter = TraceFilter {
+                    addresses: args.trace_addresses,
+                    steps: args.trace_steps,
+                };
+                let mut tracer = Tracer::new(std::io::BufWriter::new(file), filter);
+                let result = trace(&program, &vm_config, &mut tracer);
+                tracer.finish().expect("Failed to write trace file");
+                result
+            } else if args.snapshot_at.is_some() || args.heap_stats {
+                let output = args
+                    .snapshot_output
+                    .unwrap_or_else(|| input.with_extension("snap"));
+                Vm::new(&program, &vm_config)
+                    .and_then(|vm| run_vm(vm, args.snapshot_at, &output, args.heap_stats))
+            } else {
+                execute(&program, &vm_config, args.debug_print)
+            }
+        }
+        Command::Debug(args) => {
+            let program = input::read_obj_file(&args.input, false);
+            let mut vm_config = args.config.apply(program.header.config.clone());
+            vm_config.args = args.args;
+            debug(&program, &vm_config).map(|_| 0)
+        }
+        Command::Disasm(args) => {
+            let program = input::read_obj_file(&args.input, false);
+            let text = disassemble(&program);
+            match args.output {
+                Some(output) => std::fs::write(&output, text).expect("Failed to write disassembly"),
+                None => print!("{}", text),
+            }
+            Ok(0)
+        }
+    };
+    match result {
+        // The exit status of a process is the low byte of the code on Unix.
+        Ok(exit_code) => std::process::exit(exit_code as i32),
+        Err(trap) => {
+            eprintln!("{}", trap);
+            eprint!("{}", format_backtrace(&trap.backtrace, &line_table));
+            let status = if trap.kind.is_limit() {
+                LIMIT_EXIT_STATUS
+            } else {
+                TRAP_EXIT_STATUS
+            };
+            std::process::exit(status);
+        }
+    }
 }
@@ -0,0 +1,567 @@
+use crate::core::constants;
+use crate::core::decoder::DecodedProgram;
+use crate::core::files::FileSystem;
+use crate::core::heap::HeapStats;
+use crate::core::host::HostCall;
+use crate::core::snapshot::Snapshot;
+use crate::core::Machine;
+use crate::{ObjectFile, VmConfig, VmTrap, VmTrapKind};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// A loaded program, for running Lychee programs from Rust. By default the program uses the
+/// process's stdin and stdout and the host file system restricted by the configured file policy.
+pub struct Vm {
+    machine: Machine,
+    exit_code: Option<i64>,
+    /// The trap that stopped the program, unless it was a limit the program can be resumed from.
+    trap: Option<VmTrap>,
+    /// Set by `memory_mut`, which may have changed the decoded code.
+    code_modified: bool,
+}
+
+impl Vm {
+    /// Loads `program`, ready to start at its entry point. The configuration stored in the
+    /// program is not applied, only `vm_config` is.
+    pub fn new(program: &ObjectFile, vm_config: &VmConfig) -> Result<Vm, VmTrap> {
+        Ok(Vm {
+            machine: Machine::new(program, vm_config, false)?,
+            exit_code: None,
+            trap: None,
+            code_modified: false,
+        })
+    }
+
+    /// Resumes the program a snapshot was taken from, reopening the files that were open in
+    /// `file_system`. The other settings come from `vm_config`, which should start from
+    /// `Snapshot::vm_config` to keep the program restricted as before.
+    pub fn restore(
+        snapshot: &Snapshot,
+        vm_config: &VmConfig,
+        file_system: Box<dyn FileSystem>,
+    ) -> Result<Vm, VmTrap> {
+        Ok(Vm {
+            machine: Machine::restore(snapshot, vm_config, file_system)?,
+            exit_code: None,
+            trap: None,
+            code_modified: false,
+        })
+    }
+
+    /// Captures the state of the program, so it can be resumed with `Vm::restore`. Host
+    /// functions, stdin and stdout are not part of the snapshot.
+    pub fn snapshot(&self) -> Result<Snapshot, VmTrap> {
+        self.machine.snapshot()
+    }
+
+    pub fn set_stdin(&mut self, stdin: Box<dyn Read>) {
+        self.machine.memory.stdin = stdin;
+    }
+
+    pub fn set_stdout(&mut self, stdout: Box<dyn Write>) {
+        self.machine.memory.stdout = stdout;
+    }
+
+    /// Replaces the file system used for files opened from now on.
+    pub fn set_file_system(&mut self, file_system: Box<dyn FileSystem>) {
+        self.machine.memory.files.file_system = file_system;
+    }
+
+    /// Registers the implementation of the `extern` function `name`. Calls to an `extern`
+    /// function that was not registered trap with `VmTrapKind::UnknownHostFunction`, and errors
+    /// returned by `function` stop the program.
+    pub fn register_host_function(
+        &mut self,
+        name: &str,
+        function: impl FnMut(&mut HostCall) -> Result<(), VmTrapKind> + 'static,
+    ) {
+        self.machine
+            .host_functions
+            .register(name, Box::new(function));
+    }
+
+    /// Runs the program until it exits, faults or reaches one of its limits, returning its exit
+    /// code. After running out of fuel or time the program can be run again, while any other
+    /// trap is returned again by every later call.
+    pub fn run(&mut self) -> Result<i64, VmTrap> {
+        if let Some(exit_code) = self.exit_code {
+            return Ok(exit_code);
+        }
+        self.check_trap()?;
+        self.redecode_if_modified();
+        let result = self.machine.run();
+        let exit_code = self.record_trap(result)?;
+        self.exit_code = Some(exit_code);
+        Ok(exit_code)
+    }
+
+    /// Executes a single instruction, returning the exit code once the program has exited.
+    /// Traps are handled like in `run`.
+    pub fn step(&mut self) -> Result<Option<i64>, VmTrap> {
+        if self.exit_code.is_none() {
+            self.check_trap()?;
+            self.redecode_if_modified();
+            let result = self.machine.step();
+            self.exit_code = self.record_trap(result)?;
+        }
+        Ok(self.exit_code)
+    }
+
+    /// Runs the program until it exits or `stop` returns true, which is checked before every
+    /// instruction. Returns the exit code if the program exited.
+    pub fn run_until(&mut self, mut stop: impl FnMut(&Vm) -> bool) -> Result<Option<i64>, VmTrap> {
+        while self.exit_code.is_none() && !stop(self) {
+            self.step()?;
+        }
+        Ok(self.exit_code)
+    }
+
+    /// The exit code, once the program has exited.
+    pub fn exit_code(&self) -> Option<i64> {
+        self.exit_code
+    }
+
+    pub fn registers(&self) -> &[u64; 16] {
+        &self.machine.memory.registers
+    }
+
+    pub fn set_register(&mut self, register: usize, value: u64) {
+        self.machine.memory.registers[register] = value;
+    }
+
+    /// The address of the next instruction.
+    pub fn pc(&self) -> u64 {
+        self.machine.memory.registers[constants::PC]
+    }
+
+    /// The whole memory, starting with the program's code.
+    pub fn memory(&self) -> &[u8] {
+        &self.machine.memory.data
+    }
+
+    /// The whole memory, for changing it. Changes to the code are picked up the next time the
+    /// program runs.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.code_modified = true;
+        &mut self.machine.memory.data
+    }
+
+    fn check_trap(&self) -> Result<(), VmTrap> {
+        match &self.trap {
+            Some(trap) => Err(trap.clone()),
+            None => Ok(()),
+        }
+    }
+
+    fn record_trap<T>(&mut self, result: Result<T, VmTrap>) -> Result<T, VmTrap> {
+        if let Err(trap) = &result {
+            if !trap.kind.is_limit() {
+                self.trap = Some(trap.clone());
+            }
+        }
+        result
+    }
+
+    fn redecode_if_modified(&mut self) {
+        if self.code_modified {
+            let code_size = self.machine.program.size();
+            self.machine.program = DecodedProgram::new(&self.machine.memory, code_size);
+            self.code_modified = false;
+        }
+    }
+
+    /// Statistics about the heap allocations of the program so far.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.machine.heap.stats(&self.machine.memory)
+    }
+
+    /// The number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.machine.executed()
+    }
+
+    /// The number of instructions left in the budget, or `None` if it is unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.machine.fuel()
+    }
+
+    /// Limits how many more instructions may be executed. Once they are used up, running traps
+    /// with `VmTrapKind::OutOfFuel`, and the program can be resumed after refueling.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.machine.set_fuel(fuel);
+    }
+
+    /// Adds `fuel` instructions to the budget, if it is limited.
+    pub fn refuel(&mut self, fuel: u64) {
+        self.machine.refuel(fuel);
+    }
+
+    /// Stops execution with `VmTrapKind::Timeout` once `deadline` has passed.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.machine.set_deadline(deadline);
+    }
+}
+
+/// An in-memory buffer that can be given to a `Vm` as stdout while a clone of it is kept to read
+/// the output.
+#[derive(Clone, Debug, Default)]
+pub struct SharedBuffer {
+    data: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.borrow().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.data.borrow_mut().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lychee_compiler::assembler::assemble_source;
+    use lychee_compiler::host_function_id;
+
+    fn load(source: &str) -> Vm {
+        Vm::new(&assemble_source(source), &VmConfig::default()).unwrap()
+    }
+
+    /// Runs `code` with the given registers set and returns the registers when it exits.
+    fn run_with(registers: &[(usize, u64)], code: &str) -> [u64; 16] {
+        let mut vm = load(&format!("memory 4096 1024 1024\nentry 0\n{}\nexit\n", code));
+        for &(register, value) in registers {
+            vm.set_register(register, value);
+        }
+        vm.run().unwrap();
+        *vm.registers()
+    }
+
+    #[test]
+    fn writes_to_the_code_trap() {
+        let mut vm = load("memory 4096 1024 1024\nentry 0\nmovi r0 7\nstore #64 r0 0\nexit\n");
+        let trap = vm.run().unwrap_err();
+        assert_eq!(trap.kind, VmTrapKind::CodeWrite { address: 0 });
+
+        // Memory after the code stays writable.
+        let mut vm = load("memory 4096 1024 1024\nentry 0\nmovi r0 7\nstore #64 r0 2048\nexit\n");
+        assert_eq!(vm.run().unwrap(), 7);
+    }
+
+    #[test]
+    fn moving_the_stack_pointer_below_the_stack_traps() {
+        for instruction in ["lea sp 8", "load #64 sp 2048", "pop #64 sp", "movi sp 0"] {
+            let source = format!("memory 4096 1024 1024\nentry 0\n{}\nexit\n", instruction);
+            let trap = load(&source).run().unwrap_err();
+            assert!(
+                matches!(trap.kind, VmTrapKind::StackOverflow { .. }),
+                "{}: {}",
+                instruction,
+                trap
+            );
+        }
+    }
+
+    #[test]
+    fn traps_are_returned_again() {
+        let mut vm = load("memory 4096 1024 1024\nentry 0\nabort\nmovi r0 3\nexit\n");
+        assert_eq!(vm.run().unwrap_err().kind, VmTrapKind::Abort);
+        assert_eq!(vm.run().unwrap_err().kind, VmTrapKind::Abort);
+        assert_eq!(vm.step().unwrap_err().kind, VmTrapKind::Abort);
+
+        // Running out of fuel is not final.
+        let vm_config = VmConfig {
+            fuel: Some(1),
+            ..VmConfig::default()
+        };
+        let source = "memory 4096 1024 1024\nentry 0\nmovi r0 3\nexit\n";
+        let mut vm = Vm::new(&assemble_source(source), &vm_config).unwrap();
+        let trap = vm.run().unwrap_err();
+        assert_eq!(trap.kind, VmTrapKind::OutOfFuel { executed: 1 });
+        vm.refuel(1);
+        assert_eq!(vm.run().unwrap(), 3);
+    }
+
+    #[test]
+    fn memory_mut_redecodes_the_code() {
+        let mut vm = load("memory 4096 1024 1024\nentry 0\nmovi r0 7\nexit\n");
+        let immediate = 7i64.to_le_bytes();
+        let offset = vm
+            .memory()
+            .windows(immediate.len())
+            .position(|window| window == immediate)
+            .unwrap();
+        vm.memory_mut()[offset] = 9;
+        assert_eq!(vm.run().unwrap(), 9);
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        let (left, right) = (1.5f64.to_bits(), 0.25f64.to_bits());
+        let code = "fadd #64 r0 r4\nfsub #64 r1 r4\nfmul #64 r2 r4\nfdiv #64 r3 r4\nfneg #64 r5";
+        let registers = run_with(
+            &[
+                (0, left),
+                (1, left),
+                (2, left),
+                (3, left),
+                (4, right),
+                (5, left),
+            ],
+            code,
+        );
+        let results = registers[..6].iter().map(|&bits| f64::from_bits(bits));
+        assert_eq!(
+            results.collect::<Vec<_>>(),
+            [1.75, 1.25, 0.375, 6.0, 0.25, -1.5]
+        );
+
+        let code = "itof #64 r0\nitof #32 r1\nftoi #64 r2\nfcvt #32 r3\nfcvt #64 r4";
+        let registers = run_with(
+            &[
+                (0, -3i64 as u64),
+                (1, 5),
+                (2, (-2.75f64).to_bits()),
+                (3, 0.1f64.to_bits()),
+                (4, 0.1f32.to_bits() as u64),
+            ],
+            code,
+        );
+        assert_eq!(f64::from_bits(registers[0]), -3.0);
+        assert_eq!(f32::from_bits(registers[1] as u32), 5.0);
+        assert_eq!(registers[2] as i64, -2);
+        assert_eq!(f32::from_bits(registers[3] as u32), 0.1f32);
+        assert_eq!(f64::from_bits(registers[4]), 0.1f32 as f64);
+    }
+
+    #[test]
+    fn float_operations_round_to_single_precision() {
+        let big = 16777216f32.to_bits() as u64;
+        let one = 1f32.to_bits() as u64;
+        let (tenth, fifth) = (0.1f32.to_bits() as u64, 0.2f32.to_bits() as u64);
+        let registers = run_with(
+            &[(0, big), (1, one), (2, tenth), (3, fifth)],
+            "fadd #32 r0 r1\nfadd #32 r2 r3",
+        );
+        // 2^24 + 1 is not representable as a float.
+        assert_eq!(f32::from_bits(registers[0] as u32), 16777216.0);
+        assert_eq!(f32::from_bits(registers[2] as u32), 0.1f32 + 0.2f32);
+    }
+
+    #[test]
+    fn float_to_int_saturates() {
+        let values = [1e300, f64::NEG_INFINITY, f64::NAN];
+        let registers = run_with(
+            &[
+                (0, values[0].to_bits()),
+                (1, values[1].to_bits()),
+                (2, values[2].to_bits()),
+            ],
+            "ftoi #64 r0\nftoi #64 r1\nftoi #64 r2",
+        );
+        assert_eq!(registers[..3], [i64::MAX as u64, i64::MIN as u64, 0]);
+    }
+
+    #[test]
+    fn float_comparisons() {
+        /// The results of `setg`, `setge`, `setb`, `setbe` and `setz` after comparing the values.
+        fn compare(left: f64, right: f64) -> [u64; 5] {
+            let code = "fcmp #64 r0 r1\nsetg r2\nsetge r3\nsetb r4\nsetbe r5\nsetz r6";
+            let registers = run_with(&[(0, left.to_bits()), (1, right.to_bits())], code);
+            registers[2..7].try_into().unwrap()
+        }
+
+        assert_eq!(compare(1.0, 2.0), [0, 0, 1, 1, 0]);
+        assert_eq!(compare(2.0, 1.0), [1, 1, 0, 0, 0]);
+        assert_eq!(compare(1.0, 1.0), [0, 1, 0, 1, 1]);
+        // Every ordered comparison with NaN is false.
+        assert_eq!(compare(f64::NAN, 1.0), [0; 5]);
+        assert_eq!(compare(1.0, f64::NAN), [0; 5]);
+    }
+
+    #[test]
+    fn carry_and_overflow_flags() {
+        /// The carry and overflow flags after `op` on the two values.
+        fn flags(op: &str, left: i64, right: i64) -> (bool, bool) {
+            let code = format!("{} r0 r1\nsetb r2\nseto r3", op);
+            let registers = run_with(&[(0, left as u64), (1, right as u64)], &code);
+            (registers[2] == 1, registers[3] == 1)
+        }
+
+        assert_eq!(flags("add", -1, 1), (true, false));
+        assert_eq!(flags("add", i64::MAX, 1), (false, true));
+        assert_eq!(flags("add", i64::MIN, -1), (true, true));
+        assert_eq!(flags("add", 1, 1), (false, false));
+        assert_eq!(flags("sub", 0, 1), (true, false));
+        assert_eq!(flags("sub", i64::MIN, 1), (false, true));
+        assert_eq!(flags("sub", i64::MAX, -1), (true, true));
+        assert_eq!(flags("sub", 1, 1), (false, false));
+    }
+
+    #[test]
+    fn unsigned_and_overflow_jumps() {
+        /// Whether `jump` is taken after `op` on the two values.
+        fn taken(op: &str, jump: &str, left: i64, right: i64) -> bool {
+            let code = format!(
+                "{} r0 r1\n{} _taken\nmovi r2 0\nexit\n_taken:\nmovi r2 1",
+                op, jump
+            );
+            run_with(&[(0, left as u64), (1, right as u64)], &code)[2] == 1
+        }
+
+        // -1 is the largest unsigned value.
+        assert!(taken("cmp", "ja", -1, 1));
+        assert!(!taken("cmp", "ja", 1, -1));
+        assert!(!taken("cmp", "ja", 1, 1));
+        assert!(taken("cmp", "jb", 1, -1));
+        assert!(!taken("cmp", "jb", -1, 1));
+        assert!(taken("add", "jo", i64::MAX, 1));
+        assert!(!taken("add", "jo", -1, 1));
+    }
+
+    #[test]
+    fn unsigned_division_and_shift() {
+        let registers = run_with(
+            &[
+                (0, -7i64 as u64),
+                (1, -7i64 as u64),
+                (2, -7i64 as u64),
+                (3, 2),
+                (4, 60),
+            ],
+            "udiv r0 r3\numod r1 r3\nushr r2 r4",
+        );
+        assert_eq!(registers[..3], [(u64::MAX - 6) / 2, 1, 15]);
+    }
+
+    #[test]
+    fn arithmetic_wraps_around() {
+        let min = i64::MIN as u64;
+        let registers = run_with(
+            &[
+                (0, min),
+                (1, min),
+                (2, -1i64 as u64),
+                (3, i64::MAX as u64),
+                (4, 1),
+            ],
+            "div r0 r2\nmod r1 r2\naddi r3 1\nshli r4 64",
+        );
+        assert_eq!(registers[0], min);
+        assert_eq!(registers[1], 0);
+        assert_eq!(registers[3], min);
+        assert_eq!(registers[4], 0);
+    }
+
+    #[test]
+    fn checked_arithmetic_traps_on_overflow() {
+        /// Runs `op` on r0 and r1 with checked arithmetic and returns the exit code, r0.
+        fn checked(op: &str, left: i64, right: i64) -> Result<i64, VmTrapKind> {
+            let vm_config = VmConfig {
+                checked_arithmetic: true,
+                ..VmConfig::default()
+            };
+            let source = format!("memory 4096 1024 1024\nentry 0\n{} r0 r1\nexit\n", op);
+            let mut vm = Vm::new(&assemble_source(&source), &vm_config).unwrap();
+            vm.set_register(0, left as u64);
+            vm.set_register(1, right as u64);
+            vm.run().map_err(|trap| trap.kind)
+        }
+
+        let overflow = Err(VmTrapKind::ArithmeticOverflow);
+        assert_eq!(checked("add", i64::MAX, 1), overflow);
+        assert_eq!(checked("div", i64::MIN, -1), overflow);
+        assert_eq!(checked("shl", 1, 64), overflow);
+        assert_eq!(checked("add", i64::MAX - 1, 1), Ok(i64::MAX));
+        // Unsigned wrap around is not an overflow.
+        assert_eq!(checked("add", -1, 1), Ok(0));
+    }
+
+    #[test]
+    fn programs_resume_after_running_out_of_fuel_or_time() {
+        let source = "\
+memory 4096 1024 1024
+entry 0
+movi r0 0
+_loop:
+addi r0 1
+cmpi r0 100
+jl _loop
+exit
+";
+        let vm_config = VmConfig {
+            fuel: Some(50),
+            ..VmConfig::default()
+        };
+        let mut vm = Vm::new(&assemble_source(source), &vm_config).unwrap();
+        let trap = vm.run().unwrap_err();
+        assert_eq!(trap.kind, VmTrapKind::OutOfFuel { executed: 50 });
+        assert_eq!(vm.registers()[0], 17);
+        assert_eq!(vm.fuel(), Some(0));
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmTrapKind::OutOfFuel { executed: 50 }
+        );
+
+        vm.refuel(10);
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmTrapKind::OutOfFuel { executed: 60 }
+        );
+        vm.set_fuel(None);
+        vm.set_deadline(Some(Instant::now()));
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmTrapKind::Timeout { executed: 60 }
+        );
+        vm.set_deadline(None);
+        assert_eq!(vm.run().unwrap(), 100);
+        assert_eq!(vm.executed(), 1 + 3 * 100 + 1);
+    }
+
+    #[test]
+    fn host_functions_get_their_arguments_and_return_a_value() {
+        /// Calls `name(long, int, char)` with 40, -2 and 'x' the way the compiler does.
+        fn call(name: &str) -> Vm {
+            let source = format!(
+                "memory 4096 1024 1024\nentry 0\nmovi r3 40\npush #64 r3\nmovi r3 -2\n\
+                 push #32 r3\nmovi r3 120\npush #8 r3\nmov r1 sp\nmovi r2 13\n\
+                 hostcall r1 r2 {}\nexit\n",
+                host_function_id(name)
+            );
+            load(&source)
+        }
+
+        let mut vm = call("scale");
+        vm.register_host_function("scale", |call| {
+            let (value, factor) = (call.arg_long()?, call.arg_int()?);
+            let offset = call.arg_char()? as i64;
+            call.set_return((value * factor as i64 + offset) as u64);
+            Ok(())
+        });
+        assert_eq!(vm.run().unwrap(), 40);
+
+        let mut vm = call("missing");
+        vm.register_host_function("scale", |_| Ok(()));
+        let id = host_function_id("missing");
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmTrapKind::UnknownHostFunction { id }
+        );
+    }
+}
@@ -1,19 +1,28 @@
 use crate::assembler::core::{convert_line, instructions_to_bytes};
+use crate::ObjectFile;
 use std::path::PathBuf;
 
 mod core;
+mod disassembler;
 mod instruction_type;
 
+pub use crate::assembler::disassembler::disassemble;
+
 pub fn assemble(input_file: &PathBuf) -> Result<PathBuf, anyhow::Error> {
     let str = std::fs::read_to_string(input_file)?;
+    let object = assemble_source(&str);
+
+    let output = input_file.with_extension("o");
+    std::fs::write(&output, object.to_bytes())?;
+    Ok(output)
+}
+
+/// Assembles the text of a `.bud` file.
+pub fn assemble_source(source: &str) -> ObjectFile {
     let mut instructions = Vec::new();
-    for line in str.lines().filter(|line| !line.is_empty()) {
+    for line in source.lines().filter(|line| !line.is_empty()) {
         let instr = convert_line(line);
         instructions.push(instr);
     }
-    let bytes = instructions_to_bytes(instructions);
-
-    let output = input_file.with_extension("o");
-    std::fs::write(&output, bytes)?;
-    Ok(output)
+    instructions_to_bytes(instructions)
 }
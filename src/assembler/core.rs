@@ -1,6 +1,9 @@
-use crate::assembler::instruction_type::{Instruction, InstructionKind};
+use crate::assembler::instruction_type::{Instruction, InstructionKind, MemoryAddress};
+use crate::{
+    BinopType, FlagConditionType, FloatBinopType, LineRow, LineTable, ObjectFile, OpCode,
+    ProgramHeader, RegisterCode, SymbolTable, UnopType, VmMemoryConfig,
+};
 use lazy_static::lazy_static;
-use lychee_compiler::{BinopType, FlagConditionType, OpCode, RegisterCode, UnopType};
 use std::collections::HashMap;
 use std::iter::Iterator;
 
@@ -9,6 +12,27 @@ pub enum AssemblyInstruction {
     Label(String),
     Instr(Instruction),
     Bytes(Vec<u8>),
+    MemoryConfig(VmMemoryConfig),
+    Seed(u64),
+    Section(Section),
+    Entry(MemoryAddress),
+    /// Declares the source file with the given index in the line table.
+    File(usize, String),
+    /// Starts the code of a function in the line table.
+    Function(String),
+    /// Attributes the following code to a line of a source file.
+    Location {
+        file: usize,
+        line: u64,
+    },
+}
+
+/// The section the following lines are assembled into, selected with `section code` and
+/// `section rodata`. Only the code section may contain instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Code,
+    Rodata,
 }
 
 pub(crate) fn convert_line(line: &str) -> AssemblyInstruction {
@@ -22,41 +46,134 @@ pub(crate) fn convert_line(line: &str) -> AssemblyInstruction {
     if parts[0] == "bytes" {
         let mut bytes = Vec::new();
         for part in parts[1..].iter() {
-            bytes.push(u8::from_str_radix(part, 10).unwrap_or_else(|_| {
+            bytes.push(part.parse::<u8>().unwrap_or_else(|_| {
                 panic!("Invalid byte value: {}", part);
             }));
         }
         return AssemblyInstruction::Bytes(bytes);
     }
 
+    if parts[0] == "memory" {
+        let sizes = parts[1..]
+            .iter()
+            .map(|part| {
+                part.parse::<u64>().unwrap_or_else(|_| {
+                    panic!("Invalid memory size: {}", part);
+                })
+            })
+            .collect::<Vec<u64>>();
+        if sizes.len() != 3 {
+            panic!("Invalid memory config: {}", line);
+        }
+        return AssemblyInstruction::MemoryConfig(VmMemoryConfig {
+            memory_size: sizes[0],
+            heap_size: sizes[1],
+            stack_size: sizes[2],
+        });
+    }
+
+    if parts[0] == "seed" {
+        let seed = parts[1].parse::<u64>().unwrap_or_else(|_| {
+            panic!("Invalid seed: {}", parts[1]);
+        });
+        return AssemblyInstruction::Seed(seed);
+    }
+
+    if parts[0] == "section" {
+        let section = match parts[1] {
+            "code" => Section::Code,
+            "rodata" => Section::Rodata,
+            _ => panic!("Invalid section: {}", parts[1]),
+        };
+        return AssemblyInstruction::Section(section);
+    }
+
+    if parts[0] == "entry" {
+        let address = MemoryAddress::from_str(parts[1]);
+        if !matches!(
+            address,
+            MemoryAddress::Immediate(_) | MemoryAddress::Label(_)
+        ) {
+            panic!("Invalid entry point: {}", parts[1]);
+        }
+        return AssemblyInstruction::Entry(address);
+    }
+
+    if parts[0] == "file" {
+        let index = parts[1].parse::<usize>().unwrap_or_else(|_| {
+            panic!("Invalid file index: {}", parts[1]);
+        });
+        return AssemblyInstruction::File(index, parts[2..].join(" "));
+    }
+
+    if parts[0] == "func" {
+        return AssemblyInstruction::Function(parts[1..].join(" "));
+    }
+
+    if parts[0] == "loc" {
+        let values = parts[1..]
+            .iter()
+            .map(|part| {
+                part.parse::<u64>().unwrap_or_else(|_| {
+                    panic!("Invalid location: {}", line);
+                })
+            })
+            .collect::<Vec<u64>>();
+        if values.len() != 2 {
+            panic!("Invalid location: {}", line);
+        }
+        return AssemblyInstruction::Location {
+            file: values[0] as usize,
+            line: values[1],
+        };
+    }
+
     let opcode = match OPCODE_MAP.get(parts[0]).cloned() {
         Some(opcode) => opcode,
         None => panic!("Invalid opcode: {}", parts[0]),
     };
 
     let instruction_kind = match &opcode {
-        OpCode::Ret | OpCode::Exit => InstructionKind::parse_simple(),
+        OpCode::Ret | OpCode::Exit | OpCode::Abort => InstructionKind::parse_simple(),
         OpCode::Store | OpCode::Load => InstructionKind::parse_register_size_address(parts),
         OpCode::Push | OpCode::Pop | OpCode::SignExtend => {
             InstructionKind::parse_size_register(parts)
         }
-        OpCode::Binop(_) | OpCode::Alloc => InstructionKind::parse_two_registers(parts),
+        OpCode::Binop(_) | OpCode::Alloc | OpCode::Realloc | OpCode::FileSeek => {
+            InstructionKind::parse_two_registers(parts)
+        }
         OpCode::BinopImmediate(_) => InstructionKind::parse_register_immediate(parts),
         OpCode::Call | OpCode::Jump(_) => InstructionKind::parse_address(parts),
-        OpCode::Unop(_) | OpCode::Set(_) | OpCode::Free | OpCode::Rand | OpCode::FileClose => {
-            InstructionKind::parse_register(parts)
-        }
+        OpCode::Unop(_)
+        | OpCode::Set(_)
+        | OpCode::Free
+        | OpCode::Rand
+        | OpCode::SeedRand
+        | OpCode::FileClose
+        | OpCode::ArgCount
+        | OpCode::ArgLength
+        | OpCode::FileTell
+        | OpCode::FileSize => InstructionKind::parse_register(parts),
         OpCode::ReadStdin
         | OpCode::WriteStdout
         | OpCode::Lea
         | OpCode::PushMem
         | OpCode::PopMem
         | OpCode::PeekMem
-        | OpCode::FileOpen => InstructionKind::parse_register_address(parts),
-        OpCode::FileRead | OpCode::FileWrite | OpCode::MemSet => {
+        | OpCode::FileOpen
+        | OpCode::FileOpenMode
+        | OpCode::FileRemove => InstructionKind::parse_register_address(parts),
+        OpCode::FileRead | OpCode::FileWrite | OpCode::MemSet | OpCode::ArgRead => {
             InstructionKind::parse_two_registers_address(parts)
         }
-        OpCode::MemCopy => InstructionKind::parse_register_two_addresses(parts),
+        OpCode::MemCopy | OpCode::FileRename => {
+            InstructionKind::parse_register_two_addresses(parts)
+        }
+        OpCode::FloatBinop(_) => InstructionKind::parse_size_two_registers(parts),
+        OpCode::FloatNeg | OpCode::IntToFloat | OpCode::FloatToInt | OpCode::FloatConvert => {
+            InstructionKind::parse_size_register(parts)
+        }
+        OpCode::HostCall => InstructionKind::parse_two_registers_immediate(parts),
     };
 
     let instruction = Instruction {
@@ -67,44 +184,152 @@ pub(crate) fn convert_line(line: &str) -> AssemblyInstruction {
     AssemblyInstruction::Instr(instruction)
 }
 
-pub(crate) fn instructions_to_bytes(instructions: Vec<AssemblyInstruction>) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    let mut labels: HashMap<String, u64> = HashMap::new();
+pub(crate) fn instructions_to_bytes(instructions: Vec<AssemblyInstruction>) -> ObjectFile {
+    let mut header = ProgramHeader::default();
+    let mut entry = None;
+    let mut section = Section::Code;
+    let mut code = Vec::new();
+    let mut rodata = Vec::new();
+    // The addresses of the read-only data are only known once all code is assembled, so labels
+    // are recorded with their offset into their section and placeholders resolved at the end.
+    let mut labels: HashMap<String, (Section, u64)> = HashMap::new();
+    let mut code_labels: HashMap<String, u64> = HashMap::new();
     let mut label_placeholders: HashMap<String, Vec<u64>> = HashMap::new();
+    let mut line_table = LineTable::default();
+    let mut function_indices: HashMap<String, usize> = HashMap::new();
+    let mut current_function = None;
 
     for instruction in instructions {
         match instruction {
             AssemblyInstruction::Label(label) => {
-                let label_address = bytes.len() as u64;
-                if let Some(spots) = label_placeholders.get(&label) {
-                    for &spot in spots {
-                        let address_bytes: [u8; 8] = label_address.to_le_bytes();
-                        for i in 0..8 {
-                            bytes[spot as usize + i] = address_bytes[i];
-                        }
-                    }
+                let offset = match section {
+                    Section::Code => code.len(),
+                    Section::Rodata => rodata.len(),
+                } as u64;
+                if section == Section::Code {
+                    code_labels.insert(label.clone(), offset);
                 }
-                label_placeholders.remove(&label);
-                if let Some(_) = labels.insert(label, label_address) {
+                if labels.insert(label, (section, offset)).is_some() {
                     panic!("Label already exists");
                 }
             }
             AssemblyInstruction::Instr(instr) => {
-                instr.add_bytes(&mut bytes, &labels, &mut label_placeholders);
+                if section != Section::Code {
+                    panic!("Instructions must be in the code section");
+                }
+                instr.add_bytes(&mut code, &code_labels, &mut label_placeholders);
+            }
+            AssemblyInstruction::Bytes(mut b) => match section {
+                Section::Code => code.append(&mut b),
+                Section::Rodata => rodata.append(&mut b),
+            },
+            AssemblyInstruction::MemoryConfig(config) => {
+                header.config.memory = config;
+            }
+            AssemblyInstruction::Seed(seed) => {
+                header.config.seed = Some(seed);
+            }
+            AssemblyInstruction::Section(new_section) => {
+                section = new_section;
             }
-            AssemblyInstruction::Bytes(mut b) => {
-                bytes.append(&mut b);
+            AssemblyInstruction::Entry(address) => {
+                entry = Some(address);
             }
+            AssemblyInstruction::File(index, path) => {
+                if index != line_table.files.len() {
+                    panic!("File indices must be consecutive: {}", index);
+                }
+                line_table.files.push(path);
+            }
+            AssemblyInstruction::Function(name) => {
+                if section != Section::Code {
+                    panic!("Functions must be in the code section");
+                }
+                let next_index = line_table.functions.len();
+                let function = *function_indices.entry(name.clone()).or_insert_with(|| {
+                    line_table.functions.push(name);
+                    next_index
+                });
+                current_function = Some(function);
+                add_line_row(
+                    &mut line_table.rows,
+                    LineRow {
+                        address: code.len() as u64,
+                        function,
+                        file: 0,
+                        line: 0,
+                    },
+                );
+            }
+            AssemblyInstruction::Location { file, line } => {
+                let function = current_function.expect("Locations must follow a func directive");
+                if file >= line_table.files.len() {
+                    panic!("Unknown file index: {}", file);
+                }
+                add_line_row(
+                    &mut line_table.rows,
+                    LineRow {
+                        address: code.len() as u64,
+                        function,
+                        file,
+                        line,
+                    },
+                );
+            }
+        }
+    }
+
+    let rodata_start = code.len() as u64;
+    let labels = labels
+        .into_iter()
+        .map(|(label, (section, offset))| match section {
+            Section::Code => (label, offset),
+            Section::Rodata => (label, rodata_start + offset),
+        })
+        .collect::<HashMap<String, u64>>();
+    let label_address = |label: &String| {
+        *labels.get(label).unwrap_or_else(|| {
+            panic!("Unknown label: {}", label);
+        })
+    };
+    for (label, spots) in &label_placeholders {
+        let address_bytes = label_address(label).to_le_bytes();
+        for &spot in spots {
+            code[spot as usize..spot as usize + 8].copy_from_slice(&address_bytes);
         }
     }
+    header.entry = match entry {
+        Some(MemoryAddress::Label(label)) => label_address(&label),
+        Some(MemoryAddress::Immediate(address)) => address,
+        _ => 0,
+    };
 
-    bytes
+    ObjectFile {
+        header,
+        code,
+        rodata,
+        symbols: SymbolTable::new(labels),
+        line_table,
+    }
+}
+
+/// Appends `row` to the line table, replacing the last row if no code was emitted since and
+/// skipping it if it does not change the location.
+fn add_line_row(rows: &mut Vec<LineRow>, row: LineRow) {
+    match rows.last_mut() {
+        Some(last) if last.address == row.address => *last = row,
+        Some(last)
+            if (last.function, last.file, last.line) == (row.function, row.file, row.line) => {}
+        _ => rows.push(row),
+    }
 }
 
 lazy_static! {
     pub static ref OPCODE_MAP: HashMap<String, OpCode> = {
         HashMap::from([
             ("rand".to_string(), OpCode::Rand),
+            ("seedrand".to_string(), OpCode::SeedRand),
+            ("hostcall".to_string(), OpCode::HostCall),
             ("load".to_string(), OpCode::Load),
             ("store".to_string(), OpCode::Store),
             ("push".to_string(), OpCode::Push),
@@ -121,6 +346,9 @@ lazy_static! {
             ("shl".to_string(), OpCode::Binop(BinopType::Shl)),
             ("shr".to_string(), OpCode::Binop(BinopType::Shr)),
             ("cmp".to_string(), OpCode::Binop(BinopType::Cmp)),
+            ("udiv".to_string(), OpCode::Binop(BinopType::UnsignedDiv)),
+            ("umod".to_string(), OpCode::Binop(BinopType::UnsignedMod)),
+            ("ushr".to_string(), OpCode::Binop(BinopType::UnsignedShr)),
             ("movi".to_string(), OpCode::BinopImmediate(BinopType::Mov)),
             ("addi".to_string(), OpCode::BinopImmediate(BinopType::Add)),
             ("subi".to_string(), OpCode::BinopImmediate(BinopType::Sub)),
@@ -133,6 +361,18 @@ lazy_static! {
             ("shli".to_string(), OpCode::BinopImmediate(BinopType::Shl)),
             ("shri".to_string(), OpCode::BinopImmediate(BinopType::Shr)),
             ("cmpi".to_string(), OpCode::BinopImmediate(BinopType::Cmp)),
+            (
+                "udivi".to_string(),
+                OpCode::BinopImmediate(BinopType::UnsignedDiv),
+            ),
+            (
+                "umodi".to_string(),
+                OpCode::BinopImmediate(BinopType::UnsignedMod),
+            ),
+            (
+                "ushri".to_string(),
+                OpCode::BinopImmediate(BinopType::UnsignedShr),
+            ),
             ("jmp".to_string(), OpCode::Jump(FlagConditionType::Always)),
             ("jz".to_string(), OpCode::Jump(FlagConditionType::Zero)),
             ("jnz".to_string(), OpCode::Jump(FlagConditionType::NotZero)),
@@ -146,6 +386,21 @@ lazy_static! {
                 "jle".to_string(),
                 OpCode::Jump(FlagConditionType::LessEquals),
             ),
+            ("ja".to_string(), OpCode::Jump(FlagConditionType::Above)),
+            (
+                "jae".to_string(),
+                OpCode::Jump(FlagConditionType::AboveEquals),
+            ),
+            ("jb".to_string(), OpCode::Jump(FlagConditionType::Below)),
+            (
+                "jbe".to_string(),
+                OpCode::Jump(FlagConditionType::BelowEquals),
+            ),
+            ("jo".to_string(), OpCode::Jump(FlagConditionType::Overflow)),
+            (
+                "jno".to_string(),
+                OpCode::Jump(FlagConditionType::NotOverflow),
+            ),
             ("set".to_string(), OpCode::Set(FlagConditionType::Always)),
             ("setz".to_string(), OpCode::Set(FlagConditionType::Zero)),
             ("setnz".to_string(), OpCode::Set(FlagConditionType::NotZero)),
@@ -159,6 +414,21 @@ lazy_static! {
                 "setle".to_string(),
                 OpCode::Set(FlagConditionType::LessEquals),
             ),
+            ("seta".to_string(), OpCode::Set(FlagConditionType::Above)),
+            (
+                "setae".to_string(),
+                OpCode::Set(FlagConditionType::AboveEquals),
+            ),
+            ("setb".to_string(), OpCode::Set(FlagConditionType::Below)),
+            (
+                "setbe".to_string(),
+                OpCode::Set(FlagConditionType::BelowEquals),
+            ),
+            ("seto".to_string(), OpCode::Set(FlagConditionType::Overflow)),
+            (
+                "setno".to_string(),
+                OpCode::Set(FlagConditionType::NotOverflow),
+            ),
             ("not".to_string(), OpCode::Unop(UnopType::Not)),
             ("neg".to_string(), OpCode::Unop(UnopType::Neg)),
             ("inc".to_string(), OpCode::Unop(UnopType::Inc)),
@@ -175,12 +445,32 @@ lazy_static! {
             ("peekmem".to_string(), OpCode::PeekMem),
             ("alloc".to_string(), OpCode::Alloc),
             ("free".to_string(), OpCode::Free),
+            ("realloc".to_string(), OpCode::Realloc),
             ("fileopen".to_string(), OpCode::FileOpen),
             ("fileclose".to_string(), OpCode::FileClose),
             ("fileread".to_string(), OpCode::FileRead),
             ("filewrite".to_string(), OpCode::FileWrite),
             ("memcopy".to_string(), OpCode::MemCopy),
             ("memset".to_string(), OpCode::MemSet),
+            ("fadd".to_string(), OpCode::FloatBinop(FloatBinopType::Add)),
+            ("fsub".to_string(), OpCode::FloatBinop(FloatBinopType::Sub)),
+            ("fmul".to_string(), OpCode::FloatBinop(FloatBinopType::Mul)),
+            ("fdiv".to_string(), OpCode::FloatBinop(FloatBinopType::Div)),
+            ("fcmp".to_string(), OpCode::FloatBinop(FloatBinopType::Cmp)),
+            ("fneg".to_string(), OpCode::FloatNeg),
+            ("itof".to_string(), OpCode::IntToFloat),
+            ("ftoi".to_string(), OpCode::FloatToInt),
+            ("fcvt".to_string(), OpCode::FloatConvert),
+            ("abort".to_string(), OpCode::Abort),
+            ("argc".to_string(), OpCode::ArgCount),
+            ("arglen".to_string(), OpCode::ArgLength),
+            ("argread".to_string(), OpCode::ArgRead),
+            ("fileopenmode".to_string(), OpCode::FileOpenMode),
+            ("fileseek".to_string(), OpCode::FileSeek),
+            ("filetell".to_string(), OpCode::FileTell),
+            ("filesize".to_string(), OpCode::FileSize),
+            ("fileremove".to_string(), OpCode::FileRemove),
+            ("filerename".to_string(), OpCode::FileRename),
         ])
     };
     pub static ref REGISTER_MAP: HashMap<String, RegisterCode> = {
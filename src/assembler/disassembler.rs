@@ -0,0 +1,642 @@
+use crate::assembler::core::{OPCODE_MAP, REGISTER_MAP, SIZE_MAP};
+use crate::assembler::instruction_type::{Instruction, InstructionKind, MemoryAddress};
+use crate::{LineTable, ObjectFile, OpCode};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+lazy_static! {
+    /// The mnemonic and opcode of every opcode byte, the reverse of `OPCODE_MAP`.
+    static ref BYTE_CODE_MAP: HashMap<u8, (&'static str, OpCode)> = OPCODE_MAP
+        .iter()
+        .map(|(mnemonic, opcode)| (opcode.byte_code(), (mnemonic.as_str(), opcode.clone())))
+        .collect();
+    static ref REGISTER_NAMES: HashMap<u8, &'static str> = REGISTER_MAP
+        .iter()
+        .map(|(name, register)| (register.clone() as u8, name.as_str()))
+        .collect();
+    static ref SIZE_NAMES: HashMap<u8, &'static str> = SIZE_MAP
+        .iter()
+        .map(|(name, size)| (*size, name.as_str()))
+        .collect();
+}
+
+/// Turns an object file back into `.bud` text that assembles to the same object file, naming
+/// the addresses that have a label in its symbol table and keeping its line table.
+///
+/// The code is decoded with a linear sweep that restarts at every label. Bytes that do not
+/// start an instruction the assembler would have encoded the same way are written as `bytes`
+/// lines, like the read-only data.
+pub fn disassemble(object: &ObjectFile) -> String {
+    let header = &object.header;
+    let memory = &header.config.memory;
+    let mut lines = vec![format!(
+        "memory {} {} {}",
+        memory.memory_size, memory.heap_size, memory.stack_size
+    )];
+    if let Some(seed) = header.config.seed {
+        lines.push(format!("seed {}", seed));
+    }
+
+    // Only labels starting with an underscore can be used as an address operand. Of several
+    // labels at the same address, the first one is used.
+    let symbols = &object.symbols.symbols;
+    let mut labels = HashMap::new();
+    for (address, label) in symbols {
+        if label.starts_with('_') {
+            labels.entry(*address).or_insert(label.as_str());
+        }
+    }
+    let entry = MemoryAddress::Immediate(header.entry);
+    lines.push(format!("entry {}", format_address(&entry, &labels)));
+    for (index, file) in object.line_table.files.iter().enumerate() {
+        lines.push(format!("file {} {}", index, file));
+    }
+
+    let code = &object.code;
+    let mut next_symbol = 0;
+    let mut rows = LineRows {
+        table: &object.line_table,
+        next: 0,
+        function: None,
+    };
+    let mut data = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        write_labels(&mut lines, &mut data, symbols, &mut next_symbol, pc);
+        rows.write(&mut lines, &mut data, pc);
+        let end = symbols
+            .get(next_symbol)
+            .map_or(code.len(), |(address, _)| *address as usize)
+            .min(rows.next_address())
+            .min(code.len());
+        match disassemble_instruction(&code[pc..end], &labels) {
+            Some((line, length)) => {
+                flush_data(&mut lines, &mut data);
+                lines.push(line);
+                pc += length;
+            }
+            None => {
+                data.push(code[pc]);
+                pc += 1;
+            }
+        }
+    }
+    rows.write(&mut lines, &mut data, code.len());
+    flush_data(&mut lines, &mut data);
+
+    if !object.rodata.is_empty() {
+        lines.push("section rodata".to_string());
+        for (offset, byte) in object.rodata.iter().enumerate() {
+            let address = code.len() + offset;
+            write_labels(&mut lines, &mut data, symbols, &mut next_symbol, address);
+            data.push(*byte);
+        }
+        flush_data(&mut lines, &mut data);
+    }
+    for (_, label) in &symbols[next_symbol..] {
+        lines.push(format!("{}:", label));
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+/// Writes the labels up to `address` that have not been written yet, after the pending data.
+fn write_labels(
+    lines: &mut Vec<String>,
+    data: &mut Vec<u8>,
+    symbols: &[(u64, String)],
+    next_symbol: &mut usize,
+    address: usize,
+) {
+    while let Some((symbol_address, label)) = symbols.get(*next_symbol) {
+        if *symbol_address as usize > address {
+            break;
+        }
+        flush_data(lines, data);
+        lines.push(format!("{}:", label));
+        *next_symbol += 1;
+    }
+}
+
+/// The rows of a line table that are still to be written as `func` and `loc` lines.
+struct LineRows<'a> {
+    table: &'a LineTable,
+    next: usize,
+    /// The function of the last `func` line.
+    function: Option<usize>,
+}
+
+impl LineRows<'_> {
+    fn next_address(&self) -> usize {
+        self.table
+            .rows
+            .get(self.next)
+            .map_or(usize::MAX, |row| row.address as usize)
+    }
+
+    /// Writes the rows up to `address` that have not been written yet, after the pending data.
+    fn write(&mut self, lines: &mut Vec<String>, data: &mut Vec<u8>, address: usize) {
+        while let Some(row) = self.table.rows.get(self.next) {
+            if row.address as usize > address {
+                break;
+            }
+            flush_data(lines, data);
+            if row.line == 0 || self.function != Some(row.function) {
+                lines.push(format!("func {}", self.table.functions[row.function]));
+                self.function = Some(row.function);
+            }
+            if row.line != 0 {
+                lines.push(format!("loc {} {}", row.file, row.line));
+            }
+            self.next += 1;
+        }
+    }
+}
+
+fn flush_data(lines: &mut Vec<String>, data: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    let bytes = data
+        .iter()
+        .map(|byte| byte.to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    lines.push(format!("bytes {}", bytes));
+    data.clear();
+}
+
+/// Disassembles the instruction at the start of `bytes`, returning its line and its length.
+fn disassemble_instruction(bytes: &[u8], labels: &HashMap<u64, &str>) -> Option<(String, usize)> {
+    let mut reader = OperandReader { bytes, offset: 0 };
+    let (mnemonic, opcode) = BYTE_CODE_MAP.get(&reader.byte()?)?;
+    let instruction = Instruction {
+        opcode: opcode.byte_code(),
+        kind: reader.instruction_kind(opcode)?,
+    };
+
+    // Reject encodings with bits the decoder ignores, which would not survive a round trip.
+    let mut encoded = Vec::new();
+    instruction.add_bytes(&mut encoded, &HashMap::new(), &mut HashMap::new());
+    if encoded[..] != bytes[..reader.offset] {
+        return None;
+    }
+
+    let mut line = mnemonic.to_string();
+    for operand in operands(&instruction.kind, labels) {
+        line.push(' ');
+        line.push_str(&operand);
+    }
+    Some((line, reader.offset))
+}
+
+/// Reads the operands of an instruction in the encoding written by `Instruction::add_bytes`.
+struct OperandReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl OperandReader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.offset)?;
+        self.offset += 1;
+        Some(byte)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.bytes.get(self.offset..self.offset + 8)?;
+        self.offset += 8;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn register(&mut self) -> Option<u8> {
+        self.byte().filter(|register| *register < 16)
+    }
+
+    /// Reads a byte holding a size code in bits 4-5 and a register in the low nibble.
+    fn size_register(&mut self) -> Option<(u8, u8)> {
+        let byte = self.byte()?;
+        (byte < 0x40).then_some((byte >> 4, byte & 0x0F))
+    }
+
+    /// Reads a byte holding two registers, returning the low nibble first.
+    fn two_registers(&mut self) -> Option<(u8, u8)> {
+        let byte = self.byte()?;
+        Some((byte & 0x0F, byte >> 4))
+    }
+
+    fn address(&mut self) -> Option<MemoryAddress> {
+        let first_byte = self.byte()?;
+        let register = first_byte >> 4;
+        Some(match first_byte & 0b11 {
+            0 => MemoryAddress::Immediate(self.u64()?),
+            1 => MemoryAddress::Register(register),
+            2 => MemoryAddress::RegisterOffset(register, self.u64()? as i64),
+            _ => {
+                let (register, index_register) = self.two_registers()?;
+                MemoryAddress::RegisterScaledIndex(register, index_register, self.u64()? as i64)
+            }
+        })
+    }
+
+    /// Reads the operands of `opcode`, in the layout chosen for it by `convert_line`.
+    fn instruction_kind(&mut self, opcode: &OpCode) -> Option<InstructionKind> {
+        Some(match opcode {
+            OpCode::Ret | OpCode::Exit | OpCode::Abort => InstructionKind::Simple,
+            OpCode::Store | OpCode::Load => {
+                let (size, register) = self.size_register()?;
+                InstructionKind::SizeRegisterAddress {
+                    register,
+                    size,
+                    address: self.address()?,
+                }
+            }
+            OpCode::Push
+            | OpCode::Pop
+            | OpCode::SignExtend
+            | OpCode::FloatNeg
+            | OpCode::IntToFloat
+            | OpCode::FloatToInt
+            | OpCode::FloatConvert => {
+                let (size, register) = self.size_register()?;
+                InstructionKind::SizeRegister { size, register }
+            }
+            OpCode::Binop(_) | OpCode::Alloc | OpCode::Realloc | OpCode::FileSeek => {
+                let (left_register, right_register) = self.two_registers()?;
+                InstructionKind::TwoRegisters {
+                    left_register,
+                    right_register,
+                }
+            }
+            OpCode::BinopImmediate(_) => InstructionKind::RegisterImmediate {
+                register: self.register()?,
+                immediate: self.u64()? as i64,
+            },
+            OpCode::Call | OpCode::Jump(_) => InstructionKind::Address {
+                address: self.address()?,
+            },
+            OpCode::Unop(_)
+            | OpCode::Set(_)
+            | OpCode::Free
+            | OpCode::Rand
+            | OpCode::SeedRand
+            | OpCode::FileClose
+            | OpCode::ArgCount
+            | OpCode::ArgLength
+            | OpCode::FileTell
+            | OpCode::FileSize => InstructionKind::Register {
+                register: self.register()?,
+            },
+            OpCode::ReadStdin
+            | OpCode::WriteStdout
+            | OpCode::Lea
+            | OpCode::PushMem
+            | OpCode::PopMem
+            | OpCode::PeekMem
+            | OpCode::FileOpen
+            | OpCode::FileOpenMode
+            | OpCode::FileRemove => InstructionKind::RegisterAddress {
+                register: self.register()?,
+                address: self.address()?,
+            },
+            OpCode::FileRead | OpCode::FileWrite | OpCode::MemSet | OpCode::ArgRead => {
+                let (left_register, right_register) = self.two_registers()?;
+                InstructionKind::TwoRegistersAddress {
+                    left_register,
+                    right_register,
+                    address: self.address()?,
+                }
+            }
+            OpCode::MemCopy | OpCode::FileRename => InstructionKind::RegisterTwoAddresses {
+                register: self.register()?,
+                address1: self.address()?,
+                address2: self.address()?,
+            },
+            OpCode::FloatBinop(_) => {
+                let (size, left_register) = self.size_register()?;
+                InstructionKind::SizeTwoRegisters {
+                    size,
+                    left_register,
+                    right_register: self.register()?,
+                }
+            }
+            OpCode::HostCall => {
+                let (left_register, right_register) = self.two_registers()?;
+                InstructionKind::TwoRegistersImmediate {
+                    left_register,
+                    right_register,
+                    immediate: self.u64()?,
+                }
+            }
+        })
+    }
+}
+
+/// The operands of an instruction as written in the assembly, in the order `convert_line`
+/// parses them.
+fn operands(kind: &InstructionKind, labels: &HashMap<u64, &str>) -> Vec<String> {
+    let register = |register: &u8| REGISTER_NAMES[register].to_string();
+    let size = |size: &u8| SIZE_NAMES[size].to_string();
+    let address = |address: &MemoryAddress| format_address(address, labels);
+    match kind {
+        InstructionKind::Simple => vec![],
+        InstructionKind::Register { register: r } => vec![register(r)],
+        InstructionKind::SizeRegister {
+            size: s,
+            register: r,
+        } => vec![size(s), register(r)],
+        InstructionKind::SizeTwoRegisters {
+            size: s,
+            left_register,
+            right_register,
+        } => vec![size(s), register(left_register), register(right_register)],
+        InstructionKind::Address { address: a } => vec![address(a)],
+        InstructionKind::RegisterAddress {
+            register: r,
+            address: a,
+        } => vec![register(r), address(a)],
+        InstructionKind::SizeRegisterAddress {
+            register: r,
+            size: s,
+            address: a,
+        } => vec![size(s), register(r), address(a)],
+        InstructionKind::RegisterImmediate {
+            register: r,
+            immediate,
+        } => vec![register(r), immediate.to_string()],
+        InstructionKind::TwoRegisters {
+            left_register,
+            right_register,
+        } => vec![register(left_register), register(right_register)],
+        InstructionKind::TwoRegistersAddress {
+            left_register,
+            right_register,
+            address: a,
+        } => vec![
+            register(left_register),
+            register(right_register),
+            address(a),
+        ],
+        InstructionKind::RegisterTwoAddresses {
+            register: r,
+            address1,
+            address2,
+        } => vec![register(r), address(address1), address(address2)],
+        InstructionKind::TwoRegistersImmediate {
+            left_register,
+            right_register,
+            immediate,
+        } => vec![
+            register(left_register),
+            register(right_register),
+            immediate.to_string(),
+        ],
+    }
+}
+
+fn format_address(address: &MemoryAddress, labels: &HashMap<u64, &str>) -> String {
+    let register = |register: &u8| REGISTER_NAMES[register];
+    match address {
+        MemoryAddress::Immediate(address) => match labels.get(address) {
+            Some(label) => label.to_string(),
+            None => address.to_string(),
+        },
+        MemoryAddress::Register(r) => format!("[{}]", register(r)),
+        MemoryAddress::RegisterOffset(r, offset) => format!("[{};{}]", register(r), offset),
+        MemoryAddress::RegisterScaledIndex(r, index, scale) => {
+            format!("[{};{};{}]", register(r), register(index), scale)
+        }
+        MemoryAddress::Label(label) => label.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_source;
+    use crate::SymbolTable;
+
+    /// Uses every opcode and addressing mode, written the way the disassembler prints them.
+    const EVERY_OPCODE: &str = "\
+memory 1048576 65536 8192
+seed 42
+entry _main
+_main:
+load #64 r0 [bp;-8]
+store #32 r1 [r2]
+load #8 r3 [r4;r5;8]
+store #16 r6 1024
+push #64 bp
+pop #8 r12
+mov r0 r1
+add r2 r3
+sub r4 r5
+mul r6 r7
+div r8 r9
+mod r10 r11
+and r12 bp
+or sp pc
+xor r0 r0
+shl r1 r2
+shr r3 r4
+cmp r5 r6
+udiv r7 r8
+umod r9 r10
+ushr r11 r12
+movi r0 -5
+addi r1 9223372036854775807
+subi r2 -9223372036854775808
+muli r3 3
+divi r4 4
+modi r5 5
+andi r6 255
+ori r7 256
+xori r8 -1
+shli r9 2
+shri r10 3
+cmpi r11 0
+udivi r12 7
+umodi bp 8
+ushri sp 63
+_loop:
+jmp _loop
+jz _end
+jnz _main
+jg _loop
+jge _end
+jl 7
+jle [r0]
+ja [r1;16]
+jae [r2;r3;-1]
+jb _end
+jbe _end
+jo _end
+jno _end
+set r0
+setz r1
+setnz r2
+setg r3
+setge r4
+setl r5
+setle r6
+seta r7
+setae r8
+setb r9
+setbe r10
+seto r11
+setno r12
+not r0
+neg r1
+inc r2
+dec r3
+call _main
+call [r4]
+ret
+read r0 [r1]
+write r0 [sp;16]
+rand r2
+seedrand r3
+signext #32 r4
+lea r0 [bp;-24]
+lea r1 _constant
+pushmem r1 [r2]
+popmem r1 [bp;20]
+peekmem r1 [r2;r3;-4]
+alloc r0 r1
+free r0
+realloc r2 r3
+fileopen r0 [r1]
+fileclose r0
+fileread r0 r1 [r2]
+filewrite r0 r1 [r2;8]
+memcopy r0 [r1] [r2;-8]
+memset r0 r1 [sp]
+hostcall r1 r2 12345678901234567890
+fadd #32 r0 r1
+fsub #64 r2 r3
+fmul #32 r4 r5
+fdiv #64 r6 r7
+fcmp #64 r8 r9
+fneg #64 r0
+itof #32 r1
+ftoi #64 r2
+fcvt #32 r3
+_end:
+exit
+abort
+argc r0
+arglen r1
+argread r1 r2 [r3]
+fileopenmode r4 [r5;8]
+fileseek r6 r7
+filetell r8
+filesize r9
+fileremove r10 [r11]
+filerename r12 [r1] [r2;-4]
+section rodata
+_constant:
+bytes 104 105 255
+";
+
+    fn round_trip(source: &str, symbols: bool) -> String {
+        let mut object = assemble_source(source);
+        if !symbols {
+            object.symbols = SymbolTable::default();
+        }
+        let text = disassemble(&object);
+        assert_eq!(assemble_source(&text), object);
+        text
+    }
+
+    #[test]
+    fn round_trip_covers_every_opcode() {
+        let text = round_trip(EVERY_OPCODE, true);
+        assert_eq!(text, EVERY_OPCODE);
+
+        for mnemonic in OPCODE_MAP.keys() {
+            assert!(
+                text.lines()
+                    .any(|line| line.split(' ').next() == Some(mnemonic.as_str())),
+                "{} is not covered",
+                mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip_without_symbols() {
+        let text = round_trip(EVERY_OPCODE, false);
+        assert!(text.contains("\njmp 221\njz 532\njnz 0\n"), "{}", text);
+        // The read-only data is placed right after the code.
+        assert!(text.contains("\nlea r1 573\n"), "{}", text);
+        assert!(!text.contains(':'));
+    }
+
+    #[test]
+    fn round_trip_data() {
+        // Truncated instructions, non-canonical encodings and unknown opcodes stay data.
+        let source = "\
+memory 4096 1024 1024
+entry _start
+_start:
+jmp _end
+_data:
+bytes 1 3
+_more:
+bytes 3 64 17 32 255
+_end:
+exit
+";
+        assert_eq!(round_trip(source, true), source);
+        assert_eq!(
+            round_trip(source, false),
+            "\
+memory 4096 1024 1024
+entry 0
+jmp 17
+bytes 1
+push #8 r3
+bytes 64 17 32 255
+exit
+"
+        );
+    }
+
+    #[test]
+    fn round_trip_line_table() {
+        let source = "\
+memory 4096 1024 1024
+entry _start
+file 0 src/main.lyc
+file 1 src/std/vec.lyc
+_start:
+func start
+call _main
+exit
+_main:
+func main
+loc 0 3
+movi r0 1
+loc 1 112
+call _pop
+loc 0 4
+ret
+_pop:
+func pop<int>
+loc 1 94
+ret
+func exit
+exit
+";
+        assert_eq!(round_trip(source, true), source);
+        let object = assemble_source(source);
+        let rows = &object.line_table.rows;
+        assert_eq!(
+            object.line_table.describe(rows[2].address),
+            "src/std/vec.lyc:112 in main"
+        );
+        assert_eq!(object.line_table.describe(0), "address 0 in start");
+    }
+}
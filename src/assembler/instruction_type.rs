@@ -11,18 +11,16 @@ pub enum MemoryAddress {
 }
 
 fn parse_i64(str: &str) -> i64 {
-    if str.starts_with("0x") {
-        i64::from_str_radix(&str[2..], 16).unwrap()
-    } else {
-        i64::from_str_radix(str, 10).unwrap()
+    match str.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).unwrap(),
+        None => str.parse().unwrap(),
     }
 }
 
 fn parse_u64(str: &str) -> u64 {
-    if str.starts_with("0x") {
-        u64::from_str_radix(&str[2..], 16).unwrap()
-    } else {
-        u64::from_str_radix(str, 10).unwrap()
+    match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).unwrap(),
+        None => str.parse().unwrap(),
     }
 }
 
@@ -81,7 +79,7 @@ impl MemoryAddress {
                 let address = labels.get(label).cloned().unwrap_or_else(|| {
                     label_placeholders
                         .entry(label.clone())
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push((bytes.len() + 1) as u64);
                     0
                 });
@@ -108,6 +106,11 @@ pub enum InstructionKind {
         size: u8,
         register: u8,
     },
+    SizeTwoRegisters {
+        size: u8,
+        left_register: u8,
+        right_register: u8,
+    },
     Address {
         address: MemoryAddress,
     },
@@ -138,6 +141,11 @@ pub enum InstructionKind {
         address1: MemoryAddress,
         address2: MemoryAddress,
     },
+    TwoRegistersImmediate {
+        left_register: u8,
+        right_register: u8,
+        immediate: u64,
+    },
 }
 
 impl InstructionKind {
@@ -156,6 +164,17 @@ impl InstructionKind {
         InstructionKind::SizeRegister { size, register }
     }
 
+    pub fn parse_size_two_registers(parts: Vec<&str>) -> Self {
+        let size = parse_size_code(parts[1]);
+        let left_register = parse_register_code(parts[2]);
+        let right_register = parse_register_code(parts[3]);
+        InstructionKind::SizeTwoRegisters {
+            size,
+            left_register,
+            right_register,
+        }
+    }
+
     pub fn parse_address(parts: Vec<&str>) -> Self {
         let address = MemoryAddress::from_str(parts[1]);
         InstructionKind::Address { address }
@@ -217,6 +236,17 @@ impl InstructionKind {
             address2,
         }
     }
+
+    pub fn parse_two_registers_immediate(parts: Vec<&str>) -> Self {
+        let left_register = parse_register_code(parts[1]);
+        let right_register = parse_register_code(parts[2]);
+        let immediate = parse_u64(parts[3]);
+        InstructionKind::TwoRegistersImmediate {
+            left_register,
+            right_register,
+            immediate,
+        }
+    }
 }
 
 fn parse_register_code(part: &str) -> u8 {
@@ -248,6 +278,14 @@ impl Instruction {
             InstructionKind::SizeRegister { size, register } => {
                 bytes.push((size << 4) | register);
             }
+            InstructionKind::SizeTwoRegisters {
+                size,
+                left_register,
+                right_register,
+            } => {
+                bytes.push((size << 4) | left_register);
+                bytes.push(*right_register);
+            }
             InstructionKind::Address { address } => {
                 address.add_bytes(bytes, labels, label_placeholders);
             }
@@ -293,6 +331,14 @@ impl Instruction {
                 address1.add_bytes(bytes, labels, label_placeholders);
                 address2.add_bytes(bytes, labels, label_placeholders);
             }
+            InstructionKind::TwoRegistersImmediate {
+                left_register,
+                right_register,
+                immediate,
+            } => {
+                bytes.push(left_register | (right_register << 4));
+                bytes.extend(&immediate.to_le_bytes());
+            }
         };
     }
 }
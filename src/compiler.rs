@@ -56,6 +56,7 @@ pub fn compile(
 
     println!("Generating code...");
     let assembly_output = output_dir.join(format!("{}.bud", config.config.package.name));
-    codegen::gen_code(resolved_program, &assembly_output);
+    let vm_config = config.config.vm_config();
+    codegen::gen_code(resolved_program, &vm_config, &assembly_output);
     Ok(assembly_output)
 }
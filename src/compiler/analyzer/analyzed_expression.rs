@@ -96,6 +96,7 @@ pub enum AnalyzedLiteral {
     Bool(bool),
     Char(i8),
     Integer(i64),
+    Float(f64),
 }
 
 #[derive(Debug, Clone)]
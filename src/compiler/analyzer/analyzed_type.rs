@@ -11,6 +11,7 @@ pub enum AnalyzedTypeId {
     Bool,
     Char,
     Integer(usize),
+    Float(usize),
     Pointer(Box<AnalyzedTypeId>),
     StructType(StructRef),
     EnumType(ItemId),
@@ -31,6 +32,11 @@ impl Display for AnalyzedTypeId {
                 8 => write!(f, "long"),
                 _ => unreachable!("Invalid integer size: {}", size),
             },
+            AnalyzedTypeId::Float(size) => match size {
+                4 => write!(f, "float"),
+                8 => write!(f, "double"),
+                _ => unreachable!("Invalid float size: {}", size),
+            },
             AnalyzedTypeId::Pointer(inner) => write!(f, "&{}", inner),
             AnalyzedTypeId::StructType(struct_id) => {
                 write!(f, "{}", struct_id)
@@ -259,6 +259,9 @@ pub fn can_cast_to(original_type: &AnalyzedTypeId, target_type: &AnalyzedTypeId)
     }
     match (original_type, target_type) {
         (AnalyzedTypeId::Integer(_), AnalyzedTypeId::Integer(_)) => true,
+        (AnalyzedTypeId::Integer(_), AnalyzedTypeId::Float(_)) => true,
+        (AnalyzedTypeId::Float(_), AnalyzedTypeId::Integer(_)) => true,
+        (AnalyzedTypeId::Float(_), AnalyzedTypeId::Float(_)) => true,
         (AnalyzedTypeId::Char, AnalyzedTypeId::Integer(_)) => true,
         (AnalyzedTypeId::Integer(_), AnalyzedTypeId::Char) => true,
         (AnalyzedTypeId::Bool, AnalyzedTypeId::Integer(_)) => true,
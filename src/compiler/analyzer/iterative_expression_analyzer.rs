@@ -9,10 +9,10 @@ use crate::compiler::analyzer::program_analyzer::{AnalyzerContext, LocalVariable
 use crate::compiler::analyzer::AnalyzerResult;
 use crate::compiler::lexer::location::Location;
 use crate::compiler::merger::merged_expression::StructRef;
-use crate::compiler::parser::binary_op::{BinaryComparisonOp, BinaryOp};
+use crate::compiler::parser::binary_op::{BinaryComparisonOp, BinaryMathOp, BinaryOp};
 use crate::compiler::parser::item_id::ParsedGenericId;
 use crate::compiler::parser::parsed_expression::{
-    ParsedExpression, ParsedExpressionKind, ParsedLiteral, ParsedType, UnaryOp,
+    ParsedExpression, ParsedExpressionKind, ParsedLiteral, ParsedType, UnaryMathOp, UnaryOp,
 };
 use anyhow::Context;
 use std::collections::HashMap;
@@ -438,6 +438,10 @@ pub fn analyze_expression(
                             AnalyzedExpressionKind::Literal(AnalyzedLiteral::Integer(*val)),
                         )
                     }
+                    ParsedLiteral::Float(val) => (
+                        AnalyzedTypeId::Float(8),
+                        AnalyzedExpressionKind::Literal(AnalyzedLiteral::Float(*val)),
+                    ),
                     ParsedLiteral::String(val) => {
                         let mut bytes = val.as_bytes().to_vec();
                         bytes.push(0);
@@ -507,6 +511,7 @@ pub fn analyze_expression(
                         let analyzed_expr = output.pop().unwrap();
                         match analyzed_expr.ty {
                             AnalyzedTypeId::Integer(_) => {}
+                            AnalyzedTypeId::Float(_) if *math_op != UnaryMathOp::BitwiseNot => {}
                             _ => Err(anyhow::anyhow!(
                                 "Math unary expression has non-integer type '{}' at {}.",
                                 analyzed_expr.ty,
@@ -742,6 +747,7 @@ pub fn analyze_expression(
                         let analyzed_left = output.pop().unwrap();
                         match &analyzed_left.ty {
                             AnalyzedTypeId::Integer(_) => {}
+                            AnalyzedTypeId::Float(_) if is_float_math_op(math_op) => {}
                             _ => Err(anyhow::anyhow!(
                                 "Math binary left expression has non-integer type '{}' at {}.",
                                 analyzed_left.ty,
@@ -796,7 +802,7 @@ pub fn analyze_expression(
                             _ => true,
                         };
                         match &analyzed_left.ty {
-                            AnalyzedTypeId::Integer(_) => {}
+                            AnalyzedTypeId::Integer(_) | AnalyzedTypeId::Float(_) => {}
                             AnalyzedTypeId::Char
                             | AnalyzedTypeId::Bool
                             | AnalyzedTypeId::EnumType(_)
@@ -847,6 +853,7 @@ pub fn analyze_expression(
 
                         match &analyzed_left.ty {
                             AnalyzedTypeId::Integer(_) => {}
+                            AnalyzedTypeId::Float(_) if is_float_math_op(math_op) => {}
                             _ => Err(anyhow::anyhow!(
                             "Math assign binary left expression has non-integer type '{}' at {}.",
                             analyzed_left.ty,
@@ -1383,3 +1390,11 @@ fn determine_function_call(
         ))
     }
 }
+
+/// Floats only support the arithmetic operators, not `%` or the bitwise ones.
+fn is_float_math_op(math_op: &BinaryMathOp) -> bool {
+    matches!(
+        math_op,
+        BinaryMathOp::Add | BinaryMathOp::Sub | BinaryMathOp::Mul | BinaryMathOp::Div
+    )
+}
@@ -148,6 +148,27 @@ impl BuiltinFunction {
             }),
         )
     }
+
+    fn realloc() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "realloc".to_string(),
+            AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Unit)),
+            vec![
+                (
+                    "pointer".to_string(),
+                    AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Unit)),
+                ),
+                ("size".to_string(), AnalyzedTypeId::Integer(4)),
+            ],
+            Box::new(|context| {
+                context.load(4, "r1", "[sp;8]");
+                context.load(8, "r0", "[sp;12]");
+                context.realloc("r1", "r0");
+                context.ret();
+            }),
+        )
+    }
+
     fn random() -> BuiltinFunction {
         BuiltinFunction::new(
             "random".to_string(),
@@ -160,6 +181,19 @@ impl BuiltinFunction {
         )
     }
 
+    fn seed_random() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "seed_random".to_string(),
+            AnalyzedTypeId::Unit,
+            vec![("seed".to_string(), AnalyzedTypeId::Integer(8))],
+            Box::new(|context| {
+                context.load(8, "r0", "[sp;8]");
+                context.seed_rand("r0");
+                context.ret();
+            }),
+        )
+    }
+
     fn exit() -> BuiltinFunction {
         BuiltinFunction::new(
             "exit".to_string(),
@@ -172,6 +206,21 @@ impl BuiltinFunction {
         )
     }
 
+    fn abort() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "abort".to_string(),
+            AnalyzedTypeId::Unit,
+            vec![],
+            Box::new(|context| {
+                // Set up a frame like other functions do, so the backtrace of the trap includes
+                // the caller.
+                context.push(8, "bp");
+                context.mov("bp", "sp");
+                context.abort();
+            }),
+        )
+    }
+
     fn memcopy() -> BuiltinFunction {
         BuiltinFunction::new(
             "memcopy".to_string(),
@@ -273,7 +322,7 @@ impl BuiltinFunction {
     fn fwrite() -> BuiltinFunction {
         BuiltinFunction::new(
             "fwrite".to_string(),
-            AnalyzedTypeId::Unit,
+            AnalyzedTypeId::Integer(4),
             vec![
                 (
                     "buffer".to_string(),
@@ -287,6 +336,156 @@ impl BuiltinFunction {
                 context.load(4, "r1", "[sp;12]");
                 context.load(8, "r2", "[sp;16]");
                 context.file_write("r0", "r1", "[r2]");
+                context.mov("r0", "r1");
+                context.ret();
+            }),
+        )
+    }
+
+    fn fopen_mode() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "fopen_mode".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![
+                (
+                    "filename".to_string(),
+                    AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Char)),
+                ),
+                ("mode".to_string(), AnalyzedTypeId::Integer(4)),
+            ],
+            Box::new(|context| {
+                context.load(4, "r0", "[sp;8]");
+                context.load(8, "r1", "[sp;12]");
+                context.file_open_mode("r0", "[r1]");
+                context.ret();
+            }),
+        )
+    }
+
+    fn fseek() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "fseek".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![
+                ("file".to_string(), AnalyzedTypeId::Integer(4)),
+                ("position".to_string(), AnalyzedTypeId::Integer(4)),
+            ],
+            Box::new(|context| {
+                context.load(4, "r0", "[sp;8]");
+                context.load(4, "r1", "[sp;12]");
+                context.file_seek("r1", "r0");
+                context.ret();
+            }),
+        )
+    }
+
+    fn ftell() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "ftell".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![("file".to_string(), AnalyzedTypeId::Integer(4))],
+            Box::new(|context| {
+                context.load(4, "r0", "[sp;8]");
+                context.file_tell("r0");
+                context.ret();
+            }),
+        )
+    }
+
+    fn fsize() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "fsize".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![("file".to_string(), AnalyzedTypeId::Integer(4))],
+            Box::new(|context| {
+                context.load(4, "r0", "[sp;8]");
+                context.file_size("r0");
+                context.ret();
+            }),
+        )
+    }
+
+    fn fremove() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "fremove".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![(
+                "filename".to_string(),
+                AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Char)),
+            )],
+            Box::new(|context| {
+                context.load(8, "r1", "[sp;8]");
+                context.file_remove("r0", "[r1]");
+                context.ret();
+            }),
+        )
+    }
+
+    fn frename() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "frename".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![
+                (
+                    "from".to_string(),
+                    AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Char)),
+                ),
+                (
+                    "to".to_string(),
+                    AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Char)),
+                ),
+            ],
+            Box::new(|context| {
+                context.load(8, "r2", "[sp;8]");
+                context.load(8, "r1", "[sp;16]");
+                context.file_rename("r0", "[r1]", "[r2]");
+                context.ret();
+            }),
+        )
+    }
+
+    fn arg_count() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "arg_count".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![],
+            Box::new(|context| {
+                context.arg_count("r0");
+                context.ret();
+            }),
+        )
+    }
+
+    fn arg_length() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "arg_length".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![("index".to_string(), AnalyzedTypeId::Integer(4))],
+            Box::new(|context| {
+                context.load(4, "r0", "[sp;8]");
+                context.arg_length("r0");
+                context.ret();
+            }),
+        )
+    }
+
+    fn read_arg() -> BuiltinFunction {
+        BuiltinFunction::new(
+            "read_arg".to_string(),
+            AnalyzedTypeId::Integer(4),
+            vec![
+                (
+                    "buffer".to_string(),
+                    AnalyzedTypeId::Pointer(Box::new(AnalyzedTypeId::Char)),
+                ),
+                ("length".to_string(), AnalyzedTypeId::Integer(4)),
+                ("index".to_string(), AnalyzedTypeId::Integer(4)),
+            ],
+            Box::new(|context| {
+                context.load(4, "r1", "[sp;8]");
+                context.load(4, "r0", "[sp;12]");
+                context.load(8, "r2", "[sp;16]");
+                context.arg_read("r1", "r0", "[r2]");
                 context.ret();
             }),
         )
@@ -300,14 +499,26 @@ impl BuiltinFunction {
             BuiltinFunction::read(),
             BuiltinFunction::malloc(),
             BuiltinFunction::free(),
+            BuiltinFunction::realloc(),
             BuiltinFunction::random(),
+            BuiltinFunction::seed_random(),
             BuiltinFunction::exit(),
+            BuiltinFunction::abort(),
             BuiltinFunction::memcopy(),
             BuiltinFunction::memset(),
             BuiltinFunction::fopen(),
             BuiltinFunction::fclose(),
             BuiltinFunction::fread(),
             BuiltinFunction::fwrite(),
+            BuiltinFunction::fopen_mode(),
+            BuiltinFunction::fseek(),
+            BuiltinFunction::ftell(),
+            BuiltinFunction::fsize(),
+            BuiltinFunction::fremove(),
+            BuiltinFunction::frename(),
+            BuiltinFunction::arg_count(),
+            BuiltinFunction::arg_length(),
+            BuiltinFunction::read_arg(),
         ]
     }
 
@@ -367,6 +578,7 @@ impl BuiltinFunction {
             let label = context.new_label(&identifier);
             context.function_labels.insert(identifier, label.clone());
             context.label(&label);
+            context.func(&function.name);
             (function.code)(context);
         }
     }
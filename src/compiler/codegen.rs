@@ -2,7 +2,9 @@ mod expression_codegen;
 mod program_codegen;
 
 use crate::compiler::codegen::program_codegen::generate_program_code;
+use crate::compiler::lexer::location::Location;
 use crate::compiler::resolver::resolved_expression::ResolvedProgram;
+use lychee_compiler::{VmConfig, VmMemoryConfig};
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -16,6 +18,12 @@ pub struct CodegenContext {
     pub continue_label: String,
     pub current_stack_size: usize,
     pub last_loop_stack_size: usize,
+    /// The indices of the source files declared with `file` so far.
+    source_files: HashMap<PathBuf, usize>,
+    /// Source files are named relative to the directory the compiler runs in, if they are in it.
+    working_directory: Option<PathBuf>,
+    /// The file index and line of the last `loc`, which lasts until the next `loc` or `func`.
+    current_location: Option<(usize, usize)>,
 }
 
 impl CodegenContext {
@@ -30,6 +38,9 @@ impl CodegenContext {
             constant_labels: Vec::new(),
             current_stack_size: 0,
             last_loop_stack_size: 0,
+            source_files: HashMap::new(),
+            working_directory: std::env::current_dir().ok(),
+            current_location: None,
         }
     }
     pub fn function_reset(&mut self) {
@@ -58,6 +69,49 @@ impl CodegenContext {
             .join(" ");
         self.lines.push(format!("bytes {}", data));
     }
+    pub fn memory_config(&mut self, config: &VmMemoryConfig) {
+        self.lines.push(format!(
+            "memory {} {} {}",
+            config.memory_size, config.heap_size, config.stack_size
+        ));
+    }
+    pub fn seed(&mut self, seed: u64) {
+        self.lines.push(format!("seed {}", seed));
+    }
+    pub fn entry(&mut self, label: &str) {
+        self.lines.push(format!("entry {}", label));
+    }
+    pub fn section(&mut self, section: &str) {
+        self.lines.push(format!("section {}", section));
+    }
+    pub fn func(&mut self, name: &str) {
+        self.lines.push(format!("func {}", name));
+        self.current_location = None;
+    }
+    /// Attributes the following code to `location`, declaring its file on first use.
+    pub fn loc(&mut self, location: &Location) {
+        let Some(module_path) = &location.file else {
+            return;
+        };
+        let file = match self.source_files.get(&module_path.file) {
+            Some(&file) => file,
+            None => {
+                let file = self.source_files.len();
+                self.source_files.insert(module_path.file.clone(), file);
+                let path = self
+                    .working_directory
+                    .as_ref()
+                    .and_then(|directory| module_path.file.strip_prefix(directory).ok())
+                    .unwrap_or(&module_path.file);
+                self.lines.push(format!("file {} {}", file, path.display()));
+                file
+            }
+        };
+        if self.current_location != Some((file, location.line)) {
+            self.current_location = Some((file, location.line));
+            self.lines.push(format!("loc {} {}", file, location.line));
+        }
+    }
     pub fn jmp(&mut self, label: &str) {
         self.lines.push(format!("jmp {}", label));
     }
@@ -145,6 +199,9 @@ impl CodegenContext {
     pub fn exit(&mut self) {
         self.lines.push("exit".to_string());
     }
+    pub fn abort(&mut self) {
+        self.lines.push("abort".to_string());
+    }
     pub fn ret(&mut self) {
         self.lines.push("ret".to_string());
     }
@@ -192,10 +249,61 @@ impl CodegenContext {
     pub fn setge(&mut self, register: &str) {
         self.lines.push(format!("setge {register}"));
     }
+    pub fn setb(&mut self, register: &str) {
+        self.lines.push(format!("setb {register}"));
+    }
+    pub fn setbe(&mut self, register: &str) {
+        self.lines.push(format!("setbe {register}"));
+    }
     pub fn signext(&mut self, to_bytes: usize, register: &str) {
         self.lines
             .push(format!("signext #{} {register}", to_bytes * 8));
     }
+    pub fn fadd(&mut self, bytes: usize, dest_register: &str, source_register: &str) {
+        self.lines.push(format!(
+            "fadd #{} {dest_register} {source_register}",
+            bytes * 8
+        ));
+    }
+    pub fn fsub(&mut self, bytes: usize, dest_register: &str, source_register: &str) {
+        self.lines.push(format!(
+            "fsub #{} {dest_register} {source_register}",
+            bytes * 8
+        ));
+    }
+    pub fn fmul(&mut self, bytes: usize, dest_register: &str, source_register: &str) {
+        self.lines.push(format!(
+            "fmul #{} {dest_register} {source_register}",
+            bytes * 8
+        ));
+    }
+    pub fn fdiv(&mut self, bytes: usize, dest_register: &str, source_register: &str) {
+        self.lines.push(format!(
+            "fdiv #{} {dest_register} {source_register}",
+            bytes * 8
+        ));
+    }
+    pub fn fcmp(&mut self, bytes: usize, dest_register: &str, source_register: &str) {
+        self.lines.push(format!(
+            "fcmp #{} {dest_register} {source_register}",
+            bytes * 8
+        ));
+    }
+    pub fn fneg(&mut self, bytes: usize, register: &str) {
+        self.lines.push(format!("fneg #{} {register}", bytes * 8));
+    }
+    pub fn itof(&mut self, to_bytes: usize, register: &str) {
+        self.lines
+            .push(format!("itof #{} {register}", to_bytes * 8));
+    }
+    pub fn ftoi(&mut self, from_bytes: usize, register: &str) {
+        self.lines
+            .push(format!("ftoi #{} {register}", from_bytes * 8));
+    }
+    pub fn fcvt(&mut self, to_bytes: usize, register: &str) {
+        self.lines
+            .push(format!("fcvt #{} {register}", to_bytes * 8));
+    }
     pub fn call_function(&mut self, function: &str) {
         let label = self.get_label(function);
         self.lines.push(format!("call {}", label));
@@ -222,12 +330,25 @@ impl CodegenContext {
         self.lines
             .push(format!("alloc {size_register} {address_register}"));
     }
+    pub fn realloc(&mut self, size_register: &str, address_register: &str) {
+        self.lines
+            .push(format!("realloc {size_register} {address_register}"));
+    }
     pub fn free(&mut self, address_register: &str) {
         self.lines.push(format!("free {address_register}"));
     }
     pub fn rand(&mut self, register: &str) {
         self.lines.push(format!("rand {register}"));
     }
+    pub fn seed_rand(&mut self, register: &str) {
+        self.lines.push(format!("seedrand {register}"));
+    }
+
+    pub fn host_call(&mut self, address_register: &str, size_register: &str, id: u64) {
+        self.lines.push(format!(
+            "hostcall {address_register} {size_register} 0x{id:X}"
+        ));
+    }
 
     pub fn memcopy(&mut self, size_register: &str, dest_address: &str, source_address: &str) {
         self.lines.push(format!(
@@ -265,10 +386,49 @@ impl CodegenContext {
             "filewrite {pointer_register} {size_register} {buffer_address}"
         ));
     }
+    pub fn file_open_mode(&mut self, mode_register: &str, filename_address: &str) {
+        self.lines
+            .push(format!("fileopenmode {mode_register} {filename_address}"));
+    }
+    pub fn file_seek(&mut self, pointer_register: &str, position_register: &str) {
+        self.lines
+            .push(format!("fileseek {pointer_register} {position_register}"));
+    }
+    pub fn file_tell(&mut self, pointer_register: &str) {
+        self.lines.push(format!("filetell {pointer_register}"));
+    }
+    pub fn file_size(&mut self, pointer_register: &str) {
+        self.lines.push(format!("filesize {pointer_register}"));
+    }
+    pub fn file_remove(&mut self, result_register: &str, filename_address: &str) {
+        self.lines
+            .push(format!("fileremove {result_register} {filename_address}"));
+    }
+    pub fn file_rename(&mut self, result_register: &str, from_address: &str, to_address: &str) {
+        self.lines.push(format!(
+            "filerename {result_register} {from_address} {to_address}"
+        ));
+    }
+
+    pub fn arg_count(&mut self, register: &str) {
+        self.lines.push(format!("argc {register}"));
+    }
+    pub fn arg_length(&mut self, register: &str) {
+        self.lines.push(format!("arglen {register}"));
+    }
+    pub fn arg_read(&mut self, index_register: &str, size_register: &str, buffer_address: &str) {
+        self.lines.push(format!(
+            "argread {index_register} {size_register} {buffer_address}"
+        ));
+    }
 }
 
-pub fn gen_code(program: ResolvedProgram, output: &PathBuf) {
+pub fn gen_code(program: ResolvedProgram, vm_config: &VmConfig, output: &PathBuf) {
     let mut context = CodegenContext::new();
+    context.memory_config(&vm_config.memory);
+    if let Some(seed) = vm_config.seed {
+        context.seed(seed);
+    }
     generate_program_code(&mut context, &program);
     let code = context.build();
     std::fs::write(output, code).unwrap();
@@ -1,15 +1,14 @@
-use crate::compiler::analyzer::analyzed_expression::{
-    AnalyzedBinaryOp, AnalyzedLiteral, BinaryAssignOp,
-};
+use crate::compiler::analyzer::analyzed_expression::AnalyzedLiteral;
 use crate::compiler::codegen::CodegenContext;
 use crate::compiler::parser::binary_op::{BinaryComparisonOp, BinaryLogicOp, BinaryMathOp};
 use crate::compiler::parser::parsed_expression::UnaryMathOp;
 use crate::compiler::resolver::resolved_expression::{
-    ResolvedAssignableExpression, ResolvedExpression, ResolvedExpressionKind,
-    ResolvedFunctionCallType, ResolvedUnaryOp, ValueData, ValueLocation,
+    ResolvedAssignOp, ResolvedAssignableExpression, ResolvedBinaryOp, ResolvedExpression,
+    ResolvedExpressionKind, ResolvedFunctionCallType, ResolvedUnaryOp, ValueData, ValueLocation,
 };
 
 pub fn generate_expression_code(context: &mut CodegenContext, expression: &ResolvedExpression) {
+    context.loc(&expression.location);
     match &expression.kind {
         ResolvedExpressionKind::Block(expressions) => {
             for expr in expressions {
@@ -156,6 +155,9 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
             AnalyzedLiteral::Integer(i) => {
                 context.movi("r0", *i as isize);
             }
+            AnalyzedLiteral::Float(f) => {
+                context.movi("r0", f.to_bits() as isize);
+            }
         },
         ResolvedExpressionKind::Unary { op, expr } => {
             generate_expression_code(context, expr);
@@ -181,6 +183,23 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                     context.setnz("r0");
                 }
                 ResolvedUnaryOp::PointerCast => {}
+                ResolvedUnaryOp::FloatNegate(size) => {
+                    context.fneg(*size, "r0");
+                }
+                ResolvedUnaryOp::IntToFloat(size) => {
+                    context.itof(*size, "r0");
+                }
+                ResolvedUnaryOp::FloatToInt(float_size, int_size) => {
+                    context.ftoi(*float_size, "r0");
+                    if *int_size < 8 {
+                        context.signext(*int_size, "r0");
+                    }
+                }
+                ResolvedUnaryOp::FloatCast(original_size, target_size) => {
+                    if original_size != target_size {
+                        context.fcvt(*target_size, "r0");
+                    }
+                }
             }
         }
         ResolvedExpressionKind::Borrow { expr } => {
@@ -202,12 +221,16 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                     context.current_stack_size += arg.value_data.size;
                 }
             }
+            // The arguments may span other lines, so the call itself is attributed to the line
+            // of the call expression again.
             match call_type {
                 ResolvedFunctionCallType::Pointer(ptr) => {
                     generate_expression_code(context, ptr);
+                    context.loc(&expression.location);
                     context.call_address("[r0]");
                 }
                 ResolvedFunctionCallType::Function(function_name) => {
+                    context.loc(&expression.location);
                     context.call_function(function_name)
                 }
             }
@@ -253,7 +276,7 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
             context.pop(expression.value_data.size, "r0");
         }
         ResolvedExpressionKind::Binary { op, left, right } => match op {
-            AnalyzedBinaryOp::Math(math_op) => {
+            ResolvedBinaryOp::Math(math_op) => {
                 generate_expression_code(context, left);
                 context.push(left.value_data.size, "r0");
                 context.current_stack_size += left.value_data.size;
@@ -263,7 +286,17 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                 context.current_stack_size -= left.value_data.size;
                 do_math_op(context, math_op, "r0", "r1");
             }
-            AnalyzedBinaryOp::Logical(logical_op) => {
+            ResolvedBinaryOp::FloatMath(math_op, size) => {
+                generate_expression_code(context, left);
+                context.push(left.value_data.size, "r0");
+                context.current_stack_size += left.value_data.size;
+                generate_expression_code(context, right);
+                context.mov("r1", "r0");
+                context.pop(left.value_data.size, "r0");
+                context.current_stack_size -= left.value_data.size;
+                do_float_math_op(context, math_op, *size, "r0", "r1");
+            }
+            ResolvedBinaryOp::Logical(logical_op) => {
                 let short_circuit_label = context.new_label("short_circuit");
                 generate_expression_code(context, left);
                 context.cmpi("r0", 0);
@@ -274,7 +307,7 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                 generate_expression_code(context, right);
                 context.label(&short_circuit_label);
             }
-            AnalyzedBinaryOp::Comparison(comp_op) => {
+            ResolvedBinaryOp::Comparison(comp_op) => {
                 generate_expression_code(context, left);
                 context.push(left.value_data.size, "r0");
                 context.current_stack_size += left.value_data.size;
@@ -284,9 +317,19 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                 context.current_stack_size -= left.value_data.size;
                 do_comp_op(context, comp_op, "r0", "r1");
             }
+            ResolvedBinaryOp::FloatComparison(comp_op, size) => {
+                generate_expression_code(context, left);
+                context.push(left.value_data.size, "r0");
+                context.current_stack_size += left.value_data.size;
+                generate_expression_code(context, right);
+                context.mov("r1", "r0");
+                context.pop(left.value_data.size, "r0");
+                context.current_stack_size -= left.value_data.size;
+                do_float_comp_op(context, comp_op, *size, "r0", "r1");
+            }
         },
         ResolvedExpressionKind::Assign { op, lhs, rhs } => match op {
-            BinaryAssignOp::Assign => {
+            ResolvedAssignOp::Assign => {
                 generate_expression_code(context, rhs);
                 if let ValueLocation::Stack = rhs.value_data.location {
                     generate_assignable_expression_pointer_code(context, lhs);
@@ -301,7 +344,7 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                     context.store(expression.value_data.size, "r0", "[r1]");
                 }
             }
-            BinaryAssignOp::MathAssign(math_op) => {
+            ResolvedAssignOp::MathAssign(math_op) => {
                 generate_expression_code(context, rhs);
                 context.push(expression.value_data.size, "r0");
                 context.current_stack_size += expression.value_data.size;
@@ -313,7 +356,19 @@ pub fn generate_expression_code(context: &mut CodegenContext, expression: &Resol
                 do_math_op(context, math_op, "r0", "r2");
                 context.store(expression.value_data.size, "r0", "[r1]");
             }
-            BinaryAssignOp::LogicAssign(logic_op) => {
+            ResolvedAssignOp::FloatMathAssign(math_op, size) => {
+                generate_expression_code(context, rhs);
+                context.push(expression.value_data.size, "r0");
+                context.current_stack_size += expression.value_data.size;
+                generate_assignable_expression_pointer_code(context, lhs);
+                context.mov("r1", "r0");
+                context.load(expression.value_data.size, "r0", "[r1]");
+                context.pop(expression.value_data.size, "r2");
+                context.current_stack_size -= expression.value_data.size;
+                do_float_math_op(context, math_op, *size, "r0", "r2");
+                context.store(expression.value_data.size, "r0", "[r1]");
+            }
+            ResolvedAssignOp::LogicAssign(logic_op) => {
                 let short_circuit_label = context.new_label("short_circuit");
                 generate_assignable_expression_pointer_code(context, lhs);
                 context.load(expression.value_data.size, "r1", "[r0]");
@@ -504,6 +559,62 @@ fn do_comp_op(
     }
 }
 
+fn do_float_math_op(
+    context: &mut CodegenContext,
+    math_op: &BinaryMathOp,
+    size: usize,
+    dest_register: &str,
+    source_register: &str,
+) {
+    match math_op {
+        BinaryMathOp::Add => {
+            context.fadd(size, dest_register, source_register);
+        }
+        BinaryMathOp::Sub => {
+            context.fsub(size, dest_register, source_register);
+        }
+        BinaryMathOp::Mul => {
+            context.fmul(size, dest_register, source_register);
+        }
+        BinaryMathOp::Div => {
+            context.fdiv(size, dest_register, source_register);
+        }
+        _ => unreachable!("Unsupported float operation: {:?}", math_op),
+    }
+}
+
+/// `fcmp` sets the carry flag when the left operand is less, so `<` and `<=` use the unsigned
+/// conditions. This way every ordered comparison with NaN is false.
+fn do_float_comp_op(
+    context: &mut CodegenContext,
+    comp_op: &BinaryComparisonOp,
+    size: usize,
+    dest_register: &str,
+    source_register: &str,
+) {
+    context.fcmp(size, dest_register, source_register);
+    match comp_op {
+        BinaryComparisonOp::Equals => {
+            context.setz(dest_register);
+        }
+        BinaryComparisonOp::NotEquals => {
+            context.setnz(dest_register);
+        }
+        BinaryComparisonOp::Less => {
+            context.setb(dest_register);
+        }
+        BinaryComparisonOp::LessEquals => {
+            context.setbe(dest_register);
+        }
+        BinaryComparisonOp::Greater => {
+            context.setg(dest_register);
+        }
+        BinaryComparisonOp::GreaterEquals => {
+            context.setge(dest_register);
+        }
+    }
+}
+
 fn store_from_value_data(
     context: &mut CodegenContext,
     value_data: &ValueData,
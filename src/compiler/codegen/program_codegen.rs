@@ -2,7 +2,7 @@ use crate::compiler::builtin::BuiltinFunction;
 use crate::compiler::codegen::expression_codegen::generate_expression_code;
 use crate::compiler::codegen::CodegenContext;
 use crate::compiler::resolver::resolved_expression::{
-    FunctionReturnLocation, ResolvedFunction, ResolvedProgram,
+    FunctionReturnLocation, ResolvedFunction, ResolvedHostFunction, ResolvedProgram,
 };
 
 pub fn generate_program_code(context: &mut CodegenContext, program: &ResolvedProgram) {
@@ -20,12 +20,17 @@ pub fn generate_program_code(context: &mut CodegenContext, program: &ResolvedPro
 
     BuiltinFunction::generate_builtin_function_code(context);
 
+    for function in &program.host_functions {
+        generate_host_function_code(context, function);
+    }
+
     for function in &program.functions {
         context.function_reset();
         context.return_label = context.new_label(format!("{}_return", function.name).as_str());
         generate_function_code(context, function);
     }
 
+    context.section("rodata");
     for (index, constant) in program.constants.iter().enumerate() {
         let label = context.constant_labels[index].clone();
         context.label(&label);
@@ -34,13 +39,33 @@ pub fn generate_program_code(context: &mut CodegenContext, program: &ResolvedPro
 }
 
 fn generate_program_prelude(context: &mut CodegenContext, main_function_name: &String) {
+    let label = context.new_label("start");
+    context.entry(&label);
+    context.label(&label);
+    context.func("start");
     context.call_function(main_function_name);
     context.exit();
 }
 
+/// The arguments start above the return address and are followed by the stack return slot, so
+/// the host finds both from the start address and the size of the arguments.
+fn generate_host_function_code(context: &mut CodegenContext, function: &ResolvedHostFunction) {
+    let label = context.new_label(&function.name);
+    context
+        .function_labels
+        .insert(function.name.clone(), label.clone());
+    context.label(&label);
+    context.func(&function.display_name);
+    context.movi("r1", function.argument_size as isize);
+    context.lea("r0", "[sp;8]");
+    context.host_call("r0", "r1", function.id);
+    context.ret();
+}
+
 fn generate_function_code(context: &mut CodegenContext, function: &ResolvedFunction) {
     let label = context.function_labels[&function.name].clone();
     context.label(&label);
+    context.func(&function.display_name);
     generate_function_prologue(context, function);
 
     generate_expression_code(context, &function.body);
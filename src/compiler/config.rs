@@ -1,3 +1,4 @@
+use lychee_compiler::VmConfig;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
@@ -6,6 +7,26 @@ use std::path::PathBuf;
 pub struct LycheeConfig {
     pub package: Package,
     pub dependencies: Option<HashMap<String, Dependency>>,
+    pub vm: Option<VmSettings>,
+}
+
+impl LycheeConfig {
+    pub fn vm_config(&self) -> VmConfig {
+        let mut vm_config = VmConfig::default();
+        if let Some(vm) = &self.vm {
+            if let Some(memory_size) = vm.memory_size {
+                vm_config.memory.memory_size = memory_size;
+            }
+            if let Some(heap_size) = vm.heap_size {
+                vm_config.memory.heap_size = heap_size;
+            }
+            if let Some(stack_size) = vm.stack_size {
+                vm_config.memory.stack_size = stack_size;
+            }
+            vm_config.seed = vm.seed;
+        }
+        vm_config
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -18,6 +39,14 @@ pub struct Dependency {
     pub path: PathBuf,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VmSettings {
+    pub memory_size: Option<u64>,
+    pub heap_size: Option<u64>,
+    pub stack_size: Option<u64>,
+    pub seed: Option<u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ConfigData {
     pub config: LycheeConfig,
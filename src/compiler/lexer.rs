@@ -56,6 +56,7 @@ pub fn lex(input_path: &ModulePath) -> LexResult<Vec<SrcToken>> {
             }
             '"' => read_string(&input, &location, offset, '"')?,
             '\'' => read_char(&input, &location, offset)?,
+            c if c.is_ascii_digit() => read_number(&input, &location, offset)?,
             _ => read_token(&input, &location, offset)?,
         };
         tokens.push(SrcToken {
@@ -148,6 +149,43 @@ fn read_char(input: &Vec<char>, location: &Location, offset: usize) -> LexResult
     }
 }
 
+fn read_number(input: &Vec<char>, location: &Location, offset: usize) -> LexResult<(Token, usize)> {
+    let is_digit = |i: usize| i < input.len() && input[i].is_ascii_digit();
+    let mut end = offset;
+    while is_digit(end) {
+        end += 1;
+    }
+    if end >= input.len() || input[end] != '.' || !is_digit(end + 1) {
+        return read_token(input, location, offset);
+    }
+    end += 1;
+    while is_digit(end) {
+        end += 1;
+    }
+    if end < input.len() && (input[end] == 'e' || input[end] == 'E') {
+        let mut exponent_start = end + 1;
+        if exponent_start < input.len()
+            && (input[exponent_start] == '+' || input[exponent_start] == '-')
+        {
+            exponent_start += 1;
+        }
+        if is_digit(exponent_start) {
+            end = exponent_start;
+            while is_digit(end) {
+                end += 1;
+            }
+        }
+    }
+    let substr: String = input[offset..end].iter().collect();
+    let value = substr.parse::<f64>().map_err(|_| {
+        LocationError::new(
+            format!("Invalid float literal: '{substr}'"),
+            location.clone(),
+        )
+    })?;
+    Ok((Token::Literal(Literal::Float(value.to_bits())), end))
+}
+
 fn read_token(input: &Vec<char>, location: &Location, offset: usize) -> LexResult<(Token, usize)> {
     for len in (1..=StaticToken::MAX_LENGTH).rev() {
         if offset + len > input.len() {
@@ -201,6 +201,7 @@ pub enum Keyword {
     Enum,
     Trait,
     Impl,
+    Extern,
 }
 
 impl Keyword {
@@ -227,6 +228,7 @@ impl Keyword {
             "enum" => Some(Keyword::Enum),
             "trait" => Some(Keyword::Trait),
             "impl" => Some(Keyword::Impl),
+            "extern" => Some(Keyword::Extern),
             _ => None,
         }
     }
@@ -235,6 +237,8 @@ impl Keyword {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Literal {
     Integer(i64),
+    /// The bits of an `f64`, so that tokens can still be compared and hashed.
+    Float(u64),
     Bool(bool),
     Char(char),
     String(String),
@@ -244,6 +248,7 @@ impl Display for Literal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Literal::Integer(x) => write!(f, "{}", x),
+            Literal::Float(x) => write!(f, "{:?}", f64::from_bits(*x)),
             Literal::Bool(x) => write!(f, "{}", x),
             Literal::Char(x) => write!(f, "'{}'", x),
             Literal::String(x) => write!(f, "\"{}\"", x),
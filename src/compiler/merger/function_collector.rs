@@ -2,7 +2,9 @@ use crate::compiler::lexer::location::Src;
 use crate::compiler::merger::merged_expression::{FunctionId, TraitId};
 use crate::compiler::merger::MergerResult;
 use crate::compiler::parser::item_id::{ItemId, ParsedScopeId};
-use crate::compiler::parser::parsed_expression::{ParsedFunction, ParsedProgram};
+use crate::compiler::parser::parsed_expression::{
+    ParsedFunction, ParsedFunctionSignature, ParsedProgram,
+};
 use crate::compiler::parser::ModuleIdentifier;
 use std::collections::{HashMap, HashSet};
 
@@ -98,12 +100,15 @@ pub fn collect_function_data(
 ) -> MergerResult<(
     CollectedFunctionData,
     Vec<(FunctionId, Src<ParsedFunction>)>,
+    Vec<(FunctionId, Src<ParsedFunctionSignature>)>,
 )> {
     let mut function_bodies = Vec::new();
+    let mut extern_functions = Vec::new();
     let mut functions = HashMap::new();
 
     crate::compiler::builtin::BuiltinFunction::get_builtin_function_ids(&mut functions);
     collect_functions(program, &mut functions, &mut function_bodies)?;
+    collect_extern_functions(program, &mut functions, &mut extern_functions)?;
     let function_imports = collect_function_imports(program, &functions)?;
     let mut traits = HashMap::new();
     collect_trait_definitions(program, &mut traits)?;
@@ -116,6 +121,7 @@ pub fn collect_function_data(
             trait_imports,
         },
         function_bodies,
+        extern_functions,
     ))
 }
 
@@ -156,6 +162,51 @@ fn collect_functions(
     Ok(())
 }
 
+fn collect_extern_functions(
+    program: &ParsedProgram,
+    functions: &mut HashMap<ModuleIdentifier, HashMap<String, HashSet<FunctionId>>>,
+    extern_functions: &mut Vec<(FunctionId, Src<ParsedFunctionSignature>)>,
+) -> MergerResult<()> {
+    for (module_id, module) in &program.module_tree {
+        let module_functions = functions.entry(module_id.clone()).or_default();
+        for signature in &module.extern_functions {
+            validate_function_name(&signature.value.function_name)?;
+            if !signature.value.generic_params.order.is_empty() {
+                Err(anyhow::anyhow!(
+                    "Extern function {} cannot be generic at {}",
+                    signature.value.function_name,
+                    signature.location
+                ))?;
+            }
+            let id = FunctionId {
+                id: ItemId {
+                    module_id: module_id.clone(),
+                    item_name: signature.value.function_name.clone(),
+                },
+                generic_count: 0,
+                param_count: signature.value.params.len(),
+                body_index: -1,
+            };
+            let entry = module_functions
+                .entry(signature.value.function_name.clone())
+                .or_default();
+            if entry
+                .iter()
+                .any(|other| other.param_count == id.param_count)
+            {
+                Err(anyhow::anyhow!(
+                    "Extern function {} defined multiple times at {}",
+                    id.id.item_name,
+                    signature.location
+                ))?;
+            }
+            entry.insert(id.clone());
+            extern_functions.push((id, signature.clone()));
+        }
+    }
+    Ok(())
+}
+
 fn collect_function_imports(
     program: &ParsedProgram,
     functions: &HashMap<ModuleIdentifier, HashMap<String, HashSet<FunctionId>>>,
@@ -1,22 +1,34 @@
 use crate::compiler::analyzer::analyzed_type::{GenericIdKind, GenericParams};
-use crate::compiler::lexer::location::Src;
+use crate::compiler::lexer::location::{Location, Src};
 use crate::compiler::merger::function_collector::collect_function_data;
 use crate::compiler::merger::merged_expression::{FunctionId, ResolvedFunctionHeader};
 use crate::compiler::merger::resolved_functions::ResolvedFunctions;
 use crate::compiler::merger::resolved_types::ResolvedTypes;
 use crate::compiler::merger::MergerResult;
-use crate::compiler::parser::parsed_expression::{ParsedFunction, ParsedProgram};
+use crate::compiler::parser::parsed_expression::{
+    ParsedFunction, ParsedFunctionSignature, ParsedProgram,
+};
 use std::collections::HashMap;
 
 pub fn build_resolved_functions(
     program: &ParsedProgram,
     resolved_types: &ResolvedTypes,
 ) -> MergerResult<ResolvedFunctions> {
-    let (collected_function_data, function_bodies) = collect_function_data(program)?;
+    let (collected_function_data, function_bodies, extern_functions) =
+        collect_function_data(program)?;
     let mut functions = HashMap::new();
     crate::compiler::builtin::BuiltinFunction::add_builtin_function_headers(&mut functions);
 
     extract_module_functions(resolved_types, &mut functions, &function_bodies)?;
+    for (id, signature) in &extern_functions {
+        let header = extract_function(
+            &signature.value,
+            &signature.location,
+            resolved_types,
+            id.clone(),
+        )?;
+        functions.insert(id.clone(), header);
+    }
 
     let mapped_bodies = function_bodies
         .into_iter()
@@ -27,6 +39,7 @@ pub fn build_resolved_functions(
         function_headers: functions,
         collected_function_data,
         function_bodies: mapped_bodies,
+        extern_functions: extern_functions.into_iter().map(|(id, _)| id).collect(),
     })
 }
 
@@ -36,7 +49,12 @@ fn extract_module_functions(
     function_bodies: &Vec<(FunctionId, Src<ParsedFunction>)>,
 ) -> MergerResult<()> {
     for (id, func_def) in function_bodies {
-        let header = extract_function(func_def, resolved_types, id.clone())?;
+        let header = extract_function(
+            &func_def.value.signature,
+            &func_def.location,
+            resolved_types,
+            id.clone(),
+        )?;
         if functions.insert(id.clone(), header).is_some() {
             return Err(anyhow::anyhow!("Duplicate function definition: {}", id));
         }
@@ -45,11 +63,11 @@ fn extract_module_functions(
 }
 
 fn extract_function(
-    func_def: &Src<ParsedFunction>,
+    signature: &ParsedFunctionSignature,
+    location: &Location,
     resolved_types: &ResolvedTypes,
     func_id: FunctionId,
 ) -> MergerResult<ResolvedFunctionHeader> {
-    let signature = &func_def.value.signature;
     let mut parameter_order = Vec::with_capacity(signature.params.len());
     let mut parameter_types = HashMap::with_capacity(signature.params.len());
 
@@ -61,9 +79,7 @@ fn extract_function(
         parameter_order.push(arg_name.clone());
         let resolved_arg_type = resolved_types
             .map_generic_parsed_type(&arg_type.value, &resolved_generic_params)
-            .ok_or_else(|| {
-                anyhow::anyhow!("Type {} not found at {}", arg_type.value, func_def.location)
-            })?;
+            .ok_or_else(|| anyhow::anyhow!("Type {} not found at {}", arg_type.value, location))?;
         if parameter_types
             .insert(arg_name.clone(), resolved_arg_type)
             .is_some()
@@ -81,7 +97,7 @@ fn extract_function(
             anyhow::anyhow!(
                 "Return type {} not found at {}",
                 signature.return_type.value,
-                func_def.location
+                location
             )
         })?;
 
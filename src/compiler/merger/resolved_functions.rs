@@ -14,6 +14,8 @@ use std::collections::HashMap;
 pub struct ResolvedFunctions {
     pub function_headers: HashMap<FunctionId, ResolvedFunctionHeader>,
     pub function_bodies: Vec<(FunctionId, ParsedExpression)>,
+    /// Functions declared with `extern`, which have a header but no body.
+    pub extern_functions: Vec<FunctionId>,
     pub collected_function_data: CollectedFunctionData,
 }
 
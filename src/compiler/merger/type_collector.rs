@@ -124,6 +124,7 @@ impl CollectedTypeData {
             ParsedTypeKind::Bool => Some(AnalyzedTypeId::Bool),
             ParsedTypeKind::Char => Some(AnalyzedTypeId::Char),
             ParsedTypeKind::Integer(size) => Some(AnalyzedTypeId::Integer(*size)),
+            ParsedTypeKind::Float(size) => Some(AnalyzedTypeId::Float(*size)),
             ParsedTypeKind::Function {
                 return_type,
                 params,
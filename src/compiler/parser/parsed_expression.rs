@@ -16,6 +16,7 @@ pub struct ParsedProgram {
 pub struct ParsedModule {
     pub module_path: ModuleIdentifier,
     pub functions: Vec<Src<ParsedFunction>>,
+    pub extern_functions: Vec<Src<ParsedFunctionSignature>>,
     pub struct_definitions: Vec<Src<ParsedStructDefinition>>,
     pub type_aliases: Vec<Src<ParsedTypeAlias>>,
     pub imports: Vec<Src<ParsedImport>>,
@@ -191,6 +192,11 @@ impl Display for ParsedTypeKind {
                 8 => write!(f, "long"),
                 _ => unreachable!(),
             },
+            ParsedTypeKind::Float(size) => match size {
+                4 => write!(f, "float"),
+                8 => write!(f, "double"),
+                _ => unreachable!(),
+            },
             ParsedTypeKind::Function {
                 return_type,
                 params,
@@ -216,6 +222,7 @@ pub enum ParsedTypeKind {
     Bool,
     Char,
     Integer(usize),
+    Float(usize),
     Function {
         return_type: Box<ParsedType>,
         params: Vec<ParsedType>,
@@ -228,6 +235,7 @@ pub enum ParsedLiteral {
     Bool(bool),
     Char(i8),
     Integer(i64),
+    Float(f64),
     String(String),
 }
 
@@ -42,6 +42,10 @@ pub fn parse_primary_expression(tokens: &mut TokenStack) -> ParseResult<ParsedEx
                     ParsedExpressionKind::Literal(ParsedLiteral::Integer(i)),
                     token.location,
                 )),
+                Literal::Float(bits) => Ok(ParsedExpression::new(
+                    ParsedExpressionKind::Literal(ParsedLiteral::Float(f64::from_bits(bits))),
+                    token.location,
+                )),
                 Literal::Bool(f) => Ok(ParsedExpression::new(
                     ParsedExpressionKind::Literal(ParsedLiteral::Bool(f)),
                     token.location,
@@ -93,6 +93,7 @@ pub fn parse_module(
     let mut tokens = TokenStack::new(tokens);
 
     let mut functions = Vec::new();
+    let mut extern_functions = Vec::new();
     let mut struct_definitions = Vec::new();
     let mut submodule_declarations = Vec::new();
     let mut imports = Vec::new();
@@ -151,6 +152,12 @@ pub fn parse_module(
                     })?;
                 trait_impls.push(trait_impl);
             }
+            Token::Keyword(Keyword::Extern) => {
+                let signature = parse_extern_function(&mut tokens).with_context(|| {
+                    format!("Failed to parse extern function at {}.", token.location)
+                })?;
+                extern_functions.push(signature);
+            }
             _ => {
                 let func = parse_function(&mut tokens)
                     .with_context(|| format!("Failed to parse function at {}.", token.location))?;
@@ -172,6 +179,7 @@ pub fn parse_module(
     let module = ParsedModule {
         module_path: module_path.id.clone(),
         functions,
+        extern_functions,
         struct_definitions,
         type_aliases,
         imports,
@@ -379,6 +387,15 @@ pub fn parse_function(tokens: &mut TokenStack) -> ParseResult<Src<ParsedFunction
     ))
 }
 
+/// Parses the declaration of a function implemented by the host, like
+/// `extern int add(int a, int b);`.
+pub fn parse_extern_function(tokens: &mut TokenStack) -> ParseResult<Src<ParsedFunctionSignature>> {
+    pop_expected(tokens, Token::Keyword(Keyword::Extern))?;
+    let signature = parse_function_signature(tokens)?;
+    pop_expected(tokens, Token::Static(StaticToken::Semicolon))?;
+    Ok(signature)
+}
+
 pub fn parse_expression(tokens: &mut TokenStack) -> ParseResult<ParsedExpression> {
     parse_binop_expression(tokens)
 }
@@ -25,6 +25,8 @@ pub fn parse_type(tokens: &mut TokenStack) -> ParseResult<ParsedType> {
                     "short" => Some(ParsedTypeKind::Integer(2)),
                     "int" => Some(ParsedTypeKind::Integer(4)),
                     "long" => Some(ParsedTypeKind::Integer(8)),
+                    "float" => Some(ParsedTypeKind::Float(4)),
+                    "double" => Some(ParsedTypeKind::Float(8)),
                     _ => None,
                 };
                 if let Some(kind) = builtin_kind {
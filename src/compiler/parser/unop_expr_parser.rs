@@ -74,6 +74,12 @@ pub fn parse_unop_expression(tokens: &mut TokenStack) -> ParseResult<ParsedExpre
                     expr.location,
                 ))
             }
+            ParsedExpressionKind::Literal(ParsedLiteral::Float(float)) => {
+                Ok(ParsedExpression::new(
+                    ParsedExpressionKind::Literal(ParsedLiteral::Float(-float)),
+                    expr.location,
+                ))
+            }
             _ => Ok(expr),
         },
         _ => Ok(expr),
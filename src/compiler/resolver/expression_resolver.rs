@@ -1,10 +1,11 @@
 use crate::compiler::analyzer::analyzed_expression::{
-    AnalyzedConstant, AnalyzedLiteral, AnalyzedUnaryOp,
+    AnalyzedBinaryOp, AnalyzedConstant, AnalyzedLiteral, AnalyzedUnaryOp, BinaryAssignOp,
 };
+use crate::compiler::parser::parsed_expression::UnaryMathOp;
 use crate::compiler::resolver::program_resolver::ResolverContext;
 use crate::compiler::resolver::resolved_expression::{
-    ResolvedAssignableExpression, ResolvedExpression, ResolvedExpressionKind,
-    ResolvedFunctionCallType, ResolvedUnaryOp, ValueData,
+    ResolvedAssignOp, ResolvedAssignableExpression, ResolvedBinaryOp, ResolvedExpression,
+    ResolvedExpressionKind, ResolvedFunctionCallType, ResolvedUnaryOp, ValueData,
 };
 use crate::compiler::unwrapper::unwrapped_type::{
     AssignableUnwrappedExpression, AssignableUnwrappedExpressionKind, UnwrappedExpression,
@@ -41,6 +42,7 @@ pub fn resolve_expression(
                 kind: ResolvedExpressionKind::Block(resolved_expressions),
                 value_data,
                 stack_discard,
+                location: expression.location.clone(),
             }
         }
         UnwrappedExpressionKind::Return(expr) => {
@@ -49,12 +51,14 @@ pub fn resolve_expression(
                 ResolvedExpression {
                     kind: ResolvedExpressionKind::Return(Some(Box::new(resolved_expr))),
                     stack_discard,
+                    location: expression.location.clone(),
                     value_data,
                 }
             } else {
                 ResolvedExpression {
                     kind: ResolvedExpressionKind::Return(None),
                     stack_discard,
+                    location: expression.location.clone(),
                     value_data,
                 }
             }
@@ -62,6 +66,7 @@ pub fn resolve_expression(
         UnwrappedExpressionKind::Continue => ResolvedExpression {
             kind: ResolvedExpressionKind::Continue,
             stack_discard,
+            location: expression.location.clone(),
             value_data,
         },
         UnwrappedExpressionKind::Break(expr) => {
@@ -72,12 +77,14 @@ pub fn resolve_expression(
                         maybe_expr: Some(Box::new(resolved_expr)),
                     },
                     stack_discard,
+                    location: expression.location.clone(),
                     value_data,
                 }
             } else {
                 ResolvedExpression {
                     kind: ResolvedExpressionKind::Break { maybe_expr: None },
                     stack_discard,
+                    location: expression.location.clone(),
                     value_data,
                 }
             }
@@ -100,6 +107,7 @@ pub fn resolve_expression(
                     else_expr: resolved_else_expr.map(Box::new),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -133,6 +141,7 @@ pub fn resolve_expression(
                     else_expr: resolved_else_expr.map(Box::new),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -147,6 +156,7 @@ pub fn resolve_expression(
                     value: Box::new(resolved_value),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -156,6 +166,7 @@ pub fn resolve_expression(
             ResolvedExpression {
                 kind: ResolvedExpressionKind::ValueOfAssignable(resolved_assignable),
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -169,31 +180,46 @@ pub fn resolve_expression(
                     fields: resolved_fields,
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
         UnwrappedExpressionKind::Literal(lit) => ResolvedExpression {
             kind: ResolvedExpressionKind::Literal(lit.clone()),
             stack_discard,
+            location: expression.location.clone(),
             value_data,
         },
         UnwrappedExpressionKind::Unary { op, expr } => {
             let resolved_expr = resolve_expression(context, expr, false);
             let mapped_op = match op {
+                AnalyzedUnaryOp::Math(UnaryMathOp::Negate) => match &expr.ty {
+                    UnwrappedTypeId::Float(size) => ResolvedUnaryOp::FloatNegate(*size),
+                    _ => ResolvedUnaryOp::Math(UnaryMathOp::Negate),
+                },
                 AnalyzedUnaryOp::Math(math_op) => ResolvedUnaryOp::Math(math_op.clone()),
                 AnalyzedUnaryOp::LogicalNot => ResolvedUnaryOp::LogicalNot,
                 AnalyzedUnaryOp::Cast => {
                     let original_size = context.get_type_size(&expr.ty);
                     let target_size = context.get_type_size(&expression.ty);
                     let smaller_size = original_size.min(target_size);
-                    match &expression.ty {
-                        UnwrappedTypeId::Integer(_) => ResolvedUnaryOp::IntCast(smaller_size),
-                        UnwrappedTypeId::Bool => ResolvedUnaryOp::BoolCast,
-                        UnwrappedTypeId::Char => ResolvedUnaryOp::IntCast(smaller_size),
-                        UnwrappedTypeId::Pointer(_) => ResolvedUnaryOp::PointerCast,
-                        _ => {
-                            panic!("Unsupported cast: {:?}", expression.ty)
+                    match (&expr.ty, &expression.ty) {
+                        (UnwrappedTypeId::Float(_), UnwrappedTypeId::Float(_)) => {
+                            ResolvedUnaryOp::FloatCast(original_size, target_size)
+                        }
+                        (UnwrappedTypeId::Float(_), _) => {
+                            ResolvedUnaryOp::FloatToInt(original_size, target_size)
                         }
+                        (_, UnwrappedTypeId::Float(_)) => ResolvedUnaryOp::IntToFloat(target_size),
+                        _ => match &expression.ty {
+                            UnwrappedTypeId::Integer(_) => ResolvedUnaryOp::IntCast(smaller_size),
+                            UnwrappedTypeId::Bool => ResolvedUnaryOp::BoolCast,
+                            UnwrappedTypeId::Char => ResolvedUnaryOp::IntCast(smaller_size),
+                            UnwrappedTypeId::Pointer(_) => ResolvedUnaryOp::PointerCast,
+                            _ => {
+                                panic!("Unsupported cast: {:?}", expression.ty)
+                            }
+                        },
                     }
                 }
             };
@@ -204,34 +230,64 @@ pub fn resolve_expression(
                     expr: Box::new(resolved_expr),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
         UnwrappedExpressionKind::Binary { op, left, right } => {
             let resolved_left = resolve_expression(context, left, false);
             let resolved_right = resolve_expression(context, right, false);
+            let mapped_op = match (op, &left.ty) {
+                (AnalyzedBinaryOp::Math(math_op), UnwrappedTypeId::Float(size)) => {
+                    ResolvedBinaryOp::FloatMath(math_op.clone(), *size)
+                }
+                (AnalyzedBinaryOp::Comparison(comp_op), UnwrappedTypeId::Float(size)) => {
+                    ResolvedBinaryOp::FloatComparison(comp_op.clone(), *size)
+                }
+                (AnalyzedBinaryOp::Math(math_op), _) => ResolvedBinaryOp::Math(math_op.clone()),
+                (AnalyzedBinaryOp::Logical(logic_op), _) => {
+                    ResolvedBinaryOp::Logical(logic_op.clone())
+                }
+                (AnalyzedBinaryOp::Comparison(comp_op), _) => {
+                    ResolvedBinaryOp::Comparison(comp_op.clone())
+                }
+            };
 
             ResolvedExpression {
                 kind: ResolvedExpressionKind::Binary {
-                    op: op.clone(),
+                    op: mapped_op,
                     left: Box::new(resolved_left),
                     right: Box::new(resolved_right),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
         UnwrappedExpressionKind::Assign { op, lhs, rhs } => {
             let resolved_lhs = resolve_assignable_expression(context, lhs);
             let resolved_rhs = resolve_expression(context, rhs, false);
+            let mapped_op = match (op, &rhs.ty) {
+                (BinaryAssignOp::Assign, _) => ResolvedAssignOp::Assign,
+                (BinaryAssignOp::MathAssign(math_op), UnwrappedTypeId::Float(size)) => {
+                    ResolvedAssignOp::FloatMathAssign(math_op.clone(), *size)
+                }
+                (BinaryAssignOp::MathAssign(math_op), _) => {
+                    ResolvedAssignOp::MathAssign(math_op.clone())
+                }
+                (BinaryAssignOp::LogicAssign(logic_op), _) => {
+                    ResolvedAssignOp::LogicAssign(logic_op.clone())
+                }
+            };
 
             ResolvedExpression {
                 kind: ResolvedExpressionKind::Assign {
-                    op: op.clone(),
+                    op: mapped_op,
                     lhs: resolved_lhs,
                     rhs: Box::new(resolved_rhs),
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -242,6 +298,7 @@ pub fn resolve_expression(
                     expr: resolved_expr,
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -277,6 +334,7 @@ pub fn resolve_expression(
                     return_stack_space,
                 },
                 stack_discard: total_stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -292,6 +350,7 @@ pub fn resolve_expression(
                     struct_size,
                 },
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -300,6 +359,7 @@ pub fn resolve_expression(
             ResolvedExpression {
                 kind: ResolvedExpressionKind::Increment(resolved_expr, *is_prefix),
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -308,6 +368,7 @@ pub fn resolve_expression(
             ResolvedExpression {
                 kind: ResolvedExpressionKind::Decrement(resolved_expr, *is_prefix),
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
@@ -318,6 +379,7 @@ pub fn resolve_expression(
                 ResolvedExpression {
                     kind: ResolvedExpressionKind::ConstantPointer(index),
                     stack_discard,
+                    location: expression.location.clone(),
                     value_data,
                 }
             }
@@ -327,12 +389,14 @@ pub fn resolve_expression(
             ResolvedExpression {
                 kind: ResolvedExpressionKind::Literal(AnalyzedLiteral::Integer(size as i64)),
                 stack_discard,
+                location: expression.location.clone(),
                 value_data,
             }
         }
         UnwrappedExpressionKind::FunctionPointer(function) => ResolvedExpression {
             kind: ResolvedExpressionKind::FunctionPointer(function.clone()),
             stack_discard,
+            location: expression.location.clone(),
             value_data,
         },
     }
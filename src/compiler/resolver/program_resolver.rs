@@ -1,13 +1,15 @@
 use crate::compiler::resolver::expression_resolver::resolve_expression;
 use crate::compiler::resolver::resolved_expression::{
-    FunctionReturnLocation, ResolvedFunction, ResolvedProgram, ValueData, ValueLocation,
+    FunctionReturnLocation, ResolvedFunction, ResolvedHostFunction, ResolvedProgram, ValueData,
+    ValueLocation,
 };
 use crate::compiler::resolver::struct_resolver::{
     get_type_size, resolve_structs, StructInformation,
 };
 use crate::compiler::unwrapper::unwrapped_type::{
-    UnwrappedFunction, UnwrappedProgram, UnwrappedTypeId,
+    UnwrappedExternFunction, UnwrappedFunction, UnwrappedProgram, UnwrappedTypeId,
 };
+use lychee_compiler::host_function_id;
 use std::collections::HashMap;
 
 pub struct ResolverContext {
@@ -66,13 +68,36 @@ pub fn resolve_program(program: &UnwrappedProgram) -> ResolvedProgram {
         resolved_functions.push(resolve_function(&mut context, function));
     }
 
+    let host_functions = program
+        .extern_functions
+        .iter()
+        .map(|function| resolve_host_function(&context, function))
+        .collect();
+
     ResolvedProgram {
         functions: resolved_functions,
         constants: context.constants,
+        host_functions,
         main_function_name: program.main_function_name.clone(),
     }
 }
 
+fn resolve_host_function(
+    context: &ResolverContext,
+    function: &UnwrappedExternFunction,
+) -> ResolvedHostFunction {
+    ResolvedHostFunction {
+        name: function.name.clone(),
+        display_name: function.function_name.clone(),
+        id: host_function_id(&function.function_name),
+        argument_size: function
+            .parameter_types
+            .iter()
+            .map(|ty| context.get_type_size(ty))
+            .sum(),
+    }
+}
+
 fn resolve_function(
     context: &mut ResolverContext,
     function: &UnwrappedFunction,
@@ -105,6 +130,7 @@ fn resolve_function(
 
     ResolvedFunction {
         name: function.name.clone(),
+        display_name: function.display_name.clone(),
         value_location: return_location,
         body: resolved_body,
         local_var_stack_size: context.maximum_local_var_stack_size,
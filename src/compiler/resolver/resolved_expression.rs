@@ -1,6 +1,6 @@
-use crate::compiler::analyzer::analyzed_expression::{
-    AnalyzedBinaryOp, AnalyzedLiteral, BinaryAssignOp,
-};
+use crate::compiler::analyzer::analyzed_expression::AnalyzedLiteral;
+use crate::compiler::lexer::location::Location;
+use crate::compiler::parser::binary_op::{BinaryComparisonOp, BinaryLogicOp, BinaryMathOp};
 use crate::compiler::parser::parsed_expression::UnaryMathOp;
 use crate::compiler::resolver::program_resolver::ResolverContext;
 use crate::compiler::unwrapper::unwrapped_type::UnwrappedTypeId;
@@ -9,9 +9,19 @@ use crate::compiler::unwrapper::unwrapped_type::UnwrappedTypeId;
 pub struct ResolvedProgram {
     pub functions: Vec<ResolvedFunction>,
     pub constants: Vec<Vec<u8>>,
+    pub host_functions: Vec<ResolvedHostFunction>,
     pub main_function_name: String,
 }
 
+/// A stub forwarding an `extern` function to the host with `hostcall`.
+#[derive(Debug, Clone)]
+pub struct ResolvedHostFunction {
+    pub name: String,
+    pub display_name: String,
+    pub id: u64,
+    pub argument_size: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct ValueData {
     pub size: usize,
@@ -40,6 +50,7 @@ impl ValueData {
             UnwrappedTypeId::Bool
             | UnwrappedTypeId::Char
             | UnwrappedTypeId::Integer(_)
+            | UnwrappedTypeId::Float(_)
             | UnwrappedTypeId::Pointer(_)
             | UnwrappedTypeId::FunctionType(_, _) => ValueData {
                 location: ValueLocation::Register,
@@ -70,6 +81,7 @@ pub enum FunctionReturnLocation {
 #[derive(Debug, Clone)]
 pub struct ResolvedFunction {
     pub name: String,
+    pub display_name: String,
     pub body: ResolvedExpression,
     pub value_location: FunctionReturnLocation,
     pub local_var_stack_size: usize,
@@ -80,6 +92,7 @@ pub struct ResolvedExpression {
     pub kind: ResolvedExpressionKind,
     pub value_data: ValueData,
     pub stack_discard: usize,
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
@@ -114,12 +127,12 @@ pub enum ResolvedExpressionKind {
         expr: Box<ResolvedExpression>,
     },
     Binary {
-        op: AnalyzedBinaryOp,
+        op: ResolvedBinaryOp,
         left: Box<ResolvedExpression>,
         right: Box<ResolvedExpression>,
     },
     Assign {
-        op: BinaryAssignOp,
+        op: ResolvedAssignOp,
         lhs: ResolvedAssignableExpression,
         rhs: Box<ResolvedExpression>,
     },
@@ -166,4 +179,29 @@ pub enum ResolvedUnaryOp {
     PointerCast,
     IntCast(usize),
     BoolCast,
+    FloatNegate(usize),
+    /// Converts an integer to a float of the given size.
+    IntToFloat(usize),
+    /// Converts a float of the first size to an integer of the second size.
+    FloatToInt(usize, usize),
+    /// Converts a float of the first size to a float of the second size.
+    FloatCast(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolvedBinaryOp {
+    Math(BinaryMathOp),
+    Logical(BinaryLogicOp),
+    Comparison(BinaryComparisonOp),
+    /// Arithmetic on floats of the given size.
+    FloatMath(BinaryMathOp, usize),
+    FloatComparison(BinaryComparisonOp, usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolvedAssignOp {
+    Assign,
+    MathAssign(BinaryMathOp),
+    FloatMathAssign(BinaryMathOp, usize),
+    LogicAssign(BinaryLogicOp),
 }
@@ -74,7 +74,7 @@ fn compute_type_size(
         UnwrappedTypeId::Unit => Ok(0),
         UnwrappedTypeId::Bool => Ok(1),
         UnwrappedTypeId::Char => Ok(1),
-        UnwrappedTypeId::Integer(size) => Ok(*size),
+        UnwrappedTypeId::Integer(size) | UnwrappedTypeId::Float(size) => Ok(*size),
         UnwrappedTypeId::FunctionType(_, _) => Ok(8),
     }
 }
@@ -86,7 +86,7 @@ pub fn get_type_size(type_id: &UnwrappedTypeId, struct_sizes: &HashMap<String, u
         UnwrappedTypeId::Unit => 0,
         UnwrappedTypeId::Bool => 1,
         UnwrappedTypeId::Char => 1,
-        UnwrappedTypeId::Integer(size) => *size,
+        UnwrappedTypeId::Integer(size) | UnwrappedTypeId::Float(size) => *size,
         UnwrappedTypeId::FunctionType(_, _) => 8,
     }
 }
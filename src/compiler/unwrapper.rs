@@ -1,7 +1,8 @@
 use crate::compiler::analyzer::analyzed_expression::AnalyzedProgram;
 use crate::compiler::analyzer::analyzed_type::{GenericIdKind, GenericParams};
+use crate::compiler::merger::merged_expression::FunctionRef;
 use crate::compiler::unwrapper::program_unwrapper::{GenericInfo, UnwrapperContext};
-use crate::compiler::unwrapper::unwrapped_type::UnwrappedProgram;
+use crate::compiler::unwrapper::unwrapped_type::{UnwrappedExternFunction, UnwrappedProgram};
 use std::collections::{HashMap, HashSet};
 
 pub mod program_unwrapper;
@@ -16,6 +17,7 @@ pub fn unwrap_program(program: &AnalyzedProgram) -> UnwrappedProgram {
             .into_iter()
             .map(|x| x.get_key())
             .collect::<HashSet<String>>(),
+        visited_functions: HashSet::new(),
     };
 
     let mut generic_infos = GenericInfo {
@@ -25,6 +27,33 @@ pub fn unwrap_program(program: &AnalyzedProgram) -> UnwrappedProgram {
         )),
     };
 
+    let mut extern_functions = Vec::new();
+    for id in &program.resolved_functions.extern_functions {
+        let header = program.resolved_functions.get_header(id);
+        let function_ref = FunctionRef {
+            id: id.clone(),
+            generic_args: Vec::new(),
+            arg_types: header
+                .parameter_order
+                .iter()
+                .map(|name| header.parameter_types[name].clone())
+                .collect(),
+        };
+        let unwrapped_ref = program_unwrapper::unwrap_function_ref(
+            &mut context,
+            program,
+            &generic_infos,
+            &function_ref,
+        );
+        // Extern functions have no body, so they are skipped like builtins.
+        context.builtin_functions.insert(unwrapped_ref.get_key());
+        extern_functions.push(UnwrappedExternFunction {
+            name: unwrapped_ref.get_key(),
+            function_name: id.id.item_name.clone(),
+            parameter_types: unwrapped_ref.arg_types,
+        });
+    }
+
     let unwrapped_main_ref = program_unwrapper::unwrap_function_ref(
         &mut context,
         program,
@@ -41,6 +70,7 @@ pub fn unwrap_program(program: &AnalyzedProgram) -> UnwrappedProgram {
     UnwrappedProgram {
         structs: context.structs,
         functions: context.functions,
+        extern_functions,
         main_function_name: unwrapped_main_ref.get_key(),
     }
 }
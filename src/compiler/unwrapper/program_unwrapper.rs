@@ -15,6 +15,7 @@ pub struct UnwrapperContext {
     pub functions: HashMap<String, UnwrappedFunction>,
     pub structs: HashMap<String, UnwrappedStruct>,
     pub builtin_functions: HashSet<String>,
+    pub visited_functions: HashSet<String>,
 }
 
 #[derive(Debug)]
@@ -32,7 +33,7 @@ pub fn unwrap_function(
     if context.builtin_functions.contains(&function_key) {
         return;
     }
-    if context.functions.contains_key(&function_key) {
+    if !context.visited_functions.insert(function_key.clone()) {
         return;
     }
 
@@ -66,6 +67,7 @@ pub fn unwrap_function(
 
     let unwrapped_function = UnwrappedFunction {
         name: function_key.clone(),
+        display_name: function_ref.display_name(),
         return_type: unwrapped_return_type,
         parameter_types: unwrapped_parameters,
         body: unwrapped_body,
@@ -85,6 +87,7 @@ fn unwrap_type(
         AnalyzedTypeId::Bool => UnwrappedTypeId::Bool,
         AnalyzedTypeId::Char => UnwrappedTypeId::Char,
         AnalyzedTypeId::Integer(size) => UnwrappedTypeId::Integer(*size),
+        AnalyzedTypeId::Float(size) => UnwrappedTypeId::Float(*size),
         AnalyzedTypeId::Pointer(inner) => {
             UnwrappedTypeId::Pointer(Box::new(unwrap_type(context, program, inner, generic_info)))
         }
@@ -354,6 +357,7 @@ fn unwrap_expression(
     UnwrappedExpression {
         kind,
         ty: unwrapped_type,
+        location: expression.location.clone(),
     }
 }
 
@@ -2,6 +2,7 @@ use crate::compiler::analyzer::analyzed_expression::{
     AnalyzedBinaryOp, AnalyzedConstant, AnalyzedLiteral, AnalyzedUnaryOp, BinaryAssignOp,
 };
 use crate::compiler::analyzer::analyzed_type::AnalyzedTypeId;
+use crate::compiler::lexer::location::Location;
 use crate::compiler::merger::merged_expression::{FunctionId, ResolvedStruct, StructId};
 use std::collections::HashMap;
 
@@ -9,12 +10,23 @@ use std::collections::HashMap;
 pub struct UnwrappedProgram {
     pub structs: HashMap<String, UnwrappedStruct>,
     pub functions: HashMap<String, UnwrappedFunction>,
+    pub extern_functions: Vec<UnwrappedExternFunction>,
     pub main_function_name: String,
 }
 
+/// A function declared with `extern`, which is implemented by the host.
+#[derive(Debug, Clone)]
+pub struct UnwrappedExternFunction {
+    pub name: String,
+    pub function_name: String,
+    pub parameter_types: Vec<UnwrappedTypeId>,
+}
+
 #[derive(Debug, Clone)]
 pub struct UnwrappedFunction {
     pub name: String,
+    /// The name shown in backtraces, such as `pop<int>`.
+    pub display_name: String,
     pub body: UnwrappedExpression,
     pub return_type: UnwrappedTypeId,
     pub parameter_types: HashMap<String, UnwrappedTypeId>,
@@ -33,6 +45,7 @@ pub enum UnwrappedTypeId {
     Bool,
     Char,
     Integer(usize),
+    Float(usize),
     Pointer(Box<UnwrappedTypeId>),
     StructType(String),
     FunctionType(Box<UnwrappedTypeId>, Vec<UnwrappedTypeId>),
@@ -45,6 +58,7 @@ impl UnwrappedTypeId {
             AnalyzedTypeId::Bool => UnwrappedTypeId::Bool,
             AnalyzedTypeId::Char => UnwrappedTypeId::Char,
             AnalyzedTypeId::Integer(size) => UnwrappedTypeId::Integer(*size),
+            AnalyzedTypeId::Float(size) => UnwrappedTypeId::Float(*size),
             AnalyzedTypeId::Pointer(inner) => {
                 UnwrappedTypeId::Pointer(Box::new(Self::upgrade_no_generic(inner)))
             }
@@ -69,6 +83,11 @@ impl UnwrappedTypeId {
                 8 => "long".to_string(),
                 _ => unreachable!(),
             },
+            UnwrappedTypeId::Float(size) => match size {
+                4 => "float".to_string(),
+                8 => "double".to_string(),
+                _ => unreachable!(),
+            },
             UnwrappedTypeId::Pointer(inner) => format!("&{}", inner.get_key()),
             UnwrappedTypeId::StructType(id) => format!("Struct({})", id),
             UnwrappedTypeId::FunctionType(return_type, params) => {
@@ -85,6 +104,35 @@ impl UnwrappedTypeId {
             }
         }
     }
+
+    /// A readable name for the type, which unlike the key leaves out the module paths.
+    pub fn display_name(&self) -> String {
+        match self {
+            UnwrappedTypeId::Pointer(inner) => format!("&{}", inner.display_name()),
+            UnwrappedTypeId::StructType(key) => {
+                // Struct keys look like `pkg::module::Name;<1>;<int>`.
+                let mut parts = key.splitn(3, ';');
+                let path = parts.next().unwrap_or_default();
+                let name = path.rsplit("::").next().unwrap_or(path);
+                match parts.nth(1) {
+                    Some(generic_args) if generic_args != "<>" => {
+                        format!("{}{}", name, generic_args)
+                    }
+                    _ => name.to_string(),
+                }
+            }
+            UnwrappedTypeId::FunctionType(return_type, params) => format!(
+                "fn({})->{}",
+                params
+                    .iter()
+                    .map(|x| x.display_name())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                return_type.display_name()
+            ),
+            _ => self.get_key(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -111,6 +159,21 @@ impl UnwrappedFunctionRef {
                 .join(",")
         )
     }
+
+    /// The name of the function followed by its generic arguments, such as `pop<int>`.
+    pub fn display_name(&self) -> String {
+        let name = &self.id.id.item_name;
+        if self.generic_args.is_empty() {
+            return name.clone();
+        }
+        let generic_args = self
+            .generic_args
+            .iter()
+            .map(|x| x.display_name())
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{}<{}>", name, generic_args)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -138,6 +201,7 @@ impl<'a> UnwrappedStructRef<'a> {
 pub struct UnwrappedExpression {
     pub kind: UnwrappedExpressionKind,
     pub ty: UnwrappedTypeId,
+    pub location: Location,
 }
 
 #[derive(Debug, Clone)]
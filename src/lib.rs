@@ -1,3 +1,8 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub mod assembler;
+
 #[derive(Clone, Debug)]
 pub enum OpCode {
     Exit,
@@ -28,6 +33,24 @@ pub enum OpCode {
     FileWrite,
     MemCopy,
     MemSet,
+    SeedRand,
+    HostCall,
+    Realloc,
+    FloatBinop(FloatBinopType),
+    FloatNeg,
+    IntToFloat,
+    FloatToInt,
+    FloatConvert,
+    Abort,
+    ArgCount,
+    ArgLength,
+    ArgRead,
+    FileOpenMode,
+    FileSeek,
+    FileTell,
+    FileSize,
+    FileRemove,
+    FileRename,
 }
 
 impl OpCode {
@@ -38,11 +61,17 @@ impl OpCode {
             OpCode::Store => 0x02,
             OpCode::Push => 0x03,
             OpCode::Pop => 0x04,
-            OpCode::Binop(op_type) => 0x05 + op_type.clone() as u8,
-            OpCode::BinopImmediate(op_type) => 0x11 + op_type.clone() as u8,
-            OpCode::Jump(flag_cond) => 0x1D + flag_cond.clone() as u8,
-            OpCode::Set(flag_cond) => 0x24 + flag_cond.clone() as u8,
-            OpCode::Unop(op_type) => 0x2B + op_type.clone() as u8,
+            OpCode::Binop(op_type) => extended_byte_code(*op_type as u8, 0x05, BASE_BINOPS, 0x44),
+            OpCode::BinopImmediate(op_type) => {
+                extended_byte_code(*op_type as u8, 0x11, BASE_BINOPS, 0x47)
+            }
+            OpCode::Jump(flag_cond) => {
+                extended_byte_code(*flag_cond as u8, 0x1D, BASE_CONDITIONS, 0x4A)
+            }
+            OpCode::Set(flag_cond) => {
+                extended_byte_code(*flag_cond as u8, 0x24, BASE_CONDITIONS, 0x50)
+            }
+            OpCode::Unop(op_type) => 0x2B + *op_type as u8,
             OpCode::Call => 0x2F,
             OpCode::Ret => 0x30,
             OpCode::ReadStdin => 0x31,
@@ -61,12 +90,43 @@ impl OpCode {
             OpCode::FileWrite => 0x3E,
             OpCode::MemCopy => 0x3F,
             OpCode::MemSet => 0x40,
+            OpCode::SeedRand => 0x41,
+            OpCode::HostCall => 0x42,
+            OpCode::Realloc => 0x43,
+            OpCode::FloatBinop(op_type) => 0x56 + *op_type as u8,
+            OpCode::FloatNeg => 0x5B,
+            OpCode::IntToFloat => 0x5C,
+            OpCode::FloatToInt => 0x5D,
+            OpCode::FloatConvert => 0x5E,
+            OpCode::Abort => 0x5F,
+            OpCode::ArgCount => 0x60,
+            OpCode::ArgLength => 0x61,
+            OpCode::ArgRead => 0x62,
+            OpCode::FileOpenMode => 0x63,
+            OpCode::FileSeek => 0x64,
+            OpCode::FileTell => 0x65,
+            OpCode::FileSize => 0x66,
+            OpCode::FileRemove => 0x67,
+            OpCode::FileRename => 0x68,
         }
     }
 }
 
+/// The number of binops and conditions that existed when the opcodes were first assigned. The
+/// ones added later have opcode ranges of their own after the other opcodes.
+const BASE_BINOPS: u8 = 12;
+const BASE_CONDITIONS: u8 = 7;
+
+fn extended_byte_code(index: u8, base: u8, base_count: u8, extended_base: u8) -> u8 {
+    if index < base_count {
+        base + index
+    } else {
+        extended_base + index - base_count
+    }
+}
+
 #[repr(u8)]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
 pub enum BinopType {
     Mov = 0x00,
     Add = 0x01,
@@ -80,10 +140,14 @@ pub enum BinopType {
     Shl = 0x09,
     Shr = 0x0A,
     Cmp = 0x0B,
+    UnsignedDiv = 0x0C,
+    UnsignedMod = 0x0D,
+    /// Shifts in zeros, while `Shr` keeps the sign.
+    UnsignedShr = 0x0E,
 }
 
 #[repr(u8)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
 pub enum UnopType {
     Not = 0x00,
     Neg = 0x01,
@@ -91,8 +155,23 @@ pub enum UnopType {
     Dec = 0x03,
 }
 
+/// Arithmetic on the `float` or `double` held in the low 32 or all 64 bits of a register, selected
+/// by the size operand of the instruction.
 #[repr(u8)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum FloatBinopType {
+    Add = 0x00,
+    Sub = 0x01,
+    Mul = 0x02,
+    Div = 0x03,
+    /// The only float binop that sets the flags. `zero` is set if the operands are equal,
+    /// `positive` if the left one is greater and `carry` if it is less, so the ordered
+    /// comparisons are `jg`, `jge`, `jb` and `jbe`, which all fail when an operand is NaN.
+    Cmp = 0x04,
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
 pub enum FlagConditionType {
     Always = 0x00,
     Zero = 0x01,
@@ -101,6 +180,12 @@ pub enum FlagConditionType {
     GreaterEquals = 0x04,
     Less = 0x05,
     LessEquals = 0x06,
+    Above = 0x07,
+    AboveEquals = 0x08,
+    Below = 0x09,
+    BelowEquals = 0x0A,
+    Overflow = 0x0B,
+    NotOverflow = 0x0C,
 }
 
 #[repr(u8)]
@@ -127,3 +212,491 @@ pub enum RegisterCode {
 pub const DATA_SIZE_64: u8 = 0x08;
 pub const DATA_SIZE_32: u8 = 0x04;
 pub const DATA_SIZE_8: u8 = 0x01;
+
+pub const PROGRAM_MAGIC: [u8; 4] = *b"LYCH";
+
+/// The id `hostcall` uses for the `extern` function `name`, a 64-bit FNV-1a hash of the name.
+/// Functions with the same name in different modules share an id.
+pub fn host_function_id(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returned by `fileopen` instead of a handle when the file policy forbids the access, and by
+/// `fileread` and `filewrite` instead of a byte count when writing to a read-only file.
+pub const FILE_ERROR_DENIED: i64 = -1;
+/// Returned by `fileopen` when the file does not exist and may not be created.
+pub const FILE_ERROR_NOT_FOUND: i64 = -2;
+/// Returned by the file opcodes when the host reports any other I/O error.
+pub const FILE_ERROR_IO: i64 = -3;
+/// Returned by `fileopenmode` with `FILE_MODE_CREATE_NEW` when the file already exists.
+pub const FILE_ERROR_EXISTS: i64 = -4;
+/// Returned by `fileopenmode` for an unknown mode and by `fileseek` for a negative position.
+pub const FILE_ERROR_INVALID: i64 = -5;
+
+/// Opens a file for reading and writing, creating it if it does not exist. This is the mode of
+/// `fileopen`, which opens the file read-only if the file policy does not allow writing to it.
+pub const FILE_MODE_READ_WRITE: u64 = 0;
+/// Opens an existing file for reading.
+pub const FILE_MODE_READ: u64 = 1;
+/// Opens a file for writing, creating it or truncating it to zero length.
+pub const FILE_MODE_WRITE: u64 = 2;
+/// Opens a file for writing at its end, creating it if it does not exist.
+pub const FILE_MODE_APPEND: u64 = 3;
+/// Creates a file for reading and writing, failing if it already exists.
+pub const FILE_MODE_CREATE_NEW: u64 = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmMemoryConfig {
+    /// The maximum size of the memory. Only the program, the stack and the initial heap are
+    /// allocated up front, the heap grows into the rest when it runs out of free blocks.
+    pub memory_size: u64,
+    /// The initial size of the heap.
+    pub heap_size: u64,
+    pub stack_size: u64,
+}
+
+impl Default for VmMemoryConfig {
+    fn default() -> Self {
+        VmMemoryConfig {
+            memory_size: 0x10000000,
+            heap_size: 0x100000,
+            stack_size: 0x80000,
+        }
+    }
+}
+
+/// Restricts which host files the file opcodes may open. The default policy allows everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilePolicy {
+    /// Deny every file access.
+    pub deny_all: bool,
+    /// Directory that guest paths are resolved in and confined to. Absolute guest paths are
+    /// taken relative to it.
+    pub root: Option<PathBuf>,
+    /// Files and directories that may only be read.
+    pub read_only: Vec<PathBuf>,
+    /// Files and directories that may be read, written and created. If either allowlist is
+    /// non-empty, everything not on one of them is denied, and the longest matching entry wins.
+    pub read_write: Vec<PathBuf>,
+}
+
+/// The settings a program is run with, configured in the `[vm]` section of `lychee.toml`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VmConfig {
+    pub memory: VmMemoryConfig,
+    /// Seed of the random number generator. Without a seed, every run is seeded randomly.
+    pub seed: Option<u64>,
+    /// Not stored in programs, it is chosen by whoever runs them.
+    pub files: FilePolicy,
+    /// Maximum number of instructions to execute. Not stored in programs.
+    pub fuel: Option<u64>,
+    /// Maximum wall-clock time to run for. Not stored in programs.
+    pub timeout: Option<Duration>,
+    /// Trap on signed overflow and on shifts by 64 or more bits instead of wrapping around.
+    /// Not stored in programs.
+    pub checked_arithmetic: bool,
+    /// The command line arguments passed to the program, read with `argc`, `arglen` and
+    /// `argread`. Not stored in programs.
+    pub args: Vec<String>,
+}
+
+/// The version of the object file format, to be bumped whenever the layout of object files or
+/// the encoding of the instructions changes.
+pub const OBJECT_FORMAT_VERSION: u32 = 3;
+
+/// The configuration stored in the header of an object file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramHeader {
+    /// The address execution starts at.
+    pub entry: u64,
+    pub config: VmConfig,
+}
+
+/// An assembled program. The read-only data is loaded right after the code, so the addresses
+/// of its labels start at the size of the code.
+///
+/// Object files start with `PROGRAM_MAGIC` and the format version, followed by the header,
+/// the sizes of the sections and the sections themselves: the code, the read-only data, the
+/// symbol table and the line table. All numbers are little endian.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjectFile {
+    pub header: ProgramHeader,
+    pub code: Vec<u8>,
+    pub rodata: Vec<u8>,
+    pub symbols: SymbolTable,
+    pub line_table: LineTable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ObjectFileError {
+    #[error("not a lychee object file")]
+    NotAnObjectFile,
+    #[error("unsupported object format version {0}, expected version {OBJECT_FORMAT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("malformed object file")]
+    Malformed,
+}
+
+impl ObjectFile {
+    /// The memory image of the program, the code followed by the read-only data.
+    pub fn image(&self) -> Vec<u8> {
+        let mut image = self.code.clone();
+        image.extend(&self.rodata);
+        image
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let memory = &self.header.config.memory;
+        let mut bytes = PROGRAM_MAGIC.to_vec();
+        bytes.extend(OBJECT_FORMAT_VERSION.to_le_bytes());
+        for value in [
+            self.header.entry,
+            memory.memory_size,
+            memory.heap_size,
+            memory.stack_size,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.push(self.header.config.seed.is_some() as u8);
+        bytes.extend(self.header.config.seed.unwrap_or(0).to_le_bytes());
+        let line_table = &self.line_table;
+        for size in [
+            self.code.len(),
+            self.rodata.len(),
+            self.symbols.symbols.len(),
+            line_table.files.len(),
+            line_table.functions.len(),
+            line_table.rows.len(),
+        ] {
+            bytes.extend((size as u64).to_le_bytes());
+        }
+        bytes.extend(&self.code);
+        bytes.extend(&self.rodata);
+        let write_string = |bytes: &mut Vec<u8>, string: &str| {
+            bytes.extend((string.len() as u64).to_le_bytes());
+            bytes.extend(string.as_bytes());
+        };
+        for (address, label) in &self.symbols.symbols {
+            bytes.extend(address.to_le_bytes());
+            write_string(&mut bytes, label);
+        }
+        for name in line_table.files.iter().chain(&line_table.functions) {
+            write_string(&mut bytes, name);
+        }
+        for row in &line_table.rows {
+            for value in [row.address, row.function as u64, row.file as u64, row.line] {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Reads an object file written by `to_bytes`, checking that it was written for this
+    /// version of the format and that its entry point, symbols and line table lie within the
+    /// program.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, ObjectFileError> {
+        let mut reader = ObjectReader { bytes, position: 0 };
+        if reader.bytes(PROGRAM_MAGIC.len()) != Some(&PROGRAM_MAGIC[..]) {
+            return Err(ObjectFileError::NotAnObjectFile);
+        }
+        let version = reader
+            .bytes(4)
+            .map(|version| u32::from_le_bytes(version.try_into().unwrap()))
+            .ok_or(ObjectFileError::Malformed)?;
+        if version != OBJECT_FORMAT_VERSION {
+            return Err(ObjectFileError::UnsupportedVersion(version));
+        }
+        Self::read_sections(&mut reader).ok_or(ObjectFileError::Malformed)
+    }
+
+    fn read_sections(reader: &mut ObjectReader) -> Option<ObjectFile> {
+        let entry = reader.u64()?;
+        let memory = VmMemoryConfig {
+            memory_size: reader.u64()?,
+            heap_size: reader.u64()?,
+            stack_size: reader.u64()?,
+        };
+        let has_seed = reader.byte()? != 0;
+        let seed = reader.u64()?;
+        let code_size = reader.usize()?;
+        let rodata_size = reader.usize()?;
+        let symbol_count = reader.usize()?;
+        let file_count = reader.usize()?;
+        let function_count = reader.usize()?;
+        let row_count = reader.usize()?;
+        let code = reader.bytes(code_size)?.to_vec();
+        let rodata = reader.bytes(rodata_size)?.to_vec();
+        let program_size = (code_size + rodata_size) as u64;
+
+        let mut labels = Vec::new();
+        for _ in 0..symbol_count {
+            let address = reader.u64()?;
+            let label = reader.string()?;
+            if address > program_size {
+                return None;
+            }
+            labels.push((label, address));
+        }
+
+        let mut line_table = LineTable {
+            files: (0..file_count)
+                .map(|_| reader.string())
+                .collect::<Option<_>>()?,
+            functions: (0..function_count)
+                .map(|_| reader.string())
+                .collect::<Option<_>>()?,
+            rows: Vec::new(),
+        };
+        for _ in 0..row_count {
+            let row = LineRow {
+                address: reader.u64()?,
+                function: reader.usize()?,
+                file: reader.usize()?,
+                line: reader.u64()?,
+            };
+            let in_order = line_table
+                .rows
+                .last()
+                .is_none_or(|last| last.address < row.address);
+            if !in_order
+                || row.address > code_size as u64
+                || row.function >= function_count
+                || (row.line != 0 && row.file >= file_count)
+            {
+                return None;
+            }
+            line_table.rows.push(row);
+        }
+        if reader.position != reader.bytes.len() || (entry >= code_size as u64 && entry != 0) {
+            return None;
+        }
+
+        Some(ObjectFile {
+            header: ProgramHeader {
+                entry,
+                config: VmConfig {
+                    memory,
+                    seed: has_seed.then_some(seed),
+                    ..VmConfig::default()
+                },
+            },
+            code,
+            rodata,
+            symbols: SymbolTable::new(labels),
+            line_table,
+        })
+    }
+}
+
+struct ObjectReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ObjectReader<'a> {
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(length)?;
+        let bytes = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.usize()?;
+        String::from_utf8(self.bytes(length)?.to_vec()).ok()
+    }
+}
+
+/// Extracts the object file from an executable written by `--bundle-vm`, which is the VM binary
+/// followed by the object file and its size as a little endian `u64`. Returns `None` if the
+/// executable does not end with an object file.
+pub fn bundled_program(executable: &[u8]) -> Option<&[u8]> {
+    let size_offset = executable.len().checked_sub(8)?;
+    let size = u64::from_le_bytes(executable[size_offset..].try_into().unwrap());
+    let start = size_offset.checked_sub(usize::try_from(size).ok()?)?;
+    let program = &executable[start..size_offset];
+    program.starts_with(&PROGRAM_MAGIC).then_some(program)
+}
+
+/// Maps the labels of an assembled program to their addresses, sorted by address.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    pub symbols: Vec<(u64, String)>,
+}
+
+impl SymbolTable {
+    pub fn new(labels: impl IntoIterator<Item = (String, u64)>) -> SymbolTable {
+        let mut symbols = labels
+            .into_iter()
+            .map(|(label, address)| (address, label))
+            .collect::<Vec<_>>();
+        symbols.sort();
+        SymbolTable { symbols }
+    }
+
+    /// Finds the addresses of all labels matching `name`, which is either a full label or the
+    /// (possibly module-qualified) name of a function, in which case all overloads match.
+    pub fn find(&self, name: &str) -> Vec<u64> {
+        let exact = self
+            .symbols
+            .iter()
+            .filter(|(_, label)| label == name)
+            .map(|(address, _)| *address)
+            .collect::<Vec<_>>();
+        if !exact.is_empty() {
+            return exact;
+        }
+        let qualified_suffix = format!("::{}", name);
+        self.symbols
+            .iter()
+            .filter(|(_, label)| {
+                Self::function_path(label)
+                    .is_some_and(|path| path == name || path.ends_with(&qualified_suffix))
+            })
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// Returns the closest label at or before `address` and the offset from it.
+    pub fn symbolize(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self
+            .symbols
+            .partition_point(|(symbol_address, _)| *symbol_address <= address);
+        let (symbol_address, label) = self.symbols.get(index.checked_sub(1)?)?;
+        Some((label, address - symbol_address))
+    }
+
+    /// Extracts the function path from a function entry label such as
+    /// `_L7_pkg::module::name;3;<0>;(1);<>;(int)`.
+    pub fn function_path(label: &str) -> Option<&str> {
+        if !label.ends_with(')') {
+            return None;
+        }
+        let (_, key) = label.strip_prefix("_L")?.split_once('_')?;
+        key.split(';').next()
+    }
+}
+
+/// Maps code addresses to the function and the source line they were compiled from. Each row
+/// covers the code from its address up to the address of the next row.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    pub functions: Vec<String>,
+    /// Sorted by address.
+    pub rows: Vec<LineRow>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRow {
+    pub address: u64,
+    /// An index into the functions of the table.
+    pub function: usize,
+    /// An index into the files of the table, only meaningful if `line` is not 0.
+    pub file: usize,
+    /// The source line, or 0 for code without a location like the builtin functions.
+    pub line: u64,
+}
+
+impl LineTable {
+    /// Returns the row covering `address`.
+    pub fn lookup(&self, address: u64) -> Option<&LineRow> {
+        let index = self.rows.partition_point(|row| row.address <= address);
+        self.rows.get(index.checked_sub(1)?)
+    }
+
+    /// Describes `address` as `file:line in function`, falling back to the address itself if
+    /// the line is unknown.
+    pub fn describe(&self, address: u64) -> String {
+        match self.lookup(address) {
+            Some(row) if row.line != 0 => format!(
+                "{}:{} in {}",
+                self.files[row.file], row.line, self.functions[row.function]
+            ),
+            Some(row) => format!("address {} in {}", address, self.functions[row.function]),
+            None => format!("address {}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_source;
+
+    const PROGRAM: &str = "\
+memory 65536 8192 8192
+seed 7
+entry _main
+file 0 main.lyc
+_main:
+func main
+loc 0 3
+movi r0 7
+loc 0 4
+exit
+section rodata
+bytes 1 2 3
+";
+
+    #[test]
+    fn object_files_round_trip() {
+        let program = assemble_source(PROGRAM);
+        let bytes = program.to_bytes();
+        let read = ObjectFile::from_bytes(&bytes).unwrap();
+        assert_eq!(read.header.config.seed, Some(7));
+        assert_eq!(read.header.config.memory.stack_size, 8192);
+        assert_eq!(read.rodata, [1, 2, 3]);
+        assert_eq!(read.symbols, program.symbols);
+        assert_eq!(read.line_table, program.line_table);
+        assert_eq!(
+            read.line_table.describe(read.header.entry),
+            "main.lyc:3 in main"
+        );
+        assert_eq!(read.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn invalid_object_files_are_rejected() {
+        let bytes = assemble_source(PROGRAM).to_bytes();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] ^= 0xFF;
+        assert_eq!(
+            ObjectFile::from_bytes(&wrong_magic).unwrap_err(),
+            ObjectFileError::NotAnObjectFile
+        );
+
+        let mut newer = bytes.clone();
+        newer[PROGRAM_MAGIC.len()..PROGRAM_MAGIC.len() + 4]
+            .copy_from_slice(&(OBJECT_FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            ObjectFile::from_bytes(&newer).unwrap_err(),
+            ObjectFileError::UnsupportedVersion(OBJECT_FORMAT_VERSION + 1)
+        );
+
+        for length in PROGRAM_MAGIC.len()..bytes.len() {
+            assert_eq!(
+                ObjectFile::from_bytes(&bytes[..length]).unwrap_err(),
+                ObjectFileError::Malformed,
+                "truncated to {} bytes",
+                length
+            );
+        }
+    }
+}
@@ -1,9 +1,8 @@
-use crate::assembler::assemble;
 use crate::compiler::compile;
 use clap::Parser;
+use lychee_compiler::assembler::assemble;
 use std::path::PathBuf;
 
-mod assembler;
 mod compiler;
 
 #[derive(Parser, Debug)]